@@ -1,5 +1,5 @@
 import { hub } from "dom-native";
-import { apiGet, apiPatch, apiPost, apiDelete } from "../web-client";
+import { apiGetAll, apiPatch, apiPost, apiDelete } from "../web-client";
 
 // Interface for Task
 export interface Task {
@@ -7,17 +7,27 @@ export interface Task {
     name: string;
     status: "Open" | "Closed";
     creation_time: string;
+    due_at: string | null;
+    start_at: string | null;
+    priority: "Low" | "Normal" | "High" | "Urgent";
+    tags: { id: number; name: string }[];
+    parent_id: number | null;
+    blocked: boolean;
+    recurrence: string | null;
+    version: number;
+    deleted_at: string | null;
+    modified_at: string;
 }
 
 // We don't care about the ID on the front-end, so we omit it
-export type TaskPatch = Partial<Omit<Task, "id">>;
+export type TaskPatch = Partial<Omit<Task, "id" | "tags" | "blocked">>;
 
 // Model-client-object for Task. Is a singleton.
 class TaskMco {
 
-    // Get a list of all tasks from the API
+    // Get a list of all tasks from the API, across all pages
     async list(): Promise<Task[]> {
-        const data = await apiGet("tasks");
+        const data = await apiGetAll("tasks");
         return data as Task[];
     }
 
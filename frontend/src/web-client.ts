@@ -7,6 +7,21 @@ export async function apiGet(path: string, data?: any) {
     return apiCall("GET", path, data);
 }
 
+// Get all items of a paginated listing, following `next_cursor` page by page.
+export async function apiGetAll(path: string): Promise<any[]> {
+    const items: any[] = [];
+    const separator = path.includes("?") ? "&" : "?";
+    let pagePath = path;
+    while (true) {
+        const res = await apiFetch("GET", pagePath);
+        items.push(...res.data);
+        if (res.next_cursor == null) {
+            return items;
+        }
+        pagePath = `${path}${separator}cursor=${encodeURIComponent(res.next_cursor)}`;
+    }
+}
+
 export async function apiPost(path: string, data: any) {
     return apiCall("POST", path, data);
 }
@@ -22,6 +37,12 @@ export async function apiDelete(path: string, data?: any) {
 
 
 async function apiCall(httpMethod: HttpMethod, path: string, data?: any) {
+    let res = await apiFetch(httpMethod, path, data);
+    return res.data;
+}
+
+// Make an API call and return the whole response body.
+async function apiFetch(httpMethod: HttpMethod, path: string, data?: any) {
     const url = `${API_BASE_PATH}/${path}`;
 
     const response = await fetch(url,  {
@@ -34,6 +55,5 @@ async function apiCall(httpMethod: HttpMethod, path: string, data?: any) {
         body: JSON.stringify(data)
     });
 
-    let res = await response.json();
-    return res.data;
+    return response.json();
 }
\ No newline at end of file
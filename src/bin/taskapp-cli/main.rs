@@ -0,0 +1,429 @@
+//! Command line client for the REST API of a running server, with a terminal UI that
+//! also works on a local database.
+
+mod tui;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use comfy_table::presets::UTF8_FULL_CONDENSED;
+use comfy_table::Table;
+use serde::Serialize;
+use std::error::Error;
+use std::process::ExitCode;
+use taskapp::client::{Client, ListParams, DEFAULT_SERVER};
+use taskapp::config::ENV_PREFIX;
+use taskapp::database::{create_and_connect_with, DbAddress, PoolConfig};
+use taskapp::model::hierarchy::ChildPolicy;
+use taskapp::model::task::{Task, TaskPatch, TaskPriority, TaskStatus, TaskUpdate};
+
+/// Manage the tasks of a taskapp server.
+#[derive(Debug, Parser)]
+#[command(name = "taskapp-cli", version)]
+struct Cli {
+    /// URL of the server. Defaults to `TASKAPP_SERVER`, then http://127.0.0.1:8080.
+    #[arg(long, short, global = true)]
+    server: Option<String>,
+    /// Output format.
+    #[arg(long, short, global = true, value_enum, default_value_t)]
+    output: Output,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, ValueEnum)]
+enum Output {
+    #[default]
+    Table,
+    Json,
+}
+
+/// [`TaskStatus`] on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Status {
+    Open,
+    Closed,
+}
+
+impl From<Status> for TaskStatus {
+    fn from(status: Status) -> Self {
+        match status {
+            Status::Open => TaskStatus::Open,
+            Status::Closed => TaskStatus::Closed,
+        }
+    }
+}
+
+impl From<&TaskStatus> for Status {
+    fn from(status: &TaskStatus) -> Self {
+        match status {
+            TaskStatus::Open => Status::Open,
+            TaskStatus::Closed => Status::Closed,
+        }
+    }
+}
+
+/// [`TaskPriority`] on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Priority {
+    Low,
+    Normal,
+    High,
+    Urgent,
+}
+
+impl From<Priority> for TaskPriority {
+    fn from(priority: Priority) -> Self {
+        match priority {
+            Priority::Low => TaskPriority::Low,
+            Priority::Normal => TaskPriority::Normal,
+            Priority::High => TaskPriority::High,
+            Priority::Urgent => TaskPriority::Urgent,
+        }
+    }
+}
+
+impl From<TaskPriority> for Priority {
+    fn from(priority: TaskPriority) -> Self {
+        match priority {
+            TaskPriority::Low => Priority::Low,
+            TaskPriority::Normal => Priority::Normal,
+            TaskPriority::High => Priority::High,
+            TaskPriority::Urgent => Priority::Urgent,
+        }
+    }
+}
+
+/// [`ChildPolicy`] on the command line.
+#[derive(Debug, Default, Clone, Copy, PartialEq, ValueEnum)]
+enum Children {
+    #[default]
+    Refuse,
+    Cascade,
+    Orphan,
+}
+
+impl From<Children> for ChildPolicy {
+    fn from(children: Children) -> Self {
+        match children {
+            Children::Refuse => ChildPolicy::Refuse,
+            Children::Cascade => ChildPolicy::Cascade,
+            Children::Orphan => ChildPolicy::Orphan,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Create a task.
+    Add {
+        /// Name of the task. Several words are joined by spaces.
+        #[arg(required = true)]
+        name: Vec<String>,
+        #[command(flatten)]
+        fields: Fields,
+    },
+    /// List tasks.
+    List {
+        #[arg(long, value_enum)]
+        status: Option<Status>,
+        /// Only tasks carrying this tag. Repeat to require several tags.
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        /// Filter expression, e.g. `due<7d and priority>=high`.
+        #[arg(long)]
+        filter: Option<String>,
+        /// Comma-separated sort keys, e.g. `priority,due_at:asc`.
+        #[arg(long)]
+        sort: Option<String>,
+        /// Show at most this many tasks. All tasks by default.
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// Close tasks.
+    Done {
+        #[arg(required = true)]
+        ids: Vec<i64>,
+    },
+    /// Change a task.
+    Edit {
+        id: i64,
+        #[arg(long)]
+        name: Option<String>,
+        #[arg(long, value_enum)]
+        status: Option<Status>,
+        #[command(flatten)]
+        fields: Fields,
+    },
+    /// Move tasks to the trash.
+    Rm {
+        #[arg(required = true)]
+        ids: Vec<i64>,
+        /// What happens to subtasks.
+        #[arg(long, value_enum, default_value_t)]
+        children: Children,
+    },
+    /// Show a task.
+    Show { id: i64 },
+    /// Browse and change tasks in a full-screen terminal UI.
+    Tui {
+        /// SQLite file to open directly, instead of going through the server.
+        #[arg(long)]
+        database: Option<String>,
+    },
+}
+
+/// Optional task fields, shared by `add` and `edit`.
+#[derive(Debug, Args)]
+struct Fields {
+    #[arg(long, value_enum)]
+    priority: Option<Priority>,
+    /// Due time in RFC 3339, or a date for midnight UTC. `none` clears it.
+    #[arg(long, value_parser = parse_time)]
+    due: Option<Clearable<DateTime<Utc>>>,
+    /// Start time in RFC 3339, or a date for midnight UTC. `none` clears it.
+    #[arg(long, value_parser = parse_time)]
+    start: Option<Clearable<DateTime<Utc>>>,
+    /// Parent task id. `none` makes the task a top-level task.
+    #[arg(long, value_parser = parse_parent)]
+    parent: Option<Clearable<i64>>,
+    /// Recurrence rule, e.g. `FREQ=WEEKLY;BYDAY=MO`. `none` clears it.
+    #[arg(long, value_parser = parse_recurrence)]
+    recurrence: Option<Clearable<String>>,
+}
+
+/// A field value that `none` clears. Clap would read `Option<Option<T>>` as a flag
+/// with an optional value.
+type Clearable<T> = Option<T>;
+
+impl Fields {
+    fn into_patch(self, name: Option<String>, status: Option<Status>) -> TaskPatch {
+        TaskPatch {
+            name,
+            status: status.map(TaskStatus::from),
+            priority: self.priority.map(TaskPriority::from),
+            due_at: self.due,
+            start_at: self.start,
+            parent_id: self.parent,
+            recurrence: self.recurrence,
+            version: None,
+        }
+    }
+}
+
+fn parse_time(value: &str) -> Result<Clearable<DateTime<Utc>>, String> {
+    if value == "none" {
+        return Ok(None);
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(Some(time.with_timezone(&Utc)));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| Some(date.and_time(Default::default()).and_utc()))
+        .map_err(|_| "expected an RFC 3339 time, a YYYY-MM-DD date or `none`".to_string())
+}
+
+fn parse_parent(value: &str) -> Result<Clearable<i64>, String> {
+    match value {
+        "none" => Ok(None),
+        _ => value
+            .parse()
+            .map(Some)
+            .map_err(|_| "expected a task id or `none`".to_string()),
+    }
+}
+
+fn parse_recurrence(value: &str) -> Result<Clearable<String>, String> {
+    Ok(Some(value.to_string()).filter(|value| value != "none"))
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let server = cli
+        .server
+        .or_else(|| std::env::var(format!("{}SERVER", ENV_PREFIX)).ok())
+        .unwrap_or_else(|| DEFAULT_SERVER.to_string());
+    let client = Client::new(&server).with_client_id("taskapp-cli");
+    let output = cli.output;
+
+    match cli.command {
+        Command::Add { name, fields } => {
+            let task = client.insert(&fields.into_patch(Some(name.join(" ")), None))?;
+            print_tasks(output, &[task]);
+        }
+        Command::List {
+            status,
+            tags,
+            filter,
+            sort,
+            limit,
+        } => {
+            let params = ListParams {
+                status: status.map(TaskStatus::from),
+                tags,
+                filter,
+                sort,
+                limit,
+                cursor: None,
+            };
+            let tasks = match limit {
+                Some(_) => client.list(&params)?.items,
+                None => client.list_all(&params)?,
+            };
+            print_tasks(output, &tasks);
+        }
+        Command::Done { ids } => {
+            for id in ids {
+                let patch = TaskPatch {
+                    status: Some(TaskStatus::Closed),
+                    ..Default::default()
+                };
+                print_update(output, &client.update(id, &patch)?);
+            }
+        }
+        Command::Edit {
+            id,
+            name,
+            status,
+            fields,
+        } => {
+            let update = client.update(id, &fields.into_patch(name, status))?;
+            print_update(output, &update);
+        }
+        Command::Rm { ids, children } => {
+            for id in ids {
+                client.delete(id, children.into())?;
+                match output {
+                    Output::Table => println!("Moved task {} to the trash.", id),
+                    Output::Json => print_json(&serde_json::json!({ "deleted": id })),
+                }
+            }
+        }
+        Command::Show { id } => {
+            let task = client.get(id)?;
+            match output {
+                Output::Table => println!("{}", task_details(&task)),
+                Output::Json => print_json(&task),
+            }
+        }
+        Command::Tui { database: None } => tui::run(tui::Store::Remote(client), server)?,
+        Command::Tui {
+            database: Some(path),
+        } => {
+            let runtime = tokio::runtime::Runtime::new()?;
+            let address = DbAddress::Path(path.clone());
+            let db = runtime.block_on(create_and_connect_with(address, PoolConfig::default()))?;
+            tui::run(tui::Store::Local { db, runtime }, path)?;
+        }
+    }
+    Ok(())
+}
+
+fn print_json<T: Serialize>(value: &T) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).expect("output serializes")
+    );
+}
+
+fn print_tasks(output: Output, tasks: &[Task]) {
+    match output {
+        Output::Table => println!("{}", task_table(tasks)),
+        Output::Json => print_json(&tasks),
+    }
+}
+
+/// Print an updated task, and the tasks that the update unblocked or created.
+fn print_update(output: Output, update: &TaskUpdate) {
+    if output == Output::Json {
+        return print_json(update);
+    }
+    println!("{}", task_table(std::slice::from_ref(&update.task)));
+    for task in &update.unblocked {
+        println!("Unblocked task {}: {}", task.id, task.name);
+    }
+    if let Some(task) = &update.next_occurrence {
+        println!(
+            "Next occurrence is task {}, due {}.",
+            task.id,
+            time(task.due_at)
+        );
+    }
+}
+
+/// Name of a value as given on the command line, e.g. `open`.
+fn value_name<T: ValueEnum>(value: &T) -> String {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+fn time(time: Option<DateTime<Utc>>) -> String {
+    time.map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+fn tags(task: &Task) -> String {
+    let names: Vec<&str> = task.tags.iter().map(|tag| tag.name.as_str()).collect();
+    names.join(", ")
+}
+
+fn task_table(tasks: &[Task]) -> Table {
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL_CONDENSED)
+        .set_header(["ID", "Status", "Priority", "Name", "Due", "Tags"]);
+    for task in tasks {
+        table.add_row([
+            task.id.to_string(),
+            value_name(&Status::from(&task.status)),
+            value_name(&Priority::from(task.priority)),
+            task.name.clone(),
+            time(task.due_at),
+            tags(task),
+        ]);
+    }
+    table
+}
+
+fn task_details(task: &Task) -> Table {
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL_CONDENSED).add_rows([
+        ["ID".to_string(), task.id.to_string()],
+        ["Name".to_string(), task.name.clone()],
+        [
+            "Status".to_string(),
+            value_name(&Status::from(&task.status)),
+        ],
+        [
+            "Priority".to_string(),
+            value_name(&Priority::from(task.priority)),
+        ],
+        ["Due".to_string(), time(task.due_at)],
+        ["Start".to_string(), time(task.start_at)],
+        [
+            "Parent".to_string(),
+            task.parent_id.map(|id| id.to_string()).unwrap_or_default(),
+        ],
+        [
+            "Recurrence".to_string(),
+            task.recurrence.clone().unwrap_or_default(),
+        ],
+        ["Tags".to_string(), tags(task)],
+        ["Blocked".to_string(), task.blocked.to_string()],
+        ["Created".to_string(), time(Some(task.creation_time))],
+        ["Modified".to_string(), time(Some(task.modified_at))],
+        ["Version".to_string(), task.version.to_string()],
+    ]);
+    table
+}
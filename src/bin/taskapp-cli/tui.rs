@@ -0,0 +1,451 @@
+//! Full-screen terminal UI. Works on a SQLite file through the model, or on a server
+//! through the REST API, and reloads the tasks whenever they change.
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Cell, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+use taskapp::client::{Client, ListParams};
+use taskapp::database::Database;
+use taskapp::model::events::EventMac;
+use taskapp::model::hierarchy::ChildPolicy;
+use taskapp::model::task::{Task, TaskMac, TaskPatch, TaskStatus};
+use tokio::runtime::Runtime;
+
+/// Recorded in the task history as the author of changes.
+const CLIENT_ID: &str = "taskapp-tui";
+/// How often a local database is checked for changes by other processes.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Pause before reconnecting to the event stream of a server.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Where the tasks live.
+pub enum Store {
+    /// A SQLite file, changed through the model directly.
+    Local { db: Database, runtime: Runtime },
+    /// A server, changed through the REST API.
+    Remote(Client),
+}
+
+impl Store {
+    fn list(&self) -> Result<Vec<Task>, String> {
+        match self {
+            Store::Local { db, runtime } => runtime
+                .block_on(TaskMac::list(db))
+                .map_err(|e| e.to_string()),
+            Store::Remote(client) => client
+                .list_all(&ListParams::default())
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    fn update(&self, id: i64, patch: TaskPatch) -> Result<(), String> {
+        match self {
+            Store::Local { db, runtime } => runtime
+                .block_on(TaskMac::update_by(db, id, patch, Some(CLIENT_ID)))
+                .map(drop)
+                .map_err(|e| e.to_string()),
+            Store::Remote(client) => client
+                .update(id, &patch)
+                .map(drop)
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Move a task and its subtasks to the trash.
+    fn delete(&self, id: i64) -> Result<(), String> {
+        match self {
+            Store::Local { db, runtime } => runtime
+                .block_on(TaskMac::delete_with(
+                    db,
+                    id,
+                    ChildPolicy::Cascade,
+                    Some(CLIENT_ID),
+                ))
+                .map_err(|e| e.to_string()),
+            Store::Remote(client) => client
+                .delete(id, ChildPolicy::Cascade)
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Send on `changes` from a background thread whenever the tasks change, until the
+    /// receiver is dropped. A local database is polled, so that changes by other
+    /// processes are seen too; a server streams its change events.
+    fn watch(&self, changes: Sender<()>) {
+        match self {
+            Store::Local { db, runtime } => {
+                let (db, runtime) = (db.clone(), runtime.handle().clone());
+                thread::spawn(move || {
+                    let mut seen = None;
+                    loop {
+                        let latest = runtime.block_on(EventMac::latest_id(&db)).ok();
+                        if seen.is_some() && latest != seen && changes.send(()).is_err() {
+                            return;
+                        }
+                        seen = latest;
+                        thread::sleep(POLL_INTERVAL);
+                    }
+                });
+            }
+            Store::Remote(client) => {
+                let client = client.clone();
+                thread::spawn(move || loop {
+                    if let Ok(events) = client.events() {
+                        // Changes may have been missed while disconnected.
+                        if changes.send(()).is_err() {
+                            return;
+                        }
+                        for event in events {
+                            if event.is_err() {
+                                break;
+                            }
+                            if changes.send(()).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    thread::sleep(RECONNECT_DELAY);
+                });
+            }
+        }
+    }
+}
+
+/// What the keys currently do.
+#[derive(Debug, Clone, PartialEq)]
+enum Mode {
+    Browse,
+    /// Editing the name of a task, based on the given version of it.
+    Rename {
+        id: i64,
+        version: i64,
+        name: String,
+    },
+    /// Asking whether to delete a task.
+    ConfirmDelete {
+        id: i64,
+    },
+}
+
+struct App {
+    store: Store,
+    /// Shown in the title, e.g. the server URL.
+    source: String,
+    tasks: Vec<Task>,
+    table: TableState,
+    mode: Mode,
+    /// Result of the last action, shown until the next key press.
+    message: Option<String>,
+    quit: bool,
+}
+
+/// Run the UI until the user quits.
+pub fn run(store: Store, source: String) -> std::io::Result<()> {
+    let (sender, changes) = mpsc::channel();
+    store.watch(sender);
+    let mut app = App::new(store, source);
+    app.reload();
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal, &changes);
+    ratatui::restore();
+    result
+}
+
+impl App {
+    fn new(store: Store, source: String) -> Self {
+        App {
+            store,
+            source,
+            tasks: Vec::new(),
+            table: TableState::default(),
+            mode: Mode::Browse,
+            message: None,
+            quit: false,
+        }
+    }
+
+    fn run(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        changes: &Receiver<()>,
+    ) -> std::io::Result<()> {
+        while !self.quit {
+            terminal.draw(|frame| self.draw(frame))?;
+            if event::poll(Duration::from_millis(250))? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        self.handle_key(key);
+                    }
+                }
+            }
+            if changes.try_iter().count() > 0 {
+                self.reload();
+            }
+        }
+        Ok(())
+    }
+
+    fn selected(&self) -> Option<&Task> {
+        self.table
+            .selected()
+            .and_then(|index| self.tasks.get(index))
+    }
+
+    /// Read the tasks again, keeping the selected task selected.
+    fn reload(&mut self) {
+        let selected = self.selected().map(|task| task.id);
+        match self.store.list() {
+            Ok(tasks) => self.tasks = tasks,
+            Err(e) => self.message = Some(e),
+        }
+        let index = selected
+            .and_then(|id| self.tasks.iter().position(|task| task.id == id))
+            .or_else(|| self.table.selected())
+            .map(|index| index.min(self.tasks.len().saturating_sub(1)));
+        self.table
+            .select(index.or(Some(0)).filter(|_| !self.tasks.is_empty()));
+    }
+
+    /// Apply a change, then reload.
+    fn change(&mut self, result: Result<(), String>) {
+        if let Err(e) = result {
+            self.message = Some(e);
+        }
+        self.reload();
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        self.message = None;
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return;
+        }
+        match std::mem::replace(&mut self.mode, Mode::Browse) {
+            Mode::Browse => self.browse(key.code),
+            Mode::Rename {
+                id,
+                version,
+                mut name,
+            } => match key.code {
+                KeyCode::Enter => {
+                    let patch = TaskPatch {
+                        name: Some(name),
+                        version: Some(version),
+                        ..Default::default()
+                    };
+                    let result = self.store.update(id, patch);
+                    self.change(result);
+                }
+                KeyCode::Esc => {}
+                code => {
+                    match code {
+                        KeyCode::Backspace => {
+                            name.pop();
+                        }
+                        KeyCode::Char(c) => name.push(c),
+                        _ => {}
+                    }
+                    self.mode = Mode::Rename { id, version, name };
+                }
+            },
+            Mode::ConfirmDelete { id } => {
+                if key.code == KeyCode::Char('y') {
+                    let result = self.store.delete(id);
+                    self.change(result);
+                }
+            }
+        }
+    }
+
+    fn browse(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Up | KeyCode::Char('k') => self.table.select_previous(),
+            KeyCode::Down | KeyCode::Char('j') => {
+                if self.table.selected() < Some(self.tasks.len().saturating_sub(1)) {
+                    self.table.select_next();
+                }
+            }
+            KeyCode::Home | KeyCode::Char('g') => self.table.select_first(),
+            KeyCode::End | KeyCode::Char('G') => {
+                self.table.select(self.tasks.len().checked_sub(1));
+            }
+            KeyCode::Char('r') => self.reload(),
+            _ => {
+                let Some(task) = self.selected() else {
+                    return;
+                };
+                let (id, version) = (task.id, task.version);
+                match code {
+                    KeyCode::Char(' ') | KeyCode::Char('x') => {
+                        let status = match task.status {
+                            TaskStatus::Open => TaskStatus::Closed,
+                            TaskStatus::Closed => TaskStatus::Open,
+                        };
+                        let patch = TaskPatch {
+                            status: Some(status),
+                            version: Some(version),
+                            ..Default::default()
+                        };
+                        let result = self.store.update(id, patch);
+                        self.change(result);
+                    }
+                    KeyCode::Enter | KeyCode::Char('e') => {
+                        let name = task.name.clone();
+                        self.mode = Mode::Rename { id, version, name };
+                    }
+                    KeyCode::Delete | KeyCode::Char('d') => {
+                        self.mode = Mode::ConfirmDelete { id };
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [list_area, footer_area] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+
+        let open = self
+            .tasks
+            .iter()
+            .filter(|task| task.status == TaskStatus::Open)
+            .count();
+        let title = format!(
+            " Tasks: {} open, {} closed ({}) ",
+            open,
+            self.tasks.len() - open,
+            self.source
+        );
+        let rows = self.tasks.iter().map(|task| {
+            let name = match &self.mode {
+                Mode::Rename { id, name, .. } if *id == task.id => {
+                    Cell::from(format!("{}▏", name)).fg(Color::Yellow)
+                }
+                _ => Cell::from(task.name.as_str()),
+            };
+            let tags: Vec<&str> = task.tags.iter().map(|tag| tag.name.as_str()).collect();
+            let row = Row::new([
+                Cell::from(match task.status {
+                    TaskStatus::Open => "[ ]",
+                    TaskStatus::Closed => "[x]",
+                }),
+                Cell::from(task.id.to_string()),
+                Cell::from(format!("{:?}", task.priority)),
+                name,
+                Cell::from(
+                    task.due_at
+                        .map(|due| due.format("%Y-%m-%d %H:%M").to_string())
+                        .unwrap_or_default(),
+                ),
+                Cell::from(tags.join(", ")),
+            ]);
+            match task.status {
+                TaskStatus::Open => row,
+                TaskStatus::Closed => row.add_modifier(Modifier::DIM | Modifier::CROSSED_OUT),
+            }
+        });
+        let widths = [
+            Constraint::Length(3),
+            Constraint::Length(5),
+            Constraint::Length(8),
+            Constraint::Fill(1),
+            Constraint::Length(16),
+            Constraint::Length(20),
+        ];
+        let table = Table::new(rows, widths)
+            .header(
+                Row::new(["", "ID", "Priority", "Name", "Due", "Tags"])
+                    .add_modifier(Modifier::BOLD),
+            )
+            .block(Block::bordered().title(title))
+            .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, list_area, &mut self.table);
+
+        let footer = match (&self.message, &self.mode) {
+            (Some(message), _) => Line::from(message.as_str()).fg(Color::Red),
+            (None, Mode::Browse) => {
+                Line::from("↑↓ move  space toggle  e rename  d delete  r reload  q quit")
+            }
+            (None, Mode::Rename { .. }) => Line::from("enter save  esc cancel"),
+            (None, Mode::ConfirmDelete { id }) => {
+                let subtasks = self
+                    .tasks
+                    .iter()
+                    .filter(|task| task.parent_id == Some(*id))
+                    .count();
+                let name = self.selected().map(|task| task.name.as_str());
+                let text = match subtasks {
+                    0 => format!("Delete \"{}\"? y/n", name.unwrap_or_default()),
+                    _ => format!(
+                        "Delete \"{}\" and its subtasks? y/n",
+                        name.unwrap_or_default()
+                    ),
+                };
+                Line::from(text).fg(Color::Yellow)
+            }
+        };
+        frame.render_widget(Paragraph::new(footer), footer_area);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use taskapp::database::{create_and_connect, DbAddress};
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    /// Test toggling, renaming and deleting tasks with the keyboard.
+    #[test]
+    fn test_keys() {
+        // # Fixture
+        let runtime = Runtime::new().unwrap();
+        let db = runtime
+            .block_on(create_and_connect(DbAddress::Memory))
+            .unwrap();
+        for name in ["Sweep", "Mop"] {
+            let patch = TaskPatch {
+                name: Some(name.to_string()),
+                ..Default::default()
+            };
+            runtime.block_on(TaskMac::insert(&db, patch)).unwrap();
+        }
+        let mut app = App::new(Store::Local { db, runtime }, "test".to_string());
+        app.reload();
+
+        // # Toggle the second task
+        app.handle_key(key(KeyCode::Down));
+        app.handle_key(key(KeyCode::Char(' ')));
+        assert_eq!(app.tasks[1].status, TaskStatus::Closed);
+        assert_eq!(app.selected().map(|task| task.id), Some(2));
+
+        // # Rename it
+        app.handle_key(key(KeyCode::Char('e')));
+        app.handle_key(key(KeyCode::Backspace));
+        for c in "p the floor".chars() {
+            app.handle_key(key(KeyCode::Char(c)));
+        }
+        app.handle_key(key(KeyCode::Enter));
+        assert_eq!(app.tasks[1].name, "Mop the floor");
+        assert_eq!(app.mode, Mode::Browse);
+
+        // # Cancel, then confirm deleting it
+        app.handle_key(key(KeyCode::Char('d')));
+        app.handle_key(key(KeyCode::Char('n')));
+        assert_eq!(app.tasks.len(), 2);
+        app.handle_key(key(KeyCode::Char('d')));
+        app.handle_key(key(KeyCode::Char('y')));
+        assert_eq!(app.tasks.len(), 1);
+        assert_eq!(app.selected().map(|task| task.id), Some(1));
+    }
+}
@@ -0,0 +1,330 @@
+//! Blocking client for the REST API. Requests and responses use the model types, so
+//! the client and the server agree on the wire format.
+
+use crate::model::hierarchy::ChildPolicy;
+use crate::model::listing::Page;
+use crate::model::task::{Task, TaskPatch, TaskStatus, TaskUpdate};
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader};
+use std::time::Duration;
+
+/// Server used when none is configured.
+pub const DEFAULT_SERVER: &str = "http://127.0.0.1:8080";
+
+/// Failure of an API call.
+#[derive(thiserror::Error, Debug)]
+pub enum ClientError {
+    /// The server could not be reached.
+    #[error("Cannot reach the server: {0}")]
+    Transport(String),
+    /// The server refused the request. `typ` is the error type in the response, e.g.
+    /// `notFound`.
+    #[error("{message}")]
+    Api {
+        status: u16,
+        typ: String,
+        message: String,
+    },
+    /// The response is not what the API sends.
+    #[error("Unexpected response from the server: {0}")]
+    Decode(String),
+}
+
+/// Query parameters for listing tasks, as for `GET /api/tasks`.
+#[derive(Debug, Default, Clone)]
+pub struct ListParams {
+    pub status: Option<TaskStatus>,
+    /// Tasks carrying all of these tags.
+    pub tags: Vec<String>,
+    /// Filter expression, e.g. `due<7d and priority>=high`.
+    pub filter: Option<String>,
+    /// Comma-separated sort keys, e.g. `priority,due_at:asc`.
+    pub sort: Option<String>,
+    /// Page size. The server default if `None`.
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+}
+
+/// Body of a successful response.
+#[derive(Deserialize)]
+struct DataBody<T> {
+    data: T,
+}
+
+/// Body of a listing response.
+#[derive(Deserialize)]
+struct PageBody {
+    data: Vec<Task>,
+    next_cursor: Option<String>,
+    total: i64,
+}
+
+/// Body of an error response.
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetail {
+    #[serde(rename = "type")]
+    typ: String,
+    message: String,
+}
+
+/// Client of the task endpoints of a server.
+#[derive(Debug, Clone)]
+pub struct Client {
+    /// Server URL without a trailing slash, e.g. `http://127.0.0.1:8080`.
+    server: String,
+    agent: ureq::Agent,
+    /// Sent as `X-Client-Id`, and recorded in the task history.
+    client_id: Option<String>,
+}
+
+impl Client {
+    pub fn new(server: &str) -> Self {
+        Client {
+            server: server.trim_end_matches('/').to_string(),
+            // No overall timeout, so that the event stream can stay open. The server sends
+            // a keep-alive comment every 15 seconds.
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(Duration::from_secs(10))
+                .timeout_read(Duration::from_secs(30))
+                .build(),
+            client_id: None,
+        }
+    }
+
+    /// Identify the client to the server, for the task history.
+    pub fn with_client_id(mut self, client_id: &str) -> Self {
+        self.client_id = Some(client_id.to_string());
+        self
+    }
+
+    /// One page of tasks.
+    pub fn list(&self, params: &ListParams) -> Result<Page<Task>, ClientError> {
+        let mut request = self.request("GET", "tasks");
+        if let Some(status) = &params.status {
+            request = request.query("status", &query_value(status));
+        }
+        if !params.tags.is_empty() {
+            request = request.query("tags", &params.tags.join(","));
+        }
+        let optional = [
+            ("filter", params.filter.clone()),
+            ("sort", params.sort.clone()),
+            ("limit", params.limit.map(|limit| limit.to_string())),
+            ("cursor", params.cursor.clone()),
+        ];
+        for (key, value) in optional {
+            if let Some(value) = value {
+                request = request.query(key, &value);
+            }
+        }
+        let body: PageBody = receive(request.call())?;
+        Ok(Page {
+            items: body.data,
+            next_cursor: body.next_cursor,
+            total: body.total,
+        })
+    }
+
+    /// All tasks, following the pages of the listing.
+    pub fn list_all(&self, params: &ListParams) -> Result<Vec<Task>, ClientError> {
+        let mut params = params.clone();
+        let mut tasks = Vec::new();
+        loop {
+            let page = self.list(&params)?;
+            tasks.extend(page.items);
+            match page.next_cursor {
+                Some(cursor) => params.cursor = Some(cursor),
+                None => return Ok(tasks),
+            }
+        }
+    }
+
+    pub fn get(&self, id: i64) -> Result<Task, ClientError> {
+        let request = self.request("GET", &format!("tasks/{}", id));
+        data(request.call())
+    }
+
+    pub fn insert(&self, patch: &TaskPatch) -> Result<Task, ClientError> {
+        let request = self.request("POST", "tasks");
+        data(request.send_json(patch))
+    }
+
+    /// Update a task, and report the tasks that closing it unblocked.
+    pub fn update(&self, id: i64, patch: &TaskPatch) -> Result<TaskUpdate, ClientError> {
+        let request = self.request("PATCH", &format!("tasks/{}", id));
+        data(request.send_json(patch))
+    }
+
+    /// Move a task to the trash, with its subtasks as `children` says.
+    pub fn delete(&self, id: i64, children: ChildPolicy) -> Result<(), ClientError> {
+        let request = self
+            .request("DELETE", &format!("tasks/{}", id))
+            .query("children", &query_value(&children));
+        data::<IgnoredAny>(request.call())?;
+        Ok(())
+    }
+
+    /// Stream the ids of the change events after now, as `GET /api/events` sends them.
+    pub fn events(&self) -> Result<Events, ClientError> {
+        let response = self.request("GET", "events").call().map_err(error)?;
+        Ok(Events {
+            reader: Box::new(BufReader::new(response.into_reader())),
+        })
+    }
+
+    /// A request to an API path, e.g. `tasks/1`.
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        let url = format!("{}/api/{}", self.server, path);
+        let request = self.agent.request(method, &url);
+        match &self.client_id {
+            Some(client_id) => request.set("x-client-id", client_id),
+            None => request,
+        }
+    }
+}
+
+/// Ids of change events, read from a server-sent event stream. Each call to `next`
+/// blocks until the next event arrives. Ends when the server closes the stream.
+pub struct Events {
+    reader: Box<dyn BufRead + Send>,
+}
+
+impl Iterator for Events {
+    type Item = Result<i64, ClientError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match self.reader.read_line(&mut line) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => return Some(Err(ClientError::Transport(e.to_string()))),
+            }
+            if let Some(id) = line.strip_prefix("id:") {
+                let id = id.trim();
+                return Some(
+                    id.parse()
+                        .map_err(|_| ClientError::Decode(format!("event id {:?}", id))),
+                );
+            }
+        }
+    }
+}
+
+/// The `data` of a response.
+fn data<T: DeserializeOwned>(
+    response: Result<ureq::Response, ureq::Error>,
+) -> Result<T, ClientError> {
+    receive::<DataBody<T>>(response).map(|body| body.data)
+}
+
+/// The body of a response, or the error it reports.
+fn receive<T: DeserializeOwned>(
+    response: Result<ureq::Response, ureq::Error>,
+) -> Result<T, ClientError> {
+    response
+        .map_err(error)?
+        .into_json()
+        .map_err(|e| ClientError::Decode(e.to_string()))
+}
+
+/// The error reported by a failed call.
+fn error(e: ureq::Error) -> ClientError {
+    match e {
+        ureq::Error::Status(status, response) => {
+            let text = response.into_string().unwrap_or_default();
+            match serde_json::from_str::<ErrorBody>(&text) {
+                Ok(body) => ClientError::Api {
+                    status,
+                    typ: body.error.typ,
+                    message: body.error.message,
+                },
+                Err(_) => ClientError::Decode(format!("status {}: {}", status, text)),
+            }
+        }
+        ureq::Error::Transport(e) => ClientError::Transport(e.to_string()),
+    }
+}
+
+/// A value as it appears in a query string, e.g. `Open` for [`TaskStatus::Open`].
+fn query_value<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value).expect("query values serialize") {
+        serde_json::Value::String(value) => value,
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use crate::web::events::event_filters;
+    use crate::web::task::task_rest_filters;
+    use std::sync::Arc;
+    use warp::Filter;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_client() -> Result<(), crate::Error> {
+        // # Setup
+        let database = Arc::new(create_and_connect(DbAddress::Memory).await?);
+        let filters = task_rest_filters("api", database.clone())
+            .or(event_filters("api", database))
+            .recover(crate::web::handle_rejection);
+        let (address, server) = warp::serve(filters).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let client = Client::new(&format!("http://{}/", address)).with_client_id("test");
+
+        // # Action
+        let result = tokio::task::spawn_blocking(move || {
+            let patch = |name: &str| TaskPatch {
+                name: Some(name.to_string()),
+                ..Default::default()
+            };
+            let mut events = client.events()?;
+            let first = client.insert(&patch("Water plants"))?;
+            let event = events.next().unwrap()?;
+            client.insert(&patch("Call mom"))?;
+            let closed = client.update(
+                first.id,
+                &TaskPatch {
+                    status: Some(TaskStatus::Closed),
+                    ..Default::default()
+                },
+            )?;
+            let params = ListParams {
+                limit: Some(1),
+                ..Default::default()
+            };
+            let page = client.list(&params)?;
+            let all = client.list_all(&params)?;
+            client.delete(first.id, ChildPolicy::Refuse)?;
+            let missing = client.get(first.id).unwrap_err();
+            let unreachable = Client::new("http://127.0.0.1:1").get(1).unwrap_err();
+            Ok::<_, ClientError>((event, closed, page, all, missing, unreachable))
+        })
+        .await
+        .unwrap();
+
+        // # Check
+        let (event, closed, page, all, missing, unreachable) = result.unwrap();
+        assert_eq!(event, 1);
+        assert_eq!(closed.task.status, TaskStatus::Closed);
+        assert_eq!((page.items.len(), page.total), (1, 2));
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[1].name, "Call mom");
+        assert!(
+            matches!(&missing, ClientError::Api { status: 404, typ, .. } if typ == "notFound"),
+            "{:?}",
+            missing
+        );
+        assert!(matches!(unreachable, ClientError::Transport(_)));
+        Ok(())
+    }
+}
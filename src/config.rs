@@ -0,0 +1,339 @@
+use crate::database::{DbAddress, PoolConfig};
+use clap::{Parser, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+/// Prefix of all environment variables read by the server.
+pub const ENV_PREFIX: &str = "TASKAPP_";
+/// Config file read when neither `--config` nor `TASKAPP_CONFIG` is given.
+pub const DEFAULT_CONFIG_FILE: &str = "taskapp.toml";
+/// Database address that selects an in-memory database.
+pub const MEMORY_DATABASE: &str = ":memory:";
+
+/// Output format of the log subscriber.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Pretty,
+    Compact,
+    Json,
+}
+
+/// Effective runtime configuration of the server.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Config {
+    pub port: u16,
+    pub bind_address: IpAddr,
+    pub root_dir: String,
+    /// Path to the SQLite file, or `:memory:`.
+    pub database: String,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub log_format: LogFormat,
+    /// Days that deleted tasks stay in the trash before they are purged. `0` keeps
+    /// them until they are purged by hand.
+    pub trash_retention_days: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            port: 8080,
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            root_dir: "frontend/dist".to_string(),
+            database: "taskapp.sqlite".to_string(),
+            max_connections: 5,
+            min_connections: 0,
+            log_format: LogFormat::Pretty,
+            trash_retention_days: 30,
+        }
+    }
+}
+
+impl Config {
+    /// Build the configuration from defaults, the config file, `TASKAPP_*` environment
+    /// variables and command line flags, in increasing order of precedence.
+    pub fn load(cli: &Cli, env: impl IntoIterator<Item = (String, String)>) -> Result<Self, crate::Error> {
+        let env = ConfigLayer::from_env(env)?;
+
+        // The config file location itself follows the same precedence.
+        let (file, required) = match (&cli.config, &env.config) {
+            (Some(path), _) | (None, Some(path)) => (path.clone(), true),
+            (None, None) => (PathBuf::from(DEFAULT_CONFIG_FILE), false),
+        };
+        let file = if required || file.exists() {
+            ConfigLayer::from_file(&file)?
+        } else {
+            ConfigLayer::default()
+        };
+
+        let config = Config::default()
+            .merge(file)
+            .merge(env)
+            .merge(cli.layer.clone());
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Override fields that are set in `layer`.
+    fn merge(mut self, layer: ConfigLayer) -> Self {
+        if let Some(port) = layer.port {
+            self.port = port;
+        }
+        if let Some(bind_address) = layer.bind_address {
+            self.bind_address = bind_address;
+        }
+        if let Some(root_dir) = layer.root_dir {
+            self.root_dir = root_dir;
+        }
+        if let Some(database) = layer.database {
+            self.database = database;
+        }
+        if let Some(max_connections) = layer.max_connections {
+            self.max_connections = max_connections;
+        }
+        if let Some(min_connections) = layer.min_connections {
+            self.min_connections = min_connections;
+        }
+        if let Some(log_format) = layer.log_format {
+            self.log_format = log_format;
+        }
+        if let Some(trash_retention_days) = layer.trash_retention_days {
+            self.trash_retention_days = trash_retention_days;
+        }
+        self
+    }
+
+    fn validate(&self) -> Result<(), crate::Error> {
+        if self.max_connections == 0 {
+            return Err(crate::Error::Config(
+                "max_connections must be at least 1".to_string(),
+            ));
+        }
+        if self.min_connections > self.max_connections {
+            return Err(crate::Error::Config(format!(
+                "min_connections ({}) exceeds max_connections ({})",
+                self.min_connections, self.max_connections
+            )));
+        }
+        Ok(())
+    }
+
+    /// Socket address the web server binds to.
+    pub fn socket_address(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_address, self.port)
+    }
+
+    /// Database address derived from the `database` setting.
+    pub fn db_address(&self) -> DbAddress {
+        match self.database.as_str() {
+            MEMORY_DATABASE => DbAddress::Memory,
+            path => DbAddress::Path(path.to_string()),
+        }
+    }
+
+    /// Connection pool settings.
+    pub fn pool(&self) -> PoolConfig {
+        PoolConfig {
+            max_connections: self.max_connections,
+            min_connections: self.min_connections,
+        }
+    }
+
+    /// How long deleted tasks stay in the trash, or `None` to keep them.
+    pub fn trash_retention(&self) -> Option<chrono::Duration> {
+        (self.trash_retention_days > 0)
+            .then(|| chrono::Duration::days(self.trash_retention_days.into()))
+    }
+
+    /// Render the configuration as TOML, in the same format the config file uses.
+    pub fn to_toml(&self) -> Result<String, crate::Error> {
+        toml::to_string(self).map_err(|e| crate::Error::Config(e.to_string()))
+    }
+}
+
+/// One layer of partial configuration. Unset fields fall through to lower layers.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, clap::Args)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigLayer {
+    /// Port to listen on.
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// IP address to bind to.
+    #[arg(long)]
+    pub bind_address: Option<IpAddr>,
+    /// Directory with the static frontend.
+    #[arg(long)]
+    pub root_dir: Option<String>,
+    /// Path to the SQLite database, or `:memory:`.
+    #[arg(long)]
+    pub database: Option<String>,
+    /// Maximum number of pooled database connections.
+    #[arg(long)]
+    pub max_connections: Option<u32>,
+    /// Minimum number of pooled database connections.
+    #[arg(long)]
+    pub min_connections: Option<u32>,
+    /// Log output format.
+    #[arg(long, value_enum)]
+    pub log_format: Option<LogFormat>,
+    /// Days before deleted tasks are purged from the trash, or 0 to keep them.
+    #[arg(long)]
+    pub trash_retention_days: Option<u32>,
+    /// Only settable through the environment. See [`Cli::config`].
+    #[arg(skip)]
+    #[serde(skip)]
+    config: Option<PathBuf>,
+}
+
+impl ConfigLayer {
+    fn from_file(path: &Path) -> Result<Self, crate::Error> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            crate::Error::Config(format!("Cannot read {}: {}", path.display(), e))
+        })?;
+        toml::from_str(&content)
+            .map_err(|e| crate::Error::Config(format!("Invalid {}: {}", path.display(), e)))
+    }
+
+    fn from_env(env: impl IntoIterator<Item = (String, String)>) -> Result<Self, crate::Error> {
+        fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, crate::Error> {
+            value.parse().map_err(|_| {
+                crate::Error::Config(format!("Invalid value {:?} for {}{}", value, ENV_PREFIX, key))
+            })
+        }
+
+        let mut layer = ConfigLayer::default();
+        for (key, value) in env {
+            let Some(key) = key.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            match key {
+                "PORT" => layer.port = Some(parse(key, &value)?),
+                "BIND_ADDRESS" => layer.bind_address = Some(parse(key, &value)?),
+                "ROOT_DIR" => layer.root_dir = Some(value),
+                "DATABASE" => layer.database = Some(value),
+                "MAX_CONNECTIONS" => layer.max_connections = Some(parse(key, &value)?),
+                "MIN_CONNECTIONS" => layer.min_connections = Some(parse(key, &value)?),
+                "LOG_FORMAT" => {
+                    layer.log_format = Some(LogFormat::from_str(&value, true).map_err(|_| {
+                        crate::Error::Config(format!(
+                            "Invalid value {:?} for {}LOG_FORMAT",
+                            value, ENV_PREFIX
+                        ))
+                    })?)
+                }
+                "TRASH_RETENTION_DAYS" => {
+                    layer.trash_retention_days = Some(parse(key, &value)?)
+                }
+                "CONFIG" => layer.config = Some(PathBuf::from(value)),
+                _ => (),
+            }
+        }
+        Ok(layer)
+    }
+}
+
+/// Command line interface of the server.
+///
+/// Settings are read from the config file, then `TASKAPP_<SETTING>` environment
+/// variables, then the flags below, with later sources taking precedence.
+#[derive(Debug, Default, Parser)]
+#[command(version, about = "Task app server")]
+pub struct Cli {
+    /// Path to a TOML config file. Defaults to `taskapp.toml` if it exists.
+    #[arg(long, short)]
+    pub config: Option<PathBuf>,
+    /// Print the effective configuration and exit.
+    #[arg(long)]
+    pub print_config: bool,
+    #[command(flatten)]
+    pub layer: ConfigLayer,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn write_config(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("taskapp-{}-{}.toml", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn defaults() -> Result<(), crate::Error> {
+        let cli = Cli {
+            config: Some(write_config("empty", "")),
+            ..Default::default()
+        };
+        assert_eq!(Config::load(&cli, env(&[]))?, Config::default());
+        Ok(())
+    }
+
+    #[test]
+    fn precedence() -> Result<(), crate::Error> {
+        // # Fixture
+        let file = write_config(
+            "precedence",
+            "port = 1000\nroot_dir = \"file\"\ndatabase = \"file.sqlite\"\nlog_format = \"json\"\n",
+        );
+        let cli = Cli::parse_from(["taskapp", "--config", file.to_str().unwrap(), "--port", "3000"]);
+        let env = env(&[
+            ("TASKAPP_PORT", "2000"),
+            ("TASKAPP_ROOT_DIR", "env"),
+            ("OTHER_PORT", "1"),
+        ]);
+
+        // # Action
+        let config = Config::load(&cli, env)?;
+
+        // # Check
+        assert_eq!(config.port, 3000);
+        assert_eq!(config.root_dir, "env");
+        assert_eq!(config.database, "file.sqlite");
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert_eq!(config.bind_address, Config::default().bind_address);
+        Ok(())
+    }
+
+    #[test]
+    fn config_path_from_env() -> Result<(), crate::Error> {
+        let file = write_config("env-path", "max_connections = 9\n");
+        let config = Config::load(
+            &Cli::default(),
+            env(&[("TASKAPP_CONFIG", file.to_str().unwrap())]),
+        )?;
+        assert_eq!(config.max_connections, 9);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_values() {
+        let result = Config::load(&Cli::default(), env(&[("TASKAPP_PORT", "high")]));
+        assert!(matches!(result, Err(crate::Error::Config(_))));
+
+        let file = write_config("unknown", "prot = 1\n");
+        let cli = Cli::parse_from(["taskapp", "--config", file.to_str().unwrap()]);
+        assert!(matches!(Config::load(&cli, env(&[])), Err(crate::Error::Config(_))));
+
+        let cli = Cli::parse_from(["taskapp", "--min-connections", "4", "--max-connections", "2"]);
+        assert!(matches!(Config::load(&cli, env(&[])), Err(crate::Error::Config(_))));
+    }
+
+    #[test]
+    fn print_round_trips() -> Result<(), crate::Error> {
+        let config = Config::default();
+        let file = write_config("round-trip", &config.to_toml()?);
+        let cli = Cli::parse_from(["taskapp", "--config", file.to_str().unwrap()]);
+        assert_eq!(Config::load(&cli, env(&[]))?, config);
+        Ok(())
+    }
+}
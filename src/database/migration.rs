@@ -0,0 +1,359 @@
+use super::Database;
+use log::info;
+use sqlx::types::chrono::Utc;
+
+/// A single versioned schema change.
+pub struct Migration {
+    /// Monotonically increasing version number, starting at 1.
+    pub version: i64,
+    /// Short human readable description, stored alongside the version.
+    pub description: &'static str,
+    /// SQL to run. May contain several statements.
+    pub sql: &'static str,
+}
+
+/// All known migrations, in the order they must be applied.
+///
+/// Never edit a migration that has been released. Add a new one instead. Changes to
+/// `tasks` that SQLite's `ALTER TABLE` cannot express should copy the rows into a new
+/// table and rename it, so that existing data survives.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create tasks table",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS tasks (
+            id INTEGER NOT NULL PRIMARY KEY ,
+            name TEXT NOT NULL,
+            status VARCHAR(5) NOT NULL DEFAULT 'open',
+            creation_time INTEGER NOT NULL
+        );
+        "#,
+    },
+    Migration {
+        version: 2,
+        description: "add due and start dates to tasks",
+        sql: r#"
+        ALTER TABLE tasks ADD COLUMN due_at INTEGER;
+        ALTER TABLE tasks ADD COLUMN start_at INTEGER;
+        CREATE INDEX tasks_due_at ON tasks (due_at);
+        "#,
+    },
+    Migration {
+        version: 3,
+        description: "add priority to tasks",
+        sql: r#"
+        ALTER TABLE tasks ADD COLUMN priority INTEGER NOT NULL DEFAULT 1;
+        CREATE INDEX tasks_priority ON tasks (priority);
+        "#,
+    },
+    Migration {
+        version: 4,
+        description: "add tags",
+        sql: r#"
+        CREATE TABLE tags (
+            id INTEGER NOT NULL PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE COLLATE NOCASE
+        );
+        CREATE TABLE task_tags (
+            task_id INTEGER NOT NULL REFERENCES tasks (id) ON DELETE CASCADE,
+            tag_id INTEGER NOT NULL REFERENCES tags (id) ON DELETE CASCADE,
+            PRIMARY KEY (task_id, tag_id)
+        );
+        CREATE INDEX task_tags_tag_id ON task_tags (tag_id);
+        "#,
+    },
+    Migration {
+        version: 5,
+        description: "add parent tasks",
+        sql: r#"
+        ALTER TABLE tasks ADD COLUMN parent_id INTEGER REFERENCES tasks (id);
+        CREATE INDEX tasks_parent_id ON tasks (parent_id);
+        "#,
+    },
+    Migration {
+        version: 6,
+        description: "add task dependencies",
+        sql: r#"
+        CREATE TABLE task_dependencies (
+            blocker_id INTEGER NOT NULL REFERENCES tasks (id) ON DELETE CASCADE,
+            blocked_id INTEGER NOT NULL REFERENCES tasks (id) ON DELETE CASCADE,
+            PRIMARY KEY (blocker_id, blocked_id)
+        );
+        CREATE INDEX task_dependencies_blocked_id ON task_dependencies (blocked_id);
+        "#,
+    },
+    Migration {
+        version: 7,
+        description: "add recurrence rules to tasks",
+        sql: r#"
+        ALTER TABLE tasks ADD COLUMN recurrence TEXT;
+        "#,
+    },
+    Migration {
+        version: 8,
+        description: "add full-text search index on task names",
+        sql: r#"
+        CREATE VIRTUAL TABLE tasks_fts USING fts5 (
+            name,
+            content = 'tasks',
+            content_rowid = 'id'
+        );
+        CREATE TRIGGER tasks_fts_insert AFTER INSERT ON tasks BEGIN
+            INSERT INTO tasks_fts (rowid, name) VALUES (new.id, new.name);
+        END;
+        CREATE TRIGGER tasks_fts_delete AFTER DELETE ON tasks BEGIN
+            INSERT INTO tasks_fts (tasks_fts, rowid, name) VALUES ('delete', old.id, old.name);
+        END;
+        CREATE TRIGGER tasks_fts_update AFTER UPDATE OF name ON tasks BEGIN
+            INSERT INTO tasks_fts (tasks_fts, rowid, name) VALUES ('delete', old.id, old.name);
+            INSERT INTO tasks_fts (rowid, name) VALUES (new.id, new.name);
+        END;
+        INSERT INTO tasks_fts (tasks_fts) VALUES ('rebuild');
+        "#,
+    },
+    Migration {
+        version: 9,
+        description: "add saved views",
+        sql: r#"
+        CREATE TABLE views (
+            id INTEGER NOT NULL PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+            filter TEXT NOT NULL,
+            sort TEXT NOT NULL DEFAULT 'id:asc'
+        );
+        "#,
+    },
+    Migration {
+        version: 10,
+        description: "add row versions to tasks",
+        sql: r#"
+        ALTER TABLE tasks ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+        "#,
+    },
+    Migration {
+        version: 11,
+        description: "add append-only task history",
+        sql: r#"
+        CREATE TABLE task_history (
+            id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            task_id INTEGER NOT NULL,
+            action TEXT NOT NULL,
+            old_values TEXT,
+            new_values TEXT,
+            changed_at INTEGER NOT NULL,
+            client TEXT
+        );
+        CREATE INDEX task_history_task ON task_history (task_id, id);
+        CREATE TRIGGER task_history_no_update BEFORE UPDATE ON task_history BEGIN
+            SELECT RAISE(ABORT, 'task history is append-only');
+        END;
+        CREATE TRIGGER task_history_no_delete BEFORE DELETE ON task_history BEGIN
+            SELECT RAISE(ABORT, 'task history is append-only');
+        END;
+        "#,
+    },
+    Migration {
+        version: 12,
+        description: "add a trash for deleted tasks",
+        sql: r#"
+        ALTER TABLE tasks ADD COLUMN deleted_at TEXT;
+        CREATE INDEX tasks_deleted_at ON tasks (deleted_at) WHERE deleted_at IS NOT NULL;
+        "#,
+    },
+    Migration {
+        version: 13,
+        description: "add modification times to tasks",
+        sql: r#"
+        ALTER TABLE tasks ADD COLUMN modified_at INTEGER NOT NULL DEFAULT 0;
+        UPDATE tasks SET modified_at = creation_time;
+        ALTER TABLE tasks ADD COLUMN field_times TEXT NOT NULL DEFAULT '{}';
+        "#,
+    },
+    Migration {
+        version: 14,
+        description: "link tasks to Taskwarrior",
+        sql: r#"
+        CREATE TABLE taskwarrior_links (
+            task_id INTEGER NOT NULL PRIMARY KEY REFERENCES tasks (id) ON DELETE CASCADE,
+            uuid TEXT NOT NULL UNIQUE COLLATE NOCASE,
+            annotations TEXT NOT NULL DEFAULT '[]',
+            version INTEGER NOT NULL DEFAULT 0,
+            modified_at INTEGER NOT NULL DEFAULT 0
+        );
+        "#,
+    },
+    Migration {
+        version: 15,
+        description: "store trash times in seconds with a batch id",
+        sql: r#"
+        DROP INDEX tasks_deleted_at;
+        ALTER TABLE tasks RENAME COLUMN deleted_at TO deleted_at_text;
+        ALTER TABLE tasks ADD COLUMN deleted_at INTEGER;
+        ALTER TABLE tasks ADD COLUMN deleted_batch INTEGER;
+        UPDATE tasks SET deleted_at = unixepoch(deleted_at_text),
+            deleted_batch = (SELECT COUNT(DISTINCT t.deleted_at_text) FROM tasks t
+                WHERE t.deleted_at_text <= tasks.deleted_at_text)
+            WHERE deleted_at_text IS NOT NULL;
+        ALTER TABLE tasks DROP COLUMN deleted_at_text;
+        CREATE INDEX tasks_deleted_at ON tasks (deleted_at) WHERE deleted_at IS NOT NULL;
+        "#,
+    },
+];
+
+const CREATE_TRACKING_SQL: &str = r#"
+    CREATE TABLE IF NOT EXISTS schema_migrations (
+        version INTEGER NOT NULL PRIMARY KEY,
+        description TEXT NOT NULL,
+        applied_at INTEGER NOT NULL
+    );
+    "#;
+const CURRENT_VERSION_SQL: &str = "SELECT COALESCE(MAX(version), 0) FROM schema_migrations";
+const RECORD_SQL: &str =
+    "INSERT INTO schema_migrations (version, description, applied_at) VALUES (?, ?, ?)";
+
+/// The newest schema version this binary knows about.
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+/// The schema version currently recorded in the database.
+pub async fn current_version(db: &Database) -> Result<i64, crate::Error> {
+    sqlx::query(CREATE_TRACKING_SQL).execute(db).await?;
+    let version: i64 = sqlx::query_scalar(CURRENT_VERSION_SQL)
+        .fetch_one(db)
+        .await?;
+    Ok(version)
+}
+
+/// Apply all pending migrations.
+///
+/// Each migration runs in its own transaction together with its tracking record. Fails
+/// with [`crate::Error::SchemaTooNew`] if the database was migrated by a newer binary.
+pub async fn migrate(db: &Database) -> Result<(), crate::Error> {
+    let current = current_version(db).await?;
+    let latest = latest_version();
+    if current > latest {
+        return Err(crate::Error::SchemaTooNew {
+            found: current,
+            known: latest,
+        });
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        info!(
+            "Applying migration {}: {}",
+            migration.version, migration.description
+        );
+        let mut tx = db.begin().await?;
+        sqlx::raw_sql(migration.sql).execute(&mut *tx).await?;
+        sqlx::query(RECORD_SQL)
+            .bind(migration.version)
+            .bind(migration.description)
+            .bind(Utc::now().timestamp())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+    info!("Database schema at version {}.", latest);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{connect, DbAddress};
+
+    #[tokio::test]
+    async fn migrate_fresh_database() -> Result<(), crate::Error> {
+        let db = connect(DbAddress::Memory).await?;
+        migrate(&db).await?;
+        assert_eq!(current_version(&db).await?, latest_version());
+
+        // Running again is a no-op.
+        migrate(&db).await?;
+        assert_eq!(current_version(&db).await?, latest_version());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn migrate_keeps_legacy_rows() -> Result<(), crate::Error> {
+        // # Fixture: a database created before migrations existed.
+        let db = connect(DbAddress::Memory).await?;
+        sqlx::raw_sql(MIGRATIONS[0].sql).execute(&db).await?;
+        sqlx::query("INSERT INTO tasks (name, status, creation_time) VALUES ('Old', 'open', 0)")
+            .execute(&db)
+            .await?;
+
+        // # Action
+        migrate(&db).await?;
+
+        // # Check
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM tasks")
+            .fetch_one(&db)
+            .await?;
+        assert_eq!(count, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn migrate_keeps_trash_batches() -> Result<(), crate::Error> {
+        // # Fixture: tasks 1 and 2 were deleted together, task 3 on its own.
+        let db = connect(DbAddress::Memory).await?;
+        sqlx::query(CREATE_TRACKING_SQL).execute(&db).await?;
+        for migration in MIGRATIONS.iter().filter(|m| m.version < 15) {
+            sqlx::raw_sql(migration.sql).execute(&db).await?;
+            sqlx::query(RECORD_SQL)
+                .bind(migration.version)
+                .bind(migration.description)
+                .bind(0)
+                .execute(&db)
+                .await?;
+        }
+        sqlx::raw_sql(
+            r#"INSERT INTO tasks (name, creation_time, deleted_at) VALUES
+            ('One', 0, '2024-03-04T10:00:00.000000002Z'),
+            ('Two', 0, '2024-03-04T10:00:00.000000002Z'),
+            ('Three', 0, '2024-03-04T10:00:00.000000001Z'),
+            ('Live', 0, NULL)"#,
+        )
+        .execute(&db)
+        .await?;
+
+        // # Action
+        migrate(&db).await?;
+
+        // # Check
+        let rows: Vec<(Option<i64>, Option<i64>)> =
+            sqlx::query_as("SELECT deleted_at, deleted_batch FROM tasks ORDER BY id")
+                .fetch_all(&db)
+                .await?;
+        let at = Some(1709546400);
+        assert_eq!(
+            rows,
+            vec![(at, Some(2)), (at, Some(2)), (at, Some(1)), (None, None)]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refuse_newer_schema() -> Result<(), crate::Error> {
+        let db = connect(DbAddress::Memory).await?;
+        migrate(&db).await?;
+        sqlx::query(RECORD_SQL)
+            .bind(latest_version() + 1)
+            .bind("from the future")
+            .bind(0)
+            .execute(&db)
+            .await?;
+
+        match migrate(&db).await {
+            Err(crate::Error::SchemaTooNew { found, known }) => {
+                assert_eq!(found, latest_version() + 1);
+                assert_eq!(known, latest_version());
+            }
+            other => panic!("Expected SchemaTooNew, got {:?}", other),
+        }
+        Ok(())
+    }
+}
@@ -1,12 +1,100 @@
+use futures_util::future::BoxFuture;
+use futures_util::stream::BoxStream;
 use log::info;
 use sqlx::migrate::MigrateDatabase;
-use sqlx::{sqlite::SqlitePoolOptions, Pool, Sqlite};
+use sqlx::sqlite::{
+    SqliteConnectOptions, SqlitePoolOptions, SqliteQueryResult, SqliteRow, SqliteStatement,
+    SqliteTypeInfo,
+};
+use sqlx::{Describe, Either, Execute, Executor, Pool, Sqlite};
 
 use std::fs::File;
+use std::ops::Deref;
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::watch;
 
-pub type Database = Pool<Sqlite>;
+pub(crate) mod migration;
+
+/// Connection pool of a database, together with the channel that wakes up
+/// subscribers after changes to it. Queries run on the pool, e.g.
+/// `query.fetch_all(&database)`.
+#[derive(Debug, Clone)]
+pub struct Database {
+    pool: Pool<Sqlite>,
+    changes: Arc<watch::Sender<()>>,
+}
+
+impl Database {
+    fn new(pool: Pool<Sqlite>) -> Self {
+        Database {
+            pool,
+            changes: Arc::new(watch::channel(()).0),
+        }
+    }
+
+    /// Sender that wakes up subscribers after a change.
+    pub(crate) fn changes(&self) -> &watch::Sender<()> {
+        &self.changes
+    }
+}
+
+impl Deref for Database {
+    type Target = Pool<Sqlite>;
+
+    fn deref(&self) -> &Pool<Sqlite> {
+        &self.pool
+    }
+}
+
+impl<'c> Executor<'c> for &'c Database {
+    type Database = Sqlite;
+
+    fn fetch_many<'e, 'q: 'e, E>(
+        self,
+        query: E,
+    ) -> BoxStream<'e, Result<Either<SqliteQueryResult, SqliteRow>, sqlx::Error>>
+    where
+        'c: 'e,
+        E: 'q + Execute<'q, Sqlite>,
+    {
+        self.pool.fetch_many(query)
+    }
+
+    fn fetch_optional<'e, 'q: 'e, E>(
+        self,
+        query: E,
+    ) -> BoxFuture<'e, Result<Option<SqliteRow>, sqlx::Error>>
+    where
+        'c: 'e,
+        E: 'q + Execute<'q, Sqlite>,
+    {
+        self.pool.fetch_optional(query)
+    }
+
+    fn prepare_with<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+        parameters: &'e [SqliteTypeInfo],
+    ) -> BoxFuture<'e, Result<SqliteStatement<'q>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        self.pool.prepare_with(sql, parameters)
+    }
+
+    fn describe<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+    ) -> BoxFuture<'e, Result<Describe<Sqlite>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        self.pool.describe(sql)
+    }
+}
 
 #[allow(dead_code)]
 /// Address to the database.
@@ -17,6 +105,22 @@ pub enum DbAddress {
     Memory,
 }
 
+/// Connection pool settings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolConfig {
+    pub max_connections: u32,
+    pub min_connections: u32,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_connections: 1,
+            min_connections: 0,
+        }
+    }
+}
+
 impl DbAddress {
     /// Convert the address to a string.
     pub async fn to_sqlite_string(&self) -> String {
@@ -29,8 +133,17 @@ impl DbAddress {
     }
 }
 
+#[allow(dead_code)]
 /// Create a new database or connect to an existing one.
 pub async fn create_and_connect(address: DbAddress) -> Result<Database, crate::Error> {
+    create_and_connect_with(address, PoolConfig::default()).await
+}
+
+/// Create a new database or connect to an existing one, with custom pool settings.
+pub async fn create_and_connect_with(
+    address: DbAddress,
+    pool: PoolConfig,
+) -> Result<Database, crate::Error> {
     let address_str = address.to_sqlite_string().await;
     match &address {
         DbAddress::Path(path) => {
@@ -58,46 +171,40 @@ pub async fn create_and_connect(address: DbAddress) -> Result<Database, crate::E
         DbAddress::Memory => (),
     };
 
-    let pool = connect(address).await?;
-    create_schema(&pool).await?;
+    let pool = connect_with(address, pool).await?;
+    migration::migrate(&pool).await?;
     Ok(pool)
 }
 
 /// Connect to the database.
+#[cfg(test)]
 async fn connect(address: DbAddress) -> Result<Database, crate::Error> {
+    connect_with(address, PoolConfig::default()).await
+}
+
+/// Connect to the database with custom pool settings.
+async fn connect_with(address: DbAddress, pool: PoolConfig) -> Result<Database, crate::Error> {
     let conn_str = address.to_sqlite_string().await;
     info!("Connecting to {}", conn_str);
+    // Every connection to an in-memory database sees its own, empty database.
+    let pool = match address {
+        DbAddress::Memory => PoolConfig::default(),
+        DbAddress::Path(_) => pool,
+    };
+    let connect_options = SqliteConnectOptions::from_str(&conn_str)?.create_if_missing(true);
     let options = SqlitePoolOptions::new()
-        .max_connections(1)
+        .max_connections(pool.max_connections)
+        .min_connections(pool.min_connections)
         .idle_timeout(Duration::from_secs(300))
         .acquire_timeout(Duration::from_secs(5))
-        .connect(&conn_str)
+        .connect_with(connect_options)
         .await?;
-    Ok(options)
-}
-
-/// Create the database schema
-async fn create_schema(db: &Database) -> Result<(), crate::Error> {
-    info!("Creating schema.");
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS tasks (
-            id INTEGER NOT NULL PRIMARY KEY ,
-            name TEXT NOT NULL,
-            status VARCHAR(5) NOT NULL DEFAULT 'open',
-            creation_time INTEGER NOT NULL
-        );
-        "#,
-    )
-    .execute(db)
-    .await?;
-    info!("Schema created.");
-    Ok(())
+    Ok(Database::new(options))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{connect, create_and_connect, create_schema, DbAddress};
+    use super::{connect, create_and_connect, migration, DbAddress};
     use sqlx::Row;
 
     #[tokio::test]
@@ -117,11 +224,35 @@ mod tests {
         let _ = create_and_connect(DbAddress::Path("test.sqlite".into())).await;
     }
 
+    #[tokio::test]
+    async fn path_persists_across_connections() -> Result<(), crate::Error> {
+        let path = std::env::temp_dir().join(format!("taskapp-{}.sqlite", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let _ = std::fs::remove_file(&path);
+
+        // # Fixture
+        let db = create_and_connect(DbAddress::Path(path.clone())).await?;
+        sqlx::query("INSERT INTO tasks (name, status, creation_time) VALUES ('Kept', 'open', 0)")
+            .execute(&db)
+            .await?;
+        db.close().await;
+
+        // # Check
+        let db = create_and_connect(DbAddress::Path(path.clone())).await?;
+        let name: String = sqlx::query_scalar("SELECT name FROM tasks")
+            .fetch_one(&db)
+            .await?;
+        assert_eq!(name, "Kept");
+        db.close().await;
+        let _ = std::fs::remove_file(&path);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_schema() -> Result<(), crate::Error> {
         // # Fixture
         let db = create_and_connect(DbAddress::Memory).await?;
-        let _ = create_schema(&db).await;
+        let _ = migration::migrate(&db).await;
 
         // # Get schema
         let rows = sqlx::query("PRAGMA table_info(tasks)")
@@ -150,6 +281,26 @@ mod tests {
                     true,
                     false
                 ),
+                ("due_at".to_string(), "INTEGER".to_string(), false, false),
+                ("start_at".to_string(), "INTEGER".to_string(), false, false),
+                ("priority".to_string(), "INTEGER".to_string(), true, false),
+                ("parent_id".to_string(), "INTEGER".to_string(), false, false),
+                ("recurrence".to_string(), "TEXT".to_string(), false, false),
+                ("version".to_string(), "INTEGER".to_string(), true, false),
+                ("modified_at".to_string(), "INTEGER".to_string(), true, false),
+                ("field_times".to_string(), "TEXT".to_string(), true, false),
+                (
+                    "deleted_at".to_string(),
+                    "INTEGER".to_string(),
+                    false,
+                    false
+                ),
+                (
+                    "deleted_batch".to_string(),
+                    "INTEGER".to_string(),
+                    false,
+                    false
+                ),
             ]
         );
         Ok(())
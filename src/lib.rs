@@ -0,0 +1,87 @@
+//! Task manager: the model and its SQLite storage, the REST API server, and a client
+//! for the REST API.
+//!
+//! The model access controllers (MACs), e.g. [`TaskMac`], work on a [`Database`]
+//! directly. The REST API consists of warp filters, which can be served as they are
+//! with [`ServerBuilder`], or combined with the routes of another app:
+//!
+//! ```
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> Result<(), taskapp::Error> {
+//! use std::sync::Arc;
+//! use taskapp::{api_filters, create_and_connect, handle_rejection, DbAddress};
+//! use taskapp::{TaskMac, TaskPatch};
+//! use warp::Filter;
+//!
+//! let database = Arc::new(create_and_connect(DbAddress::Memory).await?);
+//! let patch = TaskPatch {
+//!     name: Some("Write the docs".to_string()),
+//!     ..Default::default()
+//! };
+//! TaskMac::insert(&database, patch).await?;
+//!
+//! let health = warp::path("health").map(|| "ok");
+//! let app = health
+//!     .or(api_filters("api", database))
+//!     .recover(handle_rejection);
+//! let response = warp::test::request().path("/api/tasks").reply(&app).await;
+//! assert_eq!(response.status(), 200);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod client;
+pub mod config;
+pub mod database;
+pub mod model;
+pub mod web;
+
+pub use client::Client;
+pub use config::Config;
+pub use database::{create_and_connect, create_and_connect_with, Database, DbAddress, PoolConfig};
+pub use model::task::{Task, TaskMac, TaskPatch, TaskPriority, TaskStatus};
+pub use web::{api_filters, handle_rejection, ServerBuilder};
+
+/// Error of the library and the server.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// The requested entity does not exist.
+    #[error("{0}")]
+    NotFound(String),
+    /// The request is well-formed but its content is invalid.
+    #[error("{0}")]
+    Validation(String),
+    /// A filter expression is malformed. `column` is 1-based.
+    #[error("{message} (column {column})")]
+    Filter { message: String, column: usize },
+    /// The request conflicts with the current state of the data.
+    #[error("{0}")]
+    Conflict(String),
+    /// The task changed since the version an update was based on. Holds the current
+    /// copy.
+    #[error("Task {} has changed; its current version is {}.", .0.id, .0.version)]
+    StaleVersion(Box<model::task::Task>),
+    /// Unexpected database failure.
+    #[error(transparent)]
+    Internal(sqlx::Error),
+    #[error("Server failed to start. Root directory {0} does not exist.")]
+    RootNotFound(String),
+    #[error("Database schema version {found} is newer than the latest version {known} known to this binary.")]
+    SchemaTooNew { found: i64, known: i64 },
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(e: sqlx::Error) -> Self {
+        match &e {
+            sqlx::Error::RowNotFound => Error::NotFound("Row not found.".to_string()),
+            sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+                // The message names the table and column, which clients need not see.
+                log::info!("Unique violation: {}", db_err.message());
+                Error::Conflict("A resource with the same unique value already exists.".to_string())
+            }
+            _ => Error::Internal(e),
+        }
+    }
+}
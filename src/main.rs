@@ -1,34 +1,32 @@
-mod database;
-mod model;
-mod web;
-use database::{create_and_connect, DbAddress};
+use clap::Parser;
 use log::{info, warn};
 use std::sync::Arc;
-use web::serve;
+use taskapp::config::{Cli, LogFormat};
+use taskapp::{create_and_connect_with, Config, Error, ServerBuilder};
 use tracing_subscriber::EnvFilter;
 
-#[derive(thiserror::Error, Debug)]
-pub enum Error {
-    #[error(transparent)]
-    SqlxError(#[from] sqlx::Error),
-    #[error("Server failed to start. Root directory {0} does not exist.")]
-    RootNotFound(String),
-}
-
-const PORT: u16 = 8080;
-const ROOT_DIR: &str = "frontend/dist";
-
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    let formatter = tracing_subscriber::fmt().pretty().with_env_filter(EnvFilter::from_default_env()).finish();
-    match tracing::subscriber::set_global_default(formatter)
+    let cli = Cli::parse();
+    let config = Config::load(&cli, std::env::vars())?;
+    if cli.print_config {
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
+
+    let builder = tracing_subscriber::fmt().with_env_filter(EnvFilter::from_default_env());
+    let initialized = match config.log_format {
+        LogFormat::Pretty => tracing::subscriber::set_global_default(builder.pretty().finish()),
+        LogFormat::Compact => tracing::subscriber::set_global_default(builder.compact().finish()),
+        LogFormat::Json => tracing::subscriber::set_global_default(builder.json().finish()),
+    };
+    match initialized
     {
         Ok(_) => info!("Tracing initialized."),
         Err(reason) => warn!("Failed to initialize tracing: {}", reason),
     };
 
 
-    let db = create_and_connect(DbAddress::Memory).await?;
-    serve(ROOT_DIR, PORT, Arc::new(db)).await?;
-    Ok(())
+    let db = Arc::new(create_and_connect_with(config.db_address(), config.pool()).await?);
+    ServerBuilder::from_config(db, &config).run().await
 }
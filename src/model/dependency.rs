@@ -0,0 +1,283 @@
+//! Dependencies between tasks. An edge "A blocks B" means B cannot proceed until A is
+//! closed. The dependency graph is kept acyclic.
+
+use super::events::EventMac;
+use super::history::HistoryMac;
+use super::tag::TagMac;
+use super::task::{task_columns, Task, TaskMac};
+use crate::database::Database;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::SqliteConnection;
+
+/// The tasks a task depends on, and the tasks depending on it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Dependencies {
+    /// Tasks blocking this task.
+    pub blockers: Vec<Task>,
+    /// Tasks blocked by this task.
+    pub dependents: Vec<Task>,
+}
+
+impl TaskMac {
+    const ADD_DEPENDENCY_SQL: &'static str =
+        "INSERT OR IGNORE INTO task_dependencies (blocker_id, blocked_id) VALUES (?, ?)";
+    const REMOVE_DEPENDENCY_SQL: &'static str =
+        "DELETE FROM task_dependencies WHERE blocker_id = ? AND blocked_id = ?";
+    const BLOCKER_IDS_SQL: &'static str =
+        "SELECT blocker_id FROM task_dependencies WHERE blocked_id = ? ORDER BY blocker_id";
+    /// Whether the first task (transitively) blocks the second.
+    const BLOCKS_SQL: &'static str = r#"WITH RECURSIVE downstream(id) AS (
+            SELECT blocked_id FROM task_dependencies WHERE blocker_id = ?
+            UNION
+            SELECT d.blocked_id FROM task_dependencies d JOIN downstream s ON d.blocker_id = s.id
+        )
+        SELECT COUNT(*) FROM downstream WHERE id = ?"#;
+    const BLOCKERS_SQL: &'static str = concat!(
+        "SELECT ",
+        task_columns!(),
+        " FROM tasks WHERE deleted_at IS NULL AND id IN (SELECT blocker_id FROM task_dependencies WHERE blocked_id = ?) ORDER BY id"
+    );
+    const DEPENDENTS_SQL: &'static str = concat!(
+        "SELECT ",
+        task_columns!(),
+        " FROM tasks WHERE deleted_at IS NULL AND id IN (SELECT blocked_id FROM task_dependencies WHERE blocker_id = ?) ORDER BY id"
+    );
+
+    /// Record that `blocker_id` blocks `blocked_id`. Fails with a conflict if the edge
+    /// would create a cycle.
+    pub async fn add_dependency(
+        db: &Database,
+        blocker_id: i64,
+        blocked_id: i64,
+        client: Option<&str>,
+    ) -> Result<Task, crate::Error> {
+        if blocker_id == blocked_id {
+            return Err(crate::Error::Validation(
+                "A task cannot block itself.".to_string(),
+            ));
+        }
+        TaskMac::get(db, blocker_id).await?;
+        TaskMac::get(db, blocked_id).await?;
+
+        let mut tx = db.begin().await?;
+        let before = Self::blocker_ids(&mut tx, blocked_id).await?;
+        let creates_cycle: i64 = sqlx::query_scalar(Self::BLOCKS_SQL)
+            .bind(blocked_id)
+            .bind(blocker_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        if creates_cycle > 0 {
+            return Err(crate::Error::Conflict(format!(
+                "Task {} already depends on task {}; the dependency would create a cycle.",
+                blocker_id, blocked_id
+            )));
+        }
+        sqlx::query(Self::ADD_DEPENDENCY_SQL)
+            .bind(blocker_id)
+            .bind(blocked_id)
+            .execute(&mut *tx)
+            .await?;
+        Self::record_blockers(&mut tx, blocked_id, before, client).await?;
+        tx.commit().await?;
+        EventMac::notify(db);
+        TaskMac::get(db, blocked_id).await
+    }
+
+    /// Remove the dependency of `blocked_id` on `blocker_id`. Fails with not found if
+    /// there is no such dependency.
+    pub async fn remove_dependency(
+        db: &Database,
+        blocker_id: i64,
+        blocked_id: i64,
+        client: Option<&str>,
+    ) -> Result<Task, crate::Error> {
+        TaskMac::get(db, blocker_id).await?;
+        let mut tx = db.begin().await?;
+        let before = Self::blocker_ids(&mut tx, blocked_id).await?;
+        let removed = sqlx::query(Self::REMOVE_DEPENDENCY_SQL)
+            .bind(blocker_id)
+            .bind(blocked_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        if removed == 0 {
+            return Err(crate::Error::NotFound(format!(
+                "Task {} does not block task {}.",
+                blocker_id, blocked_id
+            )));
+        }
+        Self::record_blockers(&mut tx, blocked_id, before, client).await?;
+        tx.commit().await?;
+        EventMac::notify(db);
+        TaskMac::get(db, blocked_id).await
+    }
+
+    /// Ids of the tasks blocking a task, including deleted ones.
+    async fn blocker_ids(
+        conn: &mut SqliteConnection,
+        blocked_id: i64,
+    ) -> Result<Vec<i64>, crate::Error> {
+        let ids = sqlx::query_scalar(Self::BLOCKER_IDS_SQL)
+            .bind(blocked_id)
+            .fetch_all(conn)
+            .await?;
+        Ok(ids)
+    }
+
+    /// Record in the history of a task that its blockers changed from `before`.
+    async fn record_blockers(
+        conn: &mut SqliteConnection,
+        blocked_id: i64,
+        before: Vec<i64>,
+        client: Option<&str>,
+    ) -> Result<(), crate::Error> {
+        let after = Self::blocker_ids(conn, blocked_id).await?;
+        HistoryMac::record_relation(
+            conn,
+            blocked_id,
+            "blocked_by",
+            json!(before),
+            json!(after),
+            client,
+        )
+        .await
+    }
+
+    /// Tasks directly blocking a task.
+    pub async fn blockers(db: &Database, id: i64) -> Result<Vec<Task>, crate::Error> {
+        let mut tasks = sqlx::query_as::<_, Task>(Self::BLOCKERS_SQL)
+            .bind(id)
+            .fetch_all(db)
+            .await?;
+        TagMac::load_for(db, &mut tasks).await?;
+        Ok(tasks)
+    }
+
+    /// Tasks directly blocked by a task.
+    pub async fn dependents(db: &Database, id: i64) -> Result<Vec<Task>, crate::Error> {
+        Self::dependents_in(&mut *db.acquire().await?, id).await
+    }
+
+    /// Tasks directly blocked by a task, as part of a transaction.
+    pub(super) async fn dependents_in(
+        conn: &mut SqliteConnection,
+        id: i64,
+    ) -> Result<Vec<Task>, crate::Error> {
+        let mut tasks = sqlx::query_as::<_, Task>(Self::DEPENDENTS_SQL)
+            .bind(id)
+            .fetch_all(&mut *conn)
+            .await?;
+        TagMac::load_for(conn, &mut tasks).await?;
+        Ok(tasks)
+    }
+
+    /// Blockers and dependents of a task.
+    pub async fn dependencies(db: &Database, id: i64) -> Result<Dependencies, crate::Error> {
+        TaskMac::get(db, id).await?;
+        Ok(Dependencies {
+            blockers: Self::blockers(db, id).await?,
+            dependents: Self::dependents(db, id).await?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::database::{create_and_connect, Database, DbAddress};
+    use crate::model::task::{Task, TaskMac, TaskPatch, TaskStatus};
+
+    async fn insert(db: &Database, name: &str) -> Result<Task, crate::Error> {
+        TaskMac::insert(
+            db,
+            TaskPatch {
+                name: Some(name.to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    fn close() -> TaskPatch {
+        TaskPatch {
+            status: Some(TaskStatus::Closed),
+            ..Default::default()
+        }
+    }
+
+    /// Test that edges creating a cycle are rejected.
+    #[tokio::test]
+    async fn test_cycle_detection() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let a = insert(&db, "A").await?;
+        let b = insert(&db, "B").await?;
+        let c = insert(&db, "C").await?;
+        TaskMac::add_dependency(&db, a.id, b.id, None).await?;
+        TaskMac::add_dependency(&db, b.id, c.id, None).await?;
+
+        assert!(matches!(
+            TaskMac::add_dependency(&db, c.id, a.id, None).await,
+            Err(crate::Error::Conflict(_))
+        ));
+        assert!(matches!(
+            TaskMac::add_dependency(&db, b.id, a.id, None).await,
+            Err(crate::Error::Conflict(_))
+        ));
+        assert!(matches!(
+            TaskMac::add_dependency(&db, a.id, a.id, None).await,
+            Err(crate::Error::Validation(_))
+        ));
+        // A shortcut that keeps the graph acyclic is fine.
+        TaskMac::add_dependency(&db, a.id, c.id, None).await?;
+        Ok(())
+    }
+
+    /// Test the derived blocked flag and the blockers/dependents lists.
+    #[tokio::test]
+    async fn test_blocked() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let a = insert(&db, "A").await?;
+        let b = insert(&db, "B").await?;
+
+        let blocked = TaskMac::add_dependency(&db, a.id, b.id, None).await?;
+        assert!(blocked.blocked);
+        assert!(!TaskMac::get(&db, a.id).await?.blocked);
+
+        let dependencies = TaskMac::dependencies(&db, b.id).await?;
+        assert_eq!(dependencies.blockers.len(), 1);
+        assert_eq!(dependencies.blockers[0].id, a.id);
+        assert!(dependencies.dependents.is_empty());
+        assert_eq!(TaskMac::dependents(&db, a.id).await?[0].id, b.id);
+
+        let unblocked = TaskMac::remove_dependency(&db, a.id, b.id, None).await?;
+        assert!(!unblocked.blocked);
+        assert!(matches!(
+            TaskMac::remove_dependency(&db, a.id, b.id, None).await,
+            Err(crate::Error::NotFound(_))
+        ));
+        Ok(())
+    }
+
+    /// Test that closing a blocker reports the tasks it unblocked.
+    #[tokio::test]
+    async fn test_close_reports_unblocked() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let a = insert(&db, "A").await?;
+        let b = insert(&db, "B").await?;
+        let c = insert(&db, "C").await?;
+        let d = insert(&db, "D").await?;
+        // C is blocked by A alone, D by A and B.
+        TaskMac::add_dependency(&db, a.id, c.id, None).await?;
+        TaskMac::add_dependency(&db, a.id, d.id, None).await?;
+        TaskMac::add_dependency(&db, b.id, d.id, None).await?;
+
+        let update = TaskMac::update(&db, a.id, close()).await?;
+        let ids: Vec<i64> = update.unblocked.iter().map(|t| t.id).collect();
+        assert_eq!(ids, vec![c.id]);
+
+        let update = TaskMac::update(&db, b.id, close()).await?;
+        let ids: Vec<i64> = update.unblocked.iter().map(|t| t.id).collect();
+        assert_eq!(ids, vec![d.id]);
+        Ok(())
+    }
+}
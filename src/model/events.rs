@@ -0,0 +1,297 @@
+//! Change events. Every recorded change of a task is an event, identified by the id
+//! of its history entry, so that a client can resume after the last event it saw.
+//! Mutations wake up subscribers once their transaction has committed.
+
+use super::history::{HistoryAction, HistoryEntry};
+use super::tag::TagMac;
+use super::task::{Task, TaskMac, TaskStatus};
+use crate::database::Database;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+/// Kind of a change event.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl EventKind {
+    /// Event name on the wire.
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::Created => "created",
+            EventKind::Updated => "updated",
+            EventKind::Deleted => "deleted",
+        }
+    }
+}
+
+/// A change of a task, as seen by clients.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaskEvent {
+    pub kind: EventKind,
+    #[serde(flatten)]
+    pub entry: HistoryEntry,
+}
+
+impl TaskEvent {
+    /// The event for a history entry. Restoring a task from the trash creates it again
+    /// for clients; purging has no event, since the task was already deleted.
+    fn from_entry(entry: HistoryEntry) -> Option<TaskEvent> {
+        let kind = match entry.action {
+            HistoryAction::Created | HistoryAction::Restored => EventKind::Created,
+            HistoryAction::Updated => EventKind::Updated,
+            HistoryAction::Deleted => EventKind::Deleted,
+            HistoryAction::Purged => return None,
+        };
+        Some(TaskEvent { kind, entry })
+    }
+
+    pub fn id(&self) -> i64 {
+        self.entry.id
+    }
+}
+
+/// Which events a subscriber wants. Unset fields match every event.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+pub struct EventFilter {
+    /// Status of the task, before or after the change.
+    pub status: Option<TaskStatus>,
+    /// Tag names. The task must have any of them, before or after the change.
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl EventFilter {
+    /// Whether an event concerns a task that matches the filter. A task that changes
+    /// status or tags matches both the old and the new ones, so that subscribers learn
+    /// about tasks leaving their selection.
+    ///
+    /// What the event does not change is compared with the current state of the task,
+    /// not its state at the time of the event. The two differ when events are read
+    /// after the task changed again, e.g. when a client replays missed events. A task
+    /// that is no longer live matches no status it did not change to or from.
+    pub async fn matches(&self, db: &Database, event: &TaskEvent) -> Result<bool, crate::Error> {
+        if let Some(status) = &self.status {
+            let status = serde_json::to_value(status).unwrap_or_default();
+            let changed = changed_values(event, "status");
+            let matched = if changed.is_empty() {
+                // The status did not change, so the current one applies.
+                match TaskMac::get(db, event.entry.task_id).await {
+                    Ok(task) => serde_json::to_value(task.status).unwrap_or_default() == status,
+                    Err(crate::Error::NotFound(_)) => false,
+                    Err(e) => return Err(e),
+                }
+            } else {
+                changed.contains(&&status)
+            };
+            if !matched {
+                return Ok(false);
+            }
+        }
+        if !self.tags.is_empty() {
+            let changed = changed_values(event, "tags");
+            let names: Vec<String> = if changed.is_empty() {
+                // The tags did not change, so the current ones apply.
+                let mut task = Task {
+                    id: event.entry.task_id,
+                    ..Default::default()
+                };
+                TagMac::load_for(db, std::slice::from_mut(&mut task)).await?;
+                task.tags.into_iter().map(|tag| tag.name).collect()
+            } else {
+                changed
+                    .into_iter()
+                    .filter_map(serde_json::Value::as_array)
+                    .flatten()
+                    .filter_map(serde_json::Value::as_str)
+                    .map(str::to_string)
+                    .collect()
+            };
+            let tagged = names.iter().any(|tag| {
+                self.tags
+                    .iter()
+                    .any(|name| name.trim().eq_ignore_ascii_case(tag))
+            });
+            if !tagged {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Values of a field before and after an event, if the event changed it.
+fn changed_values<'a>(event: &'a TaskEvent, field: &str) -> Vec<&'a serde_json::Value> {
+    [&event.entry.old_values, &event.entry.new_values]
+        .into_iter()
+        .flatten()
+        .filter_map(|values| values.get(field))
+        .collect()
+}
+
+/// Event model access controller.
+pub struct EventMac;
+
+impl EventMac {
+    const SINCE_SQL: &'static str = r#"SELECT
+        id, task_id, action, old_values, new_values, changed_at, client
+        FROM task_history WHERE id > ? AND action != 'purged' ORDER BY id LIMIT ?"#;
+    const LATEST_SQL: &'static str = "SELECT COALESCE(MAX(id), 0) FROM task_history";
+
+    /// Up to `limit` events after the event with id `after`, oldest first.
+    pub async fn since(
+        db: &Database,
+        after: i64,
+        limit: usize,
+    ) -> Result<Vec<TaskEvent>, crate::Error> {
+        let entries = sqlx::query_as::<_, HistoryEntry>(Self::SINCE_SQL)
+            .bind(after)
+            .bind(limit as i64)
+            .fetch_all(db)
+            .await?;
+        Ok(entries
+            .into_iter()
+            .filter_map(TaskEvent::from_entry)
+            .collect())
+    }
+
+    /// Id of the latest event, or 0 if nothing happened yet.
+    pub async fn latest_id(db: &Database) -> Result<i64, crate::Error> {
+        let id = sqlx::query_scalar(Self::LATEST_SQL).fetch_one(db).await?;
+        Ok(id)
+    }
+
+    /// Subscribe to wake-ups after changes to the database. Subscribe before reading
+    /// the events already recorded, so that no change is missed in between.
+    pub fn subscribe(db: &Database) -> watch::Receiver<()> {
+        db.changes().subscribe()
+    }
+
+    /// Wake up subscribers. Called by mutations after their transaction committed.
+    pub(super) fn notify(db: &Database) {
+        db.changes().send_replace(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use crate::model::tag::TagPatch;
+    use crate::model::task::TaskPatch;
+
+    /// Test that changes become events in order, and wake up subscribers.
+    #[tokio::test]
+    async fn test_events() -> Result<(), crate::Error> {
+        // # Fixture
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let mut changes = EventMac::subscribe(&db);
+        let start = EventMac::latest_id(&db).await?;
+
+        // # Action
+        let task = TaskMac::insert(
+            &db,
+            TaskPatch {
+                name: Some("Watch me".to_string()),
+                ..Default::default()
+            },
+        )
+        .await?;
+        TaskMac::delete(&db, task.id).await?;
+        TaskMac::purge(&db, task.id, None).await?;
+
+        // # Check
+        assert!(changes.has_changed().unwrap());
+        changes.mark_unchanged();
+        let events = EventMac::since(&db, start, 100).await?;
+        let kinds: Vec<EventKind> = events.iter().map(|e| e.kind).collect();
+        assert_eq!(kinds, vec![EventKind::Created, EventKind::Deleted]);
+        assert_eq!(EventMac::since(&db, events[0].id(), 100).await?.len(), 1);
+        Ok(())
+    }
+
+    /// Test that changes only wake up the subscribers of their own database.
+    #[tokio::test]
+    async fn test_subscribers_per_database() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let other = create_and_connect(DbAddress::Memory).await?;
+        let changes = EventMac::subscribe(&db);
+        let other_changes = EventMac::subscribe(&other);
+
+        TaskMac::insert(
+            &db,
+            TaskPatch {
+                name: Some("Mine".to_string()),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        assert!(changes.has_changed().unwrap());
+        assert!(!other_changes.has_changed().unwrap());
+        Ok(())
+    }
+
+    /// Test matching events by the status of their task.
+    #[tokio::test]
+    async fn test_filter_status() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let task = TaskMac::insert(
+            &db,
+            TaskPatch {
+                name: Some("Ship it".to_string()),
+                ..Default::default()
+            },
+        )
+        .await?;
+        let close = TaskPatch {
+            status: Some(TaskStatus::Closed),
+            ..Default::default()
+        };
+        TaskMac::update(&db, task.id, close).await?;
+        let rename = TaskPatch {
+            name: Some("Shipped".to_string()),
+            ..Default::default()
+        };
+        TaskMac::update(&db, task.id, rename).await?;
+
+        let open = EventFilter {
+            status: Some(TaskStatus::Open),
+            ..Default::default()
+        };
+        let mut matched = Vec::new();
+        for event in EventMac::since(&db, 0, 100).await? {
+            matched.push(open.matches(&db, &event).await?);
+        }
+        // Created open, closed, then renamed while closed.
+        assert_eq!(matched, vec![true, true, false]);
+
+        let tagged = EventFilter {
+            tags: vec!["backend".to_string()],
+            ..Default::default()
+        };
+        let event = &EventMac::since(&db, 0, 1).await?[0];
+        assert!(!tagged.matches(&db, event).await?);
+
+        // Tag changes match by the tags before and after them.
+        let backend = TagMac::insert(
+            &db,
+            TagPatch {
+                name: "backend".to_string(),
+            },
+        )
+        .await?;
+        TagMac::attach(&db, task.id, backend.id, None).await?;
+        TagMac::detach(&db, task.id, backend.id, None).await?;
+        let mut matched = Vec::new();
+        for event in EventMac::since(&db, 0, 100).await? {
+            matched.push(tagged.matches(&db, &event).await?);
+        }
+        assert_eq!(matched, vec![false, false, false, true, true]);
+        Ok(())
+    }
+}
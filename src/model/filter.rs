@@ -0,0 +1,796 @@
+//! A small filter language for tasks, e.g.
+//! `status:open and (tag:backend or due<7d) and not name~"wip"`.
+//!
+//! ```text
+//! expr       := and ("or" and)*
+//! and        := unary (["and"] unary)*
+//! unary      := "not" unary | "(" expr ")" | comparison
+//! comparison := field (":" | "~" | "<" | "<=" | ">" | ">=") value
+//! value      := word | "quoted string"
+//! ```
+//!
+//! Keywords are case-insensitive and adjacent conditions are joined with `and`.
+//! Parentheses and `not` nest at most 64 deep.
+//!
+//! | Field                      | Operators          | Values                              |
+//! |----------------------------|--------------------|-------------------------------------|
+//! | `status`                   | `:`                | `open`, `closed`                    |
+//! | `name`                     | `:` (equals), `~` (contains) | text, ignoring case       |
+//! | `tag`                      | `:`                | tag name                            |
+//! | `priority`                 | `:` `<` `<=` `>` `>=` | `low`, `normal`, `high`, `urgent` |
+//! | `due`, `start`, `created`  | `:` `<` `<=` `>` `>=` | `YYYY-MM-DD` (UTC) or an offset from now such as `7d`, `-2w`, `12h`; `:none` for a missing date |
+//! | `blocked`                  | `:`                | `true`, `false`                     |
+//! | `parent`                   | `:`                | task id or `none`                   |
+//!
+//! A date stands for the whole day: `due:2024-03-04` matches any time on that day,
+//! `due>2024-03-04` starts the day after and `due<=2024-03-04` includes it. Offsets
+//! reach at most 100 years either way.
+//!
+//! Expressions are parsed into an [`Expr`], validated, and compiled into parameterized
+//! SQL. Errors carry the 1-based column they were found at.
+
+use super::task::{blocked_condition, TaskPriority, TaskStatus};
+use chrono::{Duration, NaiveDate};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{QueryBuilder, Sqlite};
+use std::str::FromStr;
+
+/// Largest offset from now, in days, so that resolving it stays within the calendar.
+const MAX_OFFSET_DAYS: i64 = 100 * 366;
+
+/// Deepest nesting of parentheses and `not`, so that parsing cannot overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+/// A task attribute that can be filtered on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Status,
+    Name,
+    Tag,
+    Priority,
+    Due,
+    Start,
+    Created,
+    Blocked,
+    Parent,
+}
+
+impl Field {
+    fn parse(word: &str) -> Option<Field> {
+        Some(match word.to_ascii_lowercase().as_str() {
+            "status" => Field::Status,
+            "name" => Field::Name,
+            "tag" => Field::Tag,
+            "priority" => Field::Priority,
+            "due" => Field::Due,
+            "start" => Field::Start,
+            "created" => Field::Created,
+            "blocked" => Field::Blocked,
+            "parent" => Field::Parent,
+            _ => return None,
+        })
+    }
+
+    /// Operators that apply to the field.
+    fn operators(&self) -> &'static [Op] {
+        match self {
+            Field::Name => &[Op::Is, Op::Contains],
+            Field::Priority | Field::Due | Field::Start | Field::Created => {
+                &[Op::Is, Op::Lt, Op::Le, Op::Gt, Op::Ge]
+            }
+            Field::Status | Field::Tag | Field::Blocked | Field::Parent => &[Op::Is],
+        }
+    }
+
+    /// Column holding a date field.
+    fn date_column(&self) -> &'static str {
+        match self {
+            Field::Due => "tasks.due_at",
+            Field::Start => "tasks.start_at",
+            _ => "tasks.creation_time",
+        }
+    }
+}
+
+/// A comparison operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// `:`
+    Is,
+    /// `~`
+    Contains,
+    /// `<`
+    Lt,
+    /// `<=`
+    Le,
+    /// `>`
+    Gt,
+    /// `>=`
+    Ge,
+}
+
+impl Op {
+    fn symbol(&self) -> &'static str {
+        match self {
+            Op::Is => ":",
+            Op::Contains => "~",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+        }
+    }
+
+    /// SQL operator of an ordering comparison.
+    fn sql(&self) -> &'static str {
+        match self {
+            Op::Lt => " < ",
+            Op::Le => " <= ",
+            Op::Gt => " > ",
+            Op::Ge => " >= ",
+            Op::Is | Op::Contains => " = ",
+        }
+    }
+}
+
+/// A point in time, absolute or relative to the time the filter runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Time {
+    /// Midnight UTC of a day.
+    Date(NaiveDate),
+    /// An offset from now.
+    FromNow(Duration),
+}
+
+impl Time {
+    fn resolve(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Time::Date(date) => date.and_hms_opt(0, 0, 0).unwrap().and_utc(),
+            Time::FromNow(offset) => now + *offset,
+        }
+    }
+}
+
+/// A validated comparison value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Status(TaskStatus),
+    Text(String),
+    Priority(TaskPriority),
+    Time(Time),
+    Bool(bool),
+    Id(i64),
+    None,
+}
+
+/// A parsed filter expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare { field: Field, op: Op, value: Value },
+}
+
+fn error(message: impl Into<String>, column: usize) -> crate::Error {
+    crate::Error::Filter {
+        message: message.into(),
+        column,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Op(Op),
+    Open,
+    Close,
+}
+
+impl Token {
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, Token::Word(word) if word.eq_ignore_ascii_case(keyword))
+    }
+}
+
+/// Split an expression into tokens, each with its 1-based column.
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, crate::Error> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let column = i + 1;
+        let c = chars[i];
+        let token = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => Token::Open,
+            ')' => Token::Close,
+            ':' => Token::Op(Op::Is),
+            '~' => Token::Op(Op::Contains),
+            '<' | '>' => {
+                let or_equal = chars.get(i + 1) == Some(&'=');
+                if or_equal {
+                    i += 1;
+                }
+                Token::Op(match (c, or_equal) {
+                    ('<', false) => Op::Lt,
+                    ('<', true) => Op::Le,
+                    (_, false) => Op::Gt,
+                    (_, true) => Op::Ge,
+                })
+            }
+            '"' => {
+                let mut text = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(error("Unterminated string.", column)),
+                        Some('"') => break,
+                        Some('\\') if matches!(chars.get(i + 1), Some('"' | '\\')) => {
+                            text.push(chars[i + 1]);
+                            i += 2;
+                        }
+                        Some(c) => {
+                            text.push(*c);
+                            i += 1;
+                        }
+                    }
+                }
+                Token::Quoted(text)
+            }
+            c if c.is_alphanumeric() || matches!(c, '_' | '-' | '+' | '.') => {
+                let start = i;
+                while chars
+                    .get(i + 1)
+                    .is_some_and(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '+' | '.'))
+                {
+                    i += 1;
+                }
+                Token::Word(chars[start..=i].iter().collect())
+            }
+            c => return Err(error(format!("Unexpected character {:?}.", c), column)),
+        };
+        tokens.push((token, column));
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser over the tokens of an expression.
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    /// Column just past the end of the source, for errors at the end.
+    end: usize,
+    /// Number of enclosing parentheses and `not`s.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .map_or(self.end, |(_, column)| *column)
+    }
+
+    fn next(&mut self) -> Option<(Token, usize)> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expression(&mut self) -> Result<Expr, crate::Error> {
+        let mut left = self.conjunction()?;
+        while self.peek().is_some_and(|t| t.is_keyword("or")) {
+            self.next();
+            let right = self.conjunction()?;
+            left = Expr::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn conjunction(&mut self) -> Result<Expr, crate::Error> {
+        let mut left = self.unary()?;
+        loop {
+            match self.peek() {
+                Some(t) if t.is_keyword("and") => {
+                    self.next();
+                }
+                Some(t) if t.is_keyword("or") => break,
+                Some(Token::Word(_) | Token::Open) => {}
+                _ => break,
+            }
+            let right = self.unary()?;
+            left = Expr::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, crate::Error> {
+        let nested = self
+            .peek()
+            .is_some_and(|t| t.is_keyword("not") || *t == Token::Open);
+        if !nested {
+            return self.comparison();
+        }
+        if self.depth == MAX_DEPTH {
+            return Err(error("Filter is nested too deeply.", self.column()));
+        }
+        self.depth += 1;
+        let expr = self.nested();
+        self.depth -= 1;
+        expr
+    }
+
+    /// A negation, or an expression in parentheses.
+    fn nested(&mut self) -> Result<Expr, crate::Error> {
+        if self.peek().is_some_and(|t| t.is_keyword("not")) {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        let (_, open) = self.next().unwrap();
+        let inner = self.expression()?;
+        let column = self.column();
+        match self.next() {
+            Some((Token::Close, _)) => Ok(inner),
+            _ => Err(error(
+                format!("Expected ')' to close the '(' at column {}.", open),
+                column,
+            )),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, crate::Error> {
+        let column = self.column();
+        let field = match self.next() {
+            Some((Token::Word(word), _))
+                if !["and", "or", "not"]
+                    .iter()
+                    .any(|k| word.eq_ignore_ascii_case(k)) =>
+            {
+                Field::parse(&word)
+                    .ok_or_else(|| error(format!("Unknown field {:?}.", word), column))?
+            }
+            Some(_) => return Err(error("Expected a condition such as status:open.", column)),
+            None => return Err(error("Unexpected end of filter.", column)),
+        };
+
+        let column = self.column();
+        let op = match self.next() {
+            Some((Token::Op(op), _)) => op,
+            _ => {
+                return Err(error(
+                    "Expected an operator (:, ~, <, <=, >, >=) after the field.",
+                    column,
+                ))
+            }
+        };
+        if !field.operators().contains(&op) {
+            let allowed: Vec<&str> = field.operators().iter().map(Op::symbol).collect();
+            return Err(error(
+                format!(
+                    "Operator {} does not apply to this field; use {}.",
+                    op.symbol(),
+                    allowed.join(" ")
+                ),
+                column,
+            ));
+        }
+
+        let column = self.column();
+        let text = match self.next() {
+            Some((Token::Word(text) | Token::Quoted(text), _)) => text,
+            _ => return Err(error("Expected a value.", column)),
+        };
+        let value = validate(field, op, &text).map_err(|message| error(message, column))?;
+        Ok(Expr::Compare { field, op, value })
+    }
+}
+
+/// Check a value against its field and operator.
+fn validate(field: Field, op: Op, text: &str) -> Result<Value, String> {
+    let lower = text.to_lowercase();
+    match field {
+        Field::Status => match lower.as_str() {
+            "open" => Ok(Value::Status(TaskStatus::Open)),
+            "closed" => Ok(Value::Status(TaskStatus::Closed)),
+            _ => Err(format!("Unknown status {:?}; use open or closed.", text)),
+        },
+        Field::Name | Field::Tag => Ok(Value::Text(text.to_string())),
+        Field::Priority => match lower.as_str() {
+            "low" => Ok(Value::Priority(TaskPriority::Low)),
+            "normal" => Ok(Value::Priority(TaskPriority::Normal)),
+            "high" => Ok(Value::Priority(TaskPriority::High)),
+            "urgent" => Ok(Value::Priority(TaskPriority::Urgent)),
+            _ => Err(format!(
+                "Unknown priority {:?}; use low, normal, high or urgent.",
+                text
+            )),
+        },
+        Field::Due | Field::Start | Field::Created => {
+            if lower == "none" {
+                return match (field, op) {
+                    (Field::Created, _) => Err("Every task has a creation time.".to_string()),
+                    (_, Op::Is) => Ok(Value::None),
+                    _ => Err("Only ':' applies to none.".to_string()),
+                };
+            }
+            let time = parse_time(&lower).ok_or_else(|| {
+                format!(
+                    "Invalid time {:?}; use YYYY-MM-DD or an offset such as 7d, -2w or 12h.",
+                    text
+                )
+            })?;
+            match time {
+                Time::FromNow(offset) if offset.num_days().abs() > MAX_OFFSET_DAYS => Err(format!(
+                    "Offset {:?} is out of range; use at most 100 years.",
+                    text
+                )),
+                _ => Ok(Value::Time(time)),
+            }
+        }
+        Field::Blocked => match lower.as_str() {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            _ => Err(format!("Invalid flag {:?}; use true or false.", text)),
+        },
+        Field::Parent => match lower.as_str() {
+            "none" => Ok(Value::None),
+            _ => text
+                .parse()
+                .map(Value::Id)
+                .map_err(|_| format!("Invalid task id {:?}.", text)),
+        },
+    }
+}
+
+/// Parse a date or an offset from now.
+fn parse_time(text: &str) -> Option<Time> {
+    if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        return Some(Time::Date(date));
+    }
+    let unit = text.chars().last()?;
+    let amount: i64 = text[..text.len() - unit.len_utf8()].parse().ok()?;
+    let offset = match unit {
+        'h' => Duration::try_hours(amount),
+        'd' => Duration::try_days(amount),
+        'w' => Duration::try_weeks(amount),
+        _ => None,
+    }?;
+    Some(Time::FromNow(offset))
+}
+
+impl FromStr for Expr {
+    type Err = crate::Error;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+            end: source.chars().count() + 1,
+            depth: 0,
+        };
+        if parser.peek().is_none() {
+            return Err(error("The filter is empty.", 1));
+        }
+        let expr = parser.expression()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(Token::Close) => Err(error("Unbalanced ')'.", parser.column())),
+            Some(_) => Err(error(
+                "Expected and, or or the end of the filter.",
+                parser.column(),
+            )),
+        }
+    }
+}
+
+impl Expr {
+    /// Append the expression as a parenthesized SQL condition on `tasks`. Offsets are
+    /// taken from `now`.
+    pub fn push_sql(&self, query: &mut QueryBuilder<'_, Sqlite>, now: DateTime<Utc>) {
+        query.push("(");
+        match self {
+            Expr::And(left, right) | Expr::Or(left, right) => {
+                left.push_sql(query, now);
+                query.push(if matches!(self, Expr::And(..)) {
+                    " AND "
+                } else {
+                    " OR "
+                });
+                right.push_sql(query, now);
+            }
+            Expr::Not(inner) => {
+                query.push("NOT ");
+                inner.push_sql(query, now);
+            }
+            Expr::Compare { field, op, value } => push_comparison(query, *field, *op, value, now),
+        }
+        query.push(")");
+    }
+}
+
+/// Append a single comparison. Missing dates never compare, so that `not` inverts
+/// conditions exactly.
+fn push_comparison(
+    query: &mut QueryBuilder<'_, Sqlite>,
+    field: Field,
+    op: Op,
+    value: &Value,
+    now: DateTime<Utc>,
+) {
+    match (field, value) {
+        (Field::Status, Value::Status(status)) => {
+            query.push("tasks.status = ").push_bind(status.clone());
+        }
+        (Field::Name, Value::Text(text)) if op == Op::Contains => {
+            query
+                .push("instr(lower(tasks.name), lower(")
+                .push_bind(text.clone())
+                .push(")) > 0");
+        }
+        (Field::Name, Value::Text(text)) => {
+            query
+                .push("tasks.name = ")
+                .push_bind(text.clone())
+                .push(" COLLATE NOCASE");
+        }
+        (Field::Tag, Value::Text(name)) => {
+            query
+                .push(
+                    "EXISTS (SELECT 1 FROM task_tags tt JOIN tags g ON g.id = tt.tag_id \
+                     WHERE tt.task_id = tasks.id AND g.name = ",
+                )
+                .push_bind(name.clone())
+                .push(")");
+        }
+        (Field::Priority, Value::Priority(priority)) => {
+            query
+                .push("tasks.priority")
+                .push(op.sql())
+                .push_bind(*priority as i64);
+        }
+        (Field::Due | Field::Start, Value::None) => {
+            query.push(field.date_column()).push(" IS NULL");
+        }
+        (Field::Due | Field::Start | Field::Created, Value::Time(time)) => {
+            let column = field.date_column();
+            let at = time.resolve(now).timestamp();
+            query.push(column).push(" IS NOT NULL AND ");
+            match (op, time) {
+                // A day matches every time within it, and orders as a whole.
+                (Op::Is, Time::Date(_)) => {
+                    query
+                        .push(column)
+                        .push(" >= ")
+                        .push_bind(at)
+                        .push(" AND ")
+                        .push(column)
+                        .push(" < ")
+                        .push_bind(at + 24 * 60 * 60);
+                }
+                (Op::Gt, Time::Date(_)) => {
+                    query.push(column).push(" >= ").push_bind(at + 24 * 60 * 60);
+                }
+                (Op::Le, Time::Date(_)) => {
+                    query.push(column).push(" < ").push_bind(at + 24 * 60 * 60);
+                }
+                _ => {
+                    query.push(column).push(op.sql()).push_bind(at);
+                }
+            }
+        }
+        (Field::Blocked, Value::Bool(blocked)) => {
+            if !blocked {
+                query.push("NOT ");
+            }
+            query.push(blocked_condition!());
+        }
+        (Field::Parent, Value::None) => {
+            query.push("tasks.parent_id IS NULL");
+        }
+        (Field::Parent, Value::Id(id)) => {
+            query.push("tasks.parent_id = ").push_bind(*id);
+        }
+        _ => unreachable!("values are validated against their field"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use crate::model::listing::TaskQuery;
+    use crate::model::tag::{TagMac, TagPatch};
+    use crate::model::task::{TaskMac, TaskPatch};
+
+    fn column(source: &str) -> usize {
+        match source.parse::<Expr>() {
+            Err(crate::Error::Filter { column, .. }) => column,
+            other => panic!("{:?} parsed as {:?}", source, other),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let expr: Expr = r#"status:open and (tag:backend or due<7d) and not name~"wip""#
+            .parse()
+            .unwrap();
+        let compare = |field, op, value| Expr::Compare { field, op, value };
+        let expected = Expr::And(
+            Box::new(Expr::And(
+                Box::new(compare(
+                    Field::Status,
+                    Op::Is,
+                    Value::Status(TaskStatus::Open),
+                )),
+                Box::new(Expr::Or(
+                    Box::new(compare(Field::Tag, Op::Is, Value::Text("backend".into()))),
+                    Box::new(compare(
+                        Field::Due,
+                        Op::Lt,
+                        Value::Time(Time::FromNow(Duration::days(7))),
+                    )),
+                )),
+            )),
+            Box::new(Expr::Not(Box::new(compare(
+                Field::Name,
+                Op::Contains,
+                Value::Text("wip".into()),
+            )))),
+        );
+        assert_eq!(expr, expected);
+
+        // `and` binds tighter than `or`, and may be left out.
+        let implicit: Expr = "TAG:a tag:b OR priority>=high".parse().unwrap();
+        let explicit: Expr = "(tag:a and tag:b) or priority>=high".parse().unwrap();
+        assert_eq!(implicit, explicit);
+    }
+
+    #[test]
+    fn test_error_columns() {
+        assert_eq!(column(""), 1);
+        assert_eq!(column("colour:red"), 1);
+        assert_eq!(column("status:pending"), 8);
+        assert_eq!(column("status<open"), 7);
+        assert_eq!(column("due<soon"), 5);
+        assert_eq!(column("status:open and"), 16);
+        assert_eq!(column("(tag:a or tag:b"), 16);
+        assert_eq!(column("tag:a)"), 6);
+        assert_eq!(column(r#"name~"wip"#), 6);
+        assert_eq!(column("name~wip & tag:a"), 10);
+        assert_eq!(column("created:none"), 9);
+        assert_eq!(column("due<9223372036854775807h"), 5);
+        assert_eq!(column("due>-36700d"), 5);
+        assert_eq!(column(&"(".repeat(100_000)), 65);
+        assert_eq!(column(&format!("{}tag:a", "not ".repeat(65))), 257);
+        let deepest = format!("{}tag:a", "not ".repeat(64));
+        assert!(deepest.parse::<Expr>().is_ok());
+    }
+
+    /// Test that compiled filters select the expected tasks.
+    #[tokio::test]
+    async fn test_compile() -> Result<(), crate::Error> {
+        // # Fixture
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let now = Utc::now();
+        let fixtures = [
+            (
+                "Deploy backend",
+                TaskStatus::Open,
+                Some(now + Duration::days(2)),
+            ),
+            ("WIP: refactor backend", TaskStatus::Open, None),
+            (
+                "Write docs",
+                TaskStatus::Open,
+                Some(now + Duration::days(30)),
+            ),
+            ("Release", TaskStatus::Closed, Some(now + Duration::days(1))),
+        ];
+        for (name, status, due_at) in fixtures {
+            TaskMac::insert(
+                &db,
+                TaskPatch {
+                    name: Some(name.to_string()),
+                    status: Some(status),
+                    due_at: Some(due_at),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        }
+        let backend = TagMac::insert(
+            &db,
+            TagPatch {
+                name: "backend".to_string(),
+            },
+        )
+        .await?;
+        TagMac::attach(&db, 1, backend.id, None).await?;
+        TagMac::attach(&db, 2, backend.id, None).await?;
+
+        let names = |filter: &'static str| {
+            let db = &db;
+            async move {
+                let query = TaskQuery {
+                    filter: Some(filter.parse()?),
+                    ..Default::default()
+                };
+                let page = TaskMac::list_page(db, &query).await?;
+                Ok::<_, crate::Error>(page.items.into_iter().map(|t| t.name).collect::<Vec<_>>())
+            }
+        };
+
+        // # Check
+        assert_eq!(
+            names(r#"status:open and (tag:backend or due<7d) and not name~"wip""#).await?,
+            vec!["Deploy backend"]
+        );
+        assert_eq!(names("due:none").await?, vec!["WIP: refactor backend"]);
+        assert_eq!(
+            names("not due<7d").await?,
+            vec!["WIP: refactor backend", "Write docs"]
+        );
+        assert_eq!(names("name:release").await?, vec!["Release"]);
+        assert_eq!(names("created>-1h blocked:false").await?.len(), 4);
+        Ok(())
+    }
+
+    /// Test that dates compare as whole days, whatever the operator.
+    #[tokio::test]
+    async fn test_compare_days() -> Result<(), crate::Error> {
+        // # Fixture
+        let db = create_and_connect(DbAddress::Memory).await?;
+        for (name, due_at) in [
+            ("Before", "2024-03-03T23:00:00Z"),
+            ("Morning", "2024-03-04T00:00:00Z"),
+            ("Evening", "2024-03-04T18:30:00Z"),
+            ("After", "2024-03-05T00:00:00Z"),
+        ] {
+            TaskMac::insert(
+                &db,
+                TaskPatch {
+                    name: Some(name.to_string()),
+                    due_at: Some(Some(due_at.parse().unwrap())),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        }
+
+        let names = |filter: &'static str| {
+            let db = &db;
+            async move {
+                let query = TaskQuery {
+                    filter: Some(filter.parse()?),
+                    ..Default::default()
+                };
+                let page = TaskMac::list_page(db, &query).await?;
+                Ok::<_, crate::Error>(page.items.into_iter().map(|t| t.name).collect::<Vec<_>>())
+            }
+        };
+
+        // # Check
+        assert_eq!(names("due:2024-03-04").await?, vec!["Morning", "Evening"]);
+        assert_eq!(names("due>2024-03-04").await?, vec!["After"]);
+        assert_eq!(
+            names("due>=2024-03-04").await?,
+            vec!["Morning", "Evening", "After"]
+        );
+        assert_eq!(
+            names("due<=2024-03-04").await?,
+            vec!["Before", "Morning", "Evening"]
+        );
+        assert_eq!(names("due<2024-03-04").await?, vec!["Before"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,373 @@
+//! Subtasks. A task may have a parent task, forming a tree of arbitrary depth.
+
+use super::events::EventMac;
+use super::history::HistoryMac;
+use super::tag::TagMac;
+use super::trash::next_trash_batch;
+use super::task::{not_found, task_columns, Task, TaskMac, TaskStatus};
+use crate::database::Database;
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+use std::collections::HashMap;
+
+/// What happens to the subtasks of a deleted task.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChildPolicy {
+    /// Refuse to delete a task that has subtasks.
+    #[default]
+    Refuse,
+    /// Delete the whole subtree.
+    Cascade,
+    /// Turn the direct subtasks into top-level tasks.
+    Orphan,
+}
+
+/// Number of closed tasks out of all descendants of a task.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct Progress {
+    pub closed: usize,
+    pub total: usize,
+}
+
+/// A task with its nested subtasks.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaskTree {
+    #[serde(flatten)]
+    pub task: Task,
+    pub progress: Progress,
+    pub children: Vec<TaskTree>,
+}
+
+impl TaskTree {
+    /// Assemble the tree rooted at `root` from the tasks of its subtree.
+    fn build(root: Task, children_of: &mut HashMap<i64, Vec<Task>>) -> TaskTree {
+        let children: Vec<TaskTree> = children_of
+            .remove(&root.id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|child| TaskTree::build(child, children_of))
+            .collect();
+        let mut progress = Progress::default();
+        for child in &children {
+            progress.total += 1 + child.progress.total;
+            progress.closed += child.progress.closed;
+            if child.task.status == TaskStatus::Closed {
+                progress.closed += 1;
+            }
+        }
+        TaskTree {
+            task: root,
+            progress,
+            children,
+        }
+    }
+}
+
+/// Ids of a task and all of its descendants, leaving out tasks in the trash.
+macro_rules! subtree_ids {
+    () => {
+        r#"WITH RECURSIVE subtree(id) AS (
+            SELECT id FROM tasks WHERE id = ? AND deleted_at IS NULL
+            UNION
+            SELECT t.id FROM tasks t JOIN subtree s ON t.parent_id = s.id
+            WHERE t.deleted_at IS NULL
+        )"#
+    };
+}
+
+impl TaskMac {
+    const SUBTREE_SQL: &'static str = concat!(
+        subtree_ids!(),
+        " SELECT ",
+        task_columns!(),
+        " FROM tasks WHERE id IN subtree ORDER BY id"
+    );
+    const IS_DESCENDANT_SQL: &'static str =
+        concat!(subtree_ids!(), " SELECT COUNT(*) FROM subtree WHERE id = ?");
+    const HAS_CHILDREN_SQL: &'static str =
+        "SELECT COUNT(*) FROM tasks WHERE parent_id = ? AND deleted_at IS NULL";
+    const CHILDREN_SQL: &'static str = concat!(
+        "SELECT ",
+        task_columns!(),
+        " FROM tasks WHERE parent_id = ? AND deleted_at IS NULL"
+    );
+    const TRASH_SUBTREE_SQL: &'static str = concat!(
+        subtree_ids!(),
+        " UPDATE tasks SET deleted_at = unixepoch(), deleted_batch = ?, modified_at = unixepoch(), version = version + 1 WHERE id IN subtree"
+    );
+    const ORPHAN_SQL: &'static str = r#"UPDATE tasks SET parent_id = NULL, modified_at = unixepoch(),
+        field_times = json_set(field_times, '$.parent_id', CAST(unixepoch('subsec') * 1000 AS INTEGER)),
+        version = version + 1
+        WHERE parent_id = ? AND deleted_at IS NULL"#;
+    const TRASH_SQL: &'static str =
+        "UPDATE tasks SET deleted_at = unixepoch(), deleted_batch = ?, modified_at = unixepoch(), version = version + 1 WHERE id = ?";
+
+    /// Get a task together with all of its descendants.
+    pub async fn subtree(db: &Database, id: i64) -> Result<TaskTree, crate::Error> {
+        let mut tasks = sqlx::query_as::<_, Task>(Self::SUBTREE_SQL)
+            .bind(id)
+            .fetch_all(db)
+            .await?;
+        TagMac::load_for(db, &mut tasks).await?;
+
+        let mut root = None;
+        let mut children_of: HashMap<i64, Vec<Task>> = HashMap::new();
+        for task in tasks {
+            match task.parent_id {
+                Some(parent_id) if task.id != id => {
+                    children_of.entry(parent_id).or_default().push(task)
+                }
+                _ => root = Some(task),
+            }
+        }
+        let root = root.ok_or_else(|| not_found(id)(sqlx::Error::RowNotFound))?;
+        Ok(TaskTree::build(root, &mut children_of))
+    }
+
+    /// Move a task to the trash on behalf of `client`, deciding what happens to its
+    /// subtasks. See [`TaskMac::restore`] and [`TaskMac::purge`].
+    pub async fn delete_with(
+        db: &Database,
+        id: i64,
+        children: ChildPolicy,
+        client: Option<&str>,
+    ) -> Result<(), crate::Error> {
+        let mut tx = db.begin().await?;
+        Self::delete_in(&mut tx, id, children, client).await?;
+        tx.commit().await?;
+        EventMac::notify(db);
+        Ok(())
+    }
+
+    /// Move a task to the trash within the transaction of the caller, as
+    /// [`TaskMac::delete_with`].
+    pub(super) async fn delete_in(
+        conn: &mut SqliteConnection,
+        id: i64,
+        children: ChildPolicy,
+        client: Option<&str>,
+    ) -> Result<(), crate::Error> {
+        let batch = next_trash_batch(conn).await?;
+        let child_count: i64 = sqlx::query_scalar(Self::HAS_CHILDREN_SQL)
+            .bind(id)
+            .fetch_one(&mut *conn)
+            .await?;
+        let deleted = match children {
+            ChildPolicy::Refuse if child_count > 0 => {
+                return Err(crate::Error::Conflict(format!(
+                    "Task {} has {} subtasks. Choose to cascade or orphan them.",
+                    id, child_count
+                )));
+            }
+            ChildPolicy::Cascade => {
+                let deleted = sqlx::query_as::<_, Task>(Self::SUBTREE_SQL)
+                    .bind(id)
+                    .fetch_all(&mut *conn)
+                    .await?;
+                sqlx::query(Self::TRASH_SUBTREE_SQL)
+                    .bind(id)
+                    .bind(batch)
+                    .execute(&mut *conn)
+                    .await?;
+                deleted
+            }
+            ChildPolicy::Refuse | ChildPolicy::Orphan => {
+                let orphans = sqlx::query_as::<_, Task>(Self::CHILDREN_SQL)
+                    .bind(id)
+                    .fetch_all(&mut *conn)
+                    .await?;
+                sqlx::query(Self::ORPHAN_SQL)
+                    .bind(id)
+                    .execute(&mut *conn)
+                    .await?;
+                for orphan in &orphans {
+                    let after = Task {
+                        parent_id: None,
+                        ..orphan.clone()
+                    };
+                    HistoryMac::record(conn, Some(orphan), Some(&after), client).await?;
+                }
+                let deleted = sqlx::query_as::<_, Task>(Self::GET_SQL)
+                    .bind(id)
+                    .fetch_all(&mut *conn)
+                    .await?;
+                sqlx::query(Self::TRASH_SQL)
+                    .bind(batch)
+                    .bind(id)
+                    .execute(&mut *conn)
+                    .await?;
+                deleted
+            }
+        };
+        if deleted.is_empty() {
+            return Err(not_found(id)(sqlx::Error::RowNotFound));
+        }
+        for task in &deleted {
+            HistoryMac::record(conn, Some(task), None, client).await?;
+        }
+        Ok(())
+    }
+
+    /// Check that `parent_id` exists and, for an existing task `id`, that it is
+    /// not the task itself or one of its descendants.
+    pub(super) async fn validate_parent(
+        conn: &mut SqliteConnection,
+        id: Option<i64>,
+        parent_id: i64,
+    ) -> Result<(), crate::Error> {
+        if let Err(crate::Error::NotFound(_)) = TaskMac::get_in(conn, parent_id).await {
+            return Err(crate::Error::Validation(format!(
+                "Parent task {} does not exist.",
+                parent_id
+            )));
+        }
+        if let Some(id) = id {
+            let is_descendant: i64 = sqlx::query_scalar(Self::IS_DESCENDANT_SQL)
+                .bind(id)
+                .bind(parent_id)
+                .fetch_one(&mut *conn)
+                .await?;
+            if is_descendant > 0 {
+                return Err(crate::Error::Validation(format!(
+                    "Task {} cannot be moved under its own subtask {}.",
+                    id, parent_id
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use crate::model::history::{HistoryAction, HistoryEntry};
+    use crate::model::task::TaskPatch;
+
+    async fn insert(
+        db: &Database,
+        name: &str,
+        parent_id: Option<i64>,
+        status: TaskStatus,
+    ) -> Result<Task, crate::Error> {
+        TaskMac::insert(
+            db,
+            TaskPatch {
+                name: Some(name.to_string()),
+                status: Some(status),
+                parent_id: Some(parent_id),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    /// Move a task under a new parent through an update.
+    async fn reparent(
+        db: &Database,
+        id: i64,
+        parent_id: Option<i64>,
+    ) -> Result<Task, crate::Error> {
+        let patch = TaskPatch {
+            parent_id: Some(parent_id),
+            ..Default::default()
+        };
+        Ok(TaskMac::update(db, id, patch).await?.task)
+    }
+
+    /// Test fetching a nested subtree with progress counts.
+    #[tokio::test]
+    async fn test_subtree() -> Result<(), crate::Error> {
+        // # Fixture
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let root = insert(&db, "Root", None, TaskStatus::Open).await?;
+        let a = insert(&db, "A", Some(root.id), TaskStatus::Closed).await?;
+        insert(&db, "A1", Some(a.id), TaskStatus::Closed).await?;
+        insert(&db, "A2", Some(a.id), TaskStatus::Open).await?;
+        insert(&db, "B", Some(root.id), TaskStatus::Open).await?;
+        insert(&db, "Other", None, TaskStatus::Open).await?;
+
+        // # Action
+        let tree = TaskMac::subtree(&db, root.id).await?;
+
+        // # Check
+        assert_eq!(tree.task, root);
+        assert_eq!(tree.progress, Progress { closed: 2, total: 4 });
+        let names: Vec<&str> = tree.children.iter().map(|c| c.task.name.as_str()).collect();
+        assert_eq!(names, vec!["A", "B"]);
+        assert_eq!(tree.children[0].progress, Progress { closed: 1, total: 2 });
+        assert!(tree.children[1].children.is_empty());
+
+        assert!(matches!(
+            TaskMac::subtree(&db, 42).await,
+            Err(crate::Error::NotFound(_))
+        ));
+        Ok(())
+    }
+
+    /// Test reparenting, including rejection of cycles.
+    #[tokio::test]
+    async fn test_reparent() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let root = insert(&db, "Root", None, TaskStatus::Open).await?;
+        let child = insert(&db, "Child", Some(root.id), TaskStatus::Open).await?;
+        let other = insert(&db, "Other", None, TaskStatus::Open).await?;
+
+        let moved = reparent(&db, child.id, Some(other.id)).await?;
+        assert_eq!(moved.parent_id, Some(other.id));
+        let moved = reparent(&db, child.id, None).await?;
+        assert_eq!(moved.parent_id, None);
+
+        reparent(&db, child.id, Some(root.id)).await?;
+        for parent in [root.id, child.id, 42] {
+            let result = reparent(&db, root.id, Some(parent)).await;
+            assert!(
+                matches!(result, Err(crate::Error::Validation(_))),
+                "parent {}",
+                parent
+            );
+        }
+        Ok(())
+    }
+
+    /// Test the explicit choice required when deleting a parent.
+    #[tokio::test]
+    async fn test_delete_parent() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let root = insert(&db, "Root", None, TaskStatus::Open).await?;
+        let child = insert(&db, "Child", Some(root.id), TaskStatus::Open).await?;
+        let grandchild = insert(&db, "Grandchild", Some(child.id), TaskStatus::Open).await?;
+
+        // Refused by default
+        assert!(matches!(
+            TaskMac::delete(&db, root.id).await,
+            Err(crate::Error::Conflict(_))
+        ));
+
+        // Orphaning keeps the subtree of the child
+        TaskMac::delete_with(&db, root.id, ChildPolicy::Orphan, None).await?;
+        assert_eq!(TaskMac::get(&db, child.id).await?.parent_id, None);
+        assert_eq!(TaskMac::get(&db, grandchild.id).await?.parent_id, Some(child.id));
+
+        // Cascading removes everything below
+        TaskMac::delete_with(&db, child.id, ChildPolicy::Cascade, None).await?;
+        assert!(TaskMac::list(&db).await?.is_empty());
+
+        // Every affected task has its change recorded
+        let actions = |entries: Vec<HistoryEntry>| -> Vec<HistoryAction> {
+            entries.into_iter().map(|e| e.action).collect()
+        };
+        use HistoryAction::*;
+        assert_eq!(
+            actions(HistoryMac::for_task(&db, child.id).await?),
+            vec![Created, Updated, Deleted]
+        );
+        assert_eq!(
+            actions(HistoryMac::for_task(&db, grandchild.id).await?),
+            vec![Created, Deleted]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,406 @@
+//! Append-only audit log of task changes. Every insert, update and delete of a task
+//! writes a history entry in the same transaction, with the changed fields before
+//! and after, the time and the acting client. Changes of the tags and blockers of a
+//! task are recorded as updates of `tags` (the tag names) and `blocked_by` (the ids
+//! of the blocking tasks).
+
+use super::listing::{Page, MAX_LIMIT};
+use super::task::{Task, TaskMac};
+use crate::database::Database;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::types::Json;
+use sqlx::{FromRow, SqliteConnection};
+
+/// Task fields tracked by the history and merged field by field by sync. Derived
+/// fields, tags and the version are not.
+pub(super) const TRACKED_FIELDS: [&str; 7] = [
+    "name",
+    "status",
+    "due_at",
+    "start_at",
+    "priority",
+    "parent_id",
+    "recurrence",
+];
+
+/// Kind of change recorded in a history entry.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryAction {
+    Created,
+    Updated,
+    /// Moved to the trash.
+    Deleted,
+    /// Restored from the trash.
+    Restored,
+    /// Removed from the trash for good.
+    Purged,
+}
+
+/// One recorded change of a task.
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow)]
+pub struct HistoryEntry {
+    pub id: i64,
+    pub task_id: i64,
+    pub action: HistoryAction,
+    /// Changed fields before the change. `null` for creations and restores.
+    pub old_values: Option<Json<Map<String, Value>>>,
+    /// Changed fields after the change. `null` for deletions and purges.
+    pub new_values: Option<Json<Map<String, Value>>>,
+    pub changed_at: DateTime<Utc>,
+    /// Client that made the change, from the `X-Client-Id` header.
+    pub client: Option<String>,
+}
+
+/// Tracked fields of a task.
+fn tracked(task: &Task) -> Map<String, Value> {
+    let Ok(Value::Object(mut fields)) = serde_json::to_value(task) else {
+        unreachable!("tasks serialize to objects");
+    };
+    fields.retain(|key, _| TRACKED_FIELDS.contains(&key.as_str()));
+    fields
+}
+
+/// History model access controller.
+pub struct HistoryMac;
+
+impl HistoryMac {
+    const INSERT_SQL: &'static str = r#"INSERT INTO task_history
+        (task_id, action, old_values, new_values, changed_at, client)
+        VALUES (?, ?, ?, ?, ?, ?)"#;
+    const FOR_TASK_SQL: &'static str = r#"SELECT
+        id, task_id, action, old_values, new_values, changed_at, client
+        FROM task_history WHERE task_id = ? ORDER BY id"#;
+    const FEED_SQL: &'static str = r#"SELECT
+        id, task_id, action, old_values, new_values, changed_at, client
+        FROM task_history WHERE id < ? ORDER BY id DESC LIMIT ?"#;
+    const COUNT_SQL: &'static str = "SELECT COUNT(*) FROM task_history";
+
+    /// Record a change from `before` to `after` within the transaction making it.
+    /// Updates that leave every tracked field unchanged are not recorded.
+    pub(super) async fn record(
+        conn: &mut SqliteConnection,
+        before: Option<&Task>,
+        after: Option<&Task>,
+        client: Option<&str>,
+    ) -> Result<(), crate::Error> {
+        let (action, task_id, old_values, new_values) = match (before, after) {
+            (None, Some(after)) => (HistoryAction::Created, after.id, None, Some(tracked(after))),
+            (Some(before), None) => (
+                HistoryAction::Deleted,
+                before.id,
+                Some(tracked(before)),
+                None,
+            ),
+            (Some(before), Some(after)) => {
+                let mut old_values = tracked(before);
+                let mut new_values = tracked(after);
+                old_values.retain(|key, value| new_values.get(key) != Some(value));
+                new_values.retain(|key, _| old_values.contains_key(key));
+                if new_values.is_empty() {
+                    return Ok(());
+                }
+                (
+                    HistoryAction::Updated,
+                    after.id,
+                    Some(old_values),
+                    Some(new_values),
+                )
+            }
+            (None, None) => return Ok(()),
+        };
+        Self::insert(conn, task_id, action, old_values, new_values, client).await
+    }
+
+    /// Record that a task was restored from, or purged from, the trash.
+    pub(super) async fn record_trash(
+        conn: &mut SqliteConnection,
+        action: HistoryAction,
+        task: &Task,
+        client: Option<&str>,
+    ) -> Result<(), crate::Error> {
+        let (old_values, new_values) = match action {
+            HistoryAction::Purged => (Some(tracked(task)), None),
+            _ => (None, Some(tracked(task))),
+        };
+        Self::insert(conn, task.id, action, old_values, new_values, client).await
+    }
+
+    /// Record that a relation of a task, such as its tags, changed from `before` to
+    /// `after`. Nothing is recorded if it did not change.
+    pub(super) async fn record_relation(
+        conn: &mut SqliteConnection,
+        task_id: i64,
+        key: &str,
+        before: Value,
+        after: Value,
+        client: Option<&str>,
+    ) -> Result<(), crate::Error> {
+        if before == after {
+            return Ok(());
+        }
+        let old_values = Map::from_iter([(key.to_string(), before)]);
+        let new_values = Map::from_iter([(key.to_string(), after)]);
+        Self::insert(
+            conn,
+            task_id,
+            HistoryAction::Updated,
+            Some(old_values),
+            Some(new_values),
+            client,
+        )
+        .await
+    }
+
+    async fn insert(
+        conn: &mut SqliteConnection,
+        task_id: i64,
+        action: HistoryAction,
+        old_values: Option<Map<String, Value>>,
+        new_values: Option<Map<String, Value>>,
+        client: Option<&str>,
+    ) -> Result<(), crate::Error> {
+        sqlx::query(Self::INSERT_SQL)
+            .bind(task_id)
+            .bind(action)
+            .bind(old_values.map(Json))
+            .bind(new_values.map(Json))
+            .bind(Utc::now().timestamp())
+            .bind(client)
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    /// The history of a task, oldest first. Deleted tasks keep their history; a task
+    /// that has none yet has an empty one.
+    pub async fn for_task(db: &Database, task_id: i64) -> Result<Vec<HistoryEntry>, crate::Error> {
+        let entries = sqlx::query_as::<_, HistoryEntry>(Self::FOR_TASK_SQL)
+            .bind(task_id)
+            .fetch_all(db)
+            .await?;
+        if entries.is_empty() {
+            TaskMac::get(db, task_id).await?;
+        }
+        Ok(entries)
+    }
+
+    /// One page of the changes to all tasks, newest first. The cursor is the id of
+    /// the last entry of the previous page.
+    pub async fn feed(
+        db: &Database,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<HistoryEntry>, crate::Error> {
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(crate::Error::Validation(format!(
+                "Limit must be between 1 and {}.",
+                MAX_LIMIT
+            )));
+        }
+        let before = match cursor {
+            Some(cursor) => cursor
+                .parse::<i64>()
+                .map_err(|_| crate::Error::Validation("Invalid cursor.".to_string()))?,
+            None => i64::MAX,
+        };
+        let mut items = sqlx::query_as::<_, HistoryEntry>(Self::FEED_SQL)
+            .bind(before)
+            .bind(limit as i64 + 1)
+            .fetch_all(db)
+            .await?;
+        let next_cursor = if items.len() > limit {
+            items.truncate(limit);
+            items.last().map(|entry| entry.id.to_string())
+        } else {
+            None
+        };
+        let total: i64 = sqlx::query_scalar(Self::COUNT_SQL).fetch_one(db).await?;
+        Ok(Page {
+            items,
+            next_cursor,
+            total,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use crate::model::hierarchy::ChildPolicy;
+    use crate::model::tag::{TagMac, TagPatch};
+    use crate::model::task::{TaskMac, TaskPatch, TaskStatus};
+    use serde_json::json;
+
+    /// Test that inserts, updates and deletes are recorded with the changed fields.
+    #[tokio::test]
+    async fn test_task_history() -> Result<(), crate::Error> {
+        // # Fixture
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let task = TaskMac::insert_by(
+            &db,
+            TaskPatch {
+                name: Some("Draft".to_string()),
+                ..Default::default()
+            },
+            Some("alice"),
+        )
+        .await?;
+
+        // # Action
+        let patch = TaskPatch {
+            name: Some("Final".to_string()),
+            status: Some(TaskStatus::Closed),
+            ..Default::default()
+        };
+        TaskMac::update_by(&db, task.id, patch, Some("bob")).await?;
+        // Writing the current values changes nothing.
+        let patch = TaskPatch {
+            name: Some("Final".to_string()),
+            ..Default::default()
+        };
+        TaskMac::update_by(&db, task.id, patch, Some("bob")).await?;
+        TaskMac::delete_with(&db, task.id, ChildPolicy::Refuse, None).await?;
+
+        // # Check
+        let history = HistoryMac::for_task(&db, task.id).await?;
+        let actions: Vec<HistoryAction> = history.iter().map(|e| e.action).collect();
+        assert_eq!(
+            actions,
+            vec![
+                HistoryAction::Created,
+                HistoryAction::Updated,
+                HistoryAction::Deleted
+            ]
+        );
+        assert_eq!(history[0].client.as_deref(), Some("alice"));
+        assert_eq!(history[0].old_values, None);
+        assert_eq!(history[0].new_values.as_ref().unwrap()["name"], "Draft");
+
+        assert_eq!(history[1].client.as_deref(), Some("bob"));
+        assert_eq!(
+            json!(history[1].old_values),
+            json!({"name": "Draft", "status": "Open"})
+        );
+        assert_eq!(
+            json!(history[1].new_values),
+            json!({"name": "Final", "status": "Closed"})
+        );
+
+        assert_eq!(history[2].client, None);
+        assert_eq!(history[2].old_values.as_ref().unwrap()["name"], "Final");
+        assert_eq!(history[2].new_values, None);
+
+        assert!(matches!(
+            HistoryMac::for_task(&db, 42).await,
+            Err(crate::Error::NotFound(_))
+        ));
+        Ok(())
+    }
+
+    /// Test that tag and dependency changes are recorded, and that a task without
+    /// history has an empty one.
+    #[tokio::test]
+    async fn test_relation_history() -> Result<(), crate::Error> {
+        // # Fixture
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let insert = |name: &str| TaskPatch {
+            name: Some(name.to_string()),
+            ..Default::default()
+        };
+        let blocker = TaskMac::insert(&db, insert("Blocker")).await?;
+        let task = TaskMac::insert(&db, insert("Task")).await?;
+        let tag = TagMac::insert(
+            &db,
+            TagPatch {
+                name: "backend".to_string(),
+            },
+        )
+        .await?;
+
+        // # Action
+        TagMac::attach(&db, task.id, tag.id, Some("alice")).await?;
+        // Attaching twice changes nothing.
+        TagMac::attach(&db, task.id, tag.id, Some("alice")).await?;
+        TaskMac::add_dependency(&db, blocker.id, task.id, Some("bob")).await?;
+        TaskMac::remove_dependency(&db, blocker.id, task.id, None).await?;
+        TagMac::detach(&db, task.id, tag.id, None).await?;
+
+        // # Check
+        let history = HistoryMac::for_task(&db, task.id).await?;
+        let changes: Vec<_> = history[1..]
+            .iter()
+            .map(|e| json!([e.old_values, e.new_values, e.client]))
+            .collect();
+        assert_eq!(
+            json!(changes),
+            json!([
+                [{"tags": []}, {"tags": ["backend"]}, "alice"],
+                [{"blocked_by": []}, {"blocked_by": [1]}, "bob"],
+                [{"blocked_by": [1]}, {"blocked_by": []}, null],
+                [{"tags": ["backend"]}, {"tags": []}, null],
+            ])
+        );
+        assert!(history[1..]
+            .iter()
+            .all(|e| e.action == HistoryAction::Updated));
+
+        sqlx::query("INSERT INTO tasks (name, creation_time) VALUES ('Legacy', 0)")
+            .execute(&db)
+            .await?;
+        assert_eq!(HistoryMac::for_task(&db, 3).await?, vec![]);
+        Ok(())
+    }
+
+    /// Test that history entries cannot be changed.
+    #[tokio::test]
+    async fn test_append_only() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        TaskMac::insert(
+            &db,
+            TaskPatch {
+                name: Some("Task".to_string()),
+                ..Default::default()
+            },
+        )
+        .await?;
+        for sql in [
+            "UPDATE task_history SET client = 'mallory'",
+            "DELETE FROM task_history",
+        ] {
+            assert!(sqlx::query(sql).execute(&db).await.is_err(), "{}", sql);
+        }
+        Ok(())
+    }
+
+    /// Test paging through the activity feed, newest first.
+    #[tokio::test]
+    async fn test_feed() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        for name in ["One", "Two", "Three"] {
+            TaskMac::insert(
+                &db,
+                TaskPatch {
+                    name: Some(name.to_string()),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        }
+
+        let first = HistoryMac::feed(&db, None, 2).await?;
+        let ids: Vec<i64> = first.items.iter().map(|e| e.task_id).collect();
+        assert_eq!(ids, vec![3, 2]);
+        assert_eq!(first.total, 3);
+
+        let second = HistoryMac::feed(&db, first.next_cursor.as_deref(), 2).await?;
+        let ids: Vec<i64> = second.items.iter().map(|e| e.task_id).collect();
+        assert_eq!(ids, vec![1]);
+        assert_eq!(second.next_cursor, None);
+        Ok(())
+    }
+}
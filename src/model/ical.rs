@@ -0,0 +1,517 @@
+//! Tasks as iCalendar (RFC 5545) `VTODO` components, for calendar apps.
+//!
+//! A task maps to a `VTODO` with its name as `SUMMARY`, its due and start times as
+//! `DUE` and `DTSTART`, its recurrence as `RRULE` and its tags as `CATEGORIES`.
+//! `Open` is `NEEDS-ACTION` and `Closed` is `COMPLETED`. Priorities map to the
+//! iCalendar scale, where 1 is the highest: urgent 1, high 3, normal 5, low 9.
+//!
+//! The importer reads the same properties back. It also accepts `IN-PROCESS` as open
+//! and `CANCELLED` as closed, dates without a time (`VALUE=DATE`, taken as midnight
+//! UTC), and local times with a `TZID`. Other properties and nested components such
+//! as `VALARM` are ignored.
+
+use super::events::EventMac;
+use super::tag::TagMac;
+use super::task::{Task, TaskMac, TaskPatch, TaskPriority, TaskStatus};
+use super::transfer::{ImportReport, RowError};
+use crate::database::Database;
+use chrono::{LocalResult, NaiveDate, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::Connection;
+use std::collections::HashMap;
+
+/// Longest content line in octets, without the line break.
+const MAX_LINE: usize = 75;
+
+/// A `VTODO` read from an iCalendar file.
+#[derive(Debug, Default, Clone)]
+pub struct ImportedTodo {
+    /// The `UID` of the component.
+    pub uid: Option<String>,
+    pub patch: TaskPatch,
+    /// Tag names from `CATEGORIES`.
+    pub tags: Vec<String>,
+}
+
+/// An iCalendar file with one `VTODO` per task.
+pub fn to_calendar(tasks: &[Task]) -> String {
+    let mut out = String::new();
+    push_line(&mut out, "BEGIN:VCALENDAR");
+    push_line(&mut out, "VERSION:2.0");
+    push_line(&mut out, "PRODID:-//taskapp//tasks//EN");
+    push_line(&mut out, "X-WR-CALNAME:Tasks");
+    for task in tasks {
+        push_todo(&mut out, task);
+    }
+    push_line(&mut out, "END:VCALENDAR");
+    out
+}
+
+/// Append the `VTODO` of a task.
+fn push_todo(out: &mut String, task: &Task) {
+    push_line(out, "BEGIN:VTODO");
+    push_line(out, &format!("UID:{}", uid(task.id)));
+    push_line(out, &format!("DTSTAMP:{}", format_time(task.modified_at)));
+    push_line(out, &format!("CREATED:{}", format_time(task.creation_time)));
+    push_line(
+        out,
+        &format!("LAST-MODIFIED:{}", format_time(task.modified_at)),
+    );
+    push_line(out, &format!("SEQUENCE:{}", task.version));
+    push_line(out, &format!("SUMMARY:{}", escape(&task.name)));
+    let status = match task.status {
+        TaskStatus::Open => "NEEDS-ACTION",
+        TaskStatus::Closed => "COMPLETED",
+    };
+    push_line(out, &format!("STATUS:{}", status));
+    let priority = match task.priority {
+        TaskPriority::Urgent => 1,
+        TaskPriority::High => 3,
+        TaskPriority::Normal => 5,
+        TaskPriority::Low => 9,
+    };
+    push_line(out, &format!("PRIORITY:{}", priority));
+    if let Some(start_at) = task.start_at {
+        push_line(out, &format!("DTSTART:{}", format_time(start_at)));
+    }
+    if let Some(due_at) = task.due_at {
+        push_line(out, &format!("DUE:{}", format_time(due_at)));
+    }
+    if let Some(recurrence) = &task.recurrence {
+        push_line(out, &format!("RRULE:{}", recurrence));
+    }
+    if !task.tags.is_empty() {
+        let tags: Vec<String> = task.tags.iter().map(|tag| escape(&tag.name)).collect();
+        push_line(out, &format!("CATEGORIES:{}", tags.join(",")));
+    }
+    if let Some(parent_id) = task.parent_id {
+        push_line(out, &format!("RELATED-TO:{}", uid(parent_id)));
+    }
+    push_line(out, "END:VTODO");
+}
+
+/// Stable `UID` of a task.
+fn uid(id: i64) -> String {
+    format!("task-{}@taskapp", id)
+}
+
+/// A UTC time in iCalendar form, e.g. `20240501T100000Z`.
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Escape a text value.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | ';' | ',' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\r' => (),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Undo [`escape`].
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Append a content line, folded into lines of at most [`MAX_LINE`] octets.
+fn push_line(out: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > MAX_LINE {
+            out.push_str("\r\n ");
+            // The leading space of a continuation counts towards its length.
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+/// A content line: name, parameters and value.
+struct Property {
+    name: String,
+    params: HashMap<String, String>,
+    value: String,
+}
+
+/// Split a content line into its parts. Colons and semicolons in quoted parameter
+/// values do not count.
+fn parse_property(line: &str) -> Option<Property> {
+    let mut quoted = false;
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut value_start = None;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                parts.push(&line[start..i]);
+                start = i + 1;
+            }
+            ':' if !quoted => {
+                parts.push(&line[start..i]);
+                value_start = Some(i + 1);
+                break;
+            }
+            _ => (),
+        }
+    }
+    let value = line[value_start?..].to_string();
+    let mut parts = parts.into_iter();
+    let name = parts.next()?.trim().to_ascii_uppercase();
+    let params = parts
+        .filter_map(|param| param.split_once('='))
+        .map(|(key, value)| {
+            (
+                key.to_ascii_uppercase(),
+                value.trim_matches('"').to_string(),
+            )
+        })
+        .collect();
+    Some(Property {
+        name,
+        params,
+        value,
+    })
+}
+
+/// Parse a `DUE` or `DTSTART` value.
+fn parse_time(property: &Property) -> Result<DateTime<Utc>, String> {
+    let value = property.value.trim();
+    let invalid = || format!("Invalid {} {:?}.", property.name, value);
+    if property.params.get("VALUE").map(String::as_str) == Some("DATE") || value.len() == 8 {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d").map_err(|_| invalid())?;
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc());
+    }
+    if let Some(utc) = value.strip_suffix('Z') {
+        let time = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").map_err(|_| invalid())?;
+        return Ok(time.and_utc());
+    }
+    let local = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").map_err(|_| invalid())?;
+    let Some(tzid) = property.params.get("TZID") else {
+        // A floating time; without a time zone, UTC is the best guess.
+        return Ok(local.and_utc());
+    };
+    let tz: Tz = tzid
+        .parse()
+        .map_err(|_| format!("Unknown time zone {:?}.", tzid))?;
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => Ok(time.with_timezone(&Utc)),
+        // Skipped by a daylight saving change; an hour later exists.
+        LocalResult::None => tz
+            .from_local_datetime(&(local + chrono::Duration::hours(1)))
+            .earliest()
+            .map(|time| time.with_timezone(&Utc))
+            .ok_or_else(invalid),
+    }
+}
+
+/// A `VTODO` by its 1-based position in the file, or why it could not be read.
+pub type ParsedTodo = (usize, Result<ImportedTodo, String>);
+
+/// Read the `VTODO` components of an iCalendar file, numbered from 1. A component
+/// that cannot be read is reported with its number; the file as a whole fails only if
+/// it is not a calendar.
+pub fn parse_calendar(text: &str) -> Result<Vec<ParsedTodo>, crate::Error> {
+    // Unfold continuation lines, which start with a space or a tab.
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    if !lines
+        .iter()
+        .any(|line| line.trim().eq_ignore_ascii_case("BEGIN:VCALENDAR"))
+    {
+        return Err(crate::Error::Validation(
+            "Not an iCalendar file.".to_string(),
+        ));
+    }
+
+    let mut todos = Vec::new();
+    // Components entered, innermost last.
+    let mut components: Vec<String> = Vec::new();
+    let mut todo: Option<Result<ImportedTodo, String>> = None;
+    for line in lines.iter().filter(|line| !line.trim().is_empty()) {
+        let Some(property) = parse_property(line) else {
+            if let Some(Ok(_)) = todo {
+                todo = Some(Err(format!("Invalid line {:?}.", line)));
+            }
+            continue;
+        };
+        match property.name.as_str() {
+            "BEGIN" => {
+                let component = property.value.trim().to_ascii_uppercase();
+                if component == "VTODO" && todo.is_none() {
+                    todo = Some(Ok(ImportedTodo::default()));
+                }
+                components.push(component);
+                continue;
+            }
+            "END" => {
+                let component = components.pop().unwrap_or_default();
+                if component == "VTODO" && !components.contains(&component) {
+                    if let Some(done) = todo.take() {
+                        let done = done.and_then(|todo| match todo.patch.name {
+                            Some(_) => Ok(todo),
+                            None => Err("VTODO has no SUMMARY.".to_string()),
+                        });
+                        todos.push((todos.len() + 1, done));
+                    }
+                }
+                continue;
+            }
+            _ => (),
+        }
+        // Only properties of the VTODO itself, not of its alarms.
+        if components.last().map(String::as_str) != Some("VTODO") {
+            continue;
+        }
+        if let Some(Ok(current)) = &mut todo {
+            if let Err(message) = apply_property(current, &property) {
+                todo = Some(Err(message));
+            }
+        }
+    }
+    Ok(todos)
+}
+
+/// Set the field of a `VTODO` that a property describes.
+fn apply_property(todo: &mut ImportedTodo, property: &Property) -> Result<(), String> {
+    let value = property.value.trim();
+    let patch = &mut todo.patch;
+    match property.name.as_str() {
+        "UID" => todo.uid = Some(value.to_string()),
+        "SUMMARY" => patch.name = Some(unescape(value)),
+        "STATUS" => {
+            patch.status = match value.to_ascii_uppercase().as_str() {
+                "NEEDS-ACTION" | "IN-PROCESS" => Some(TaskStatus::Open),
+                "COMPLETED" | "CANCELLED" => Some(TaskStatus::Closed),
+                _ => return Err(format!("Invalid STATUS {:?}.", value)),
+            }
+        }
+        // Completion without a status, as some clients write it.
+        "COMPLETED" if patch.status.is_none() => patch.status = Some(TaskStatus::Closed),
+        "PRIORITY" => {
+            let priority: u8 = value
+                .parse()
+                .map_err(|_| format!("Invalid PRIORITY {:?}.", value))?;
+            patch.priority = match priority {
+                0 => None,
+                1 => Some(TaskPriority::Urgent),
+                2..=4 => Some(TaskPriority::High),
+                5 => Some(TaskPriority::Normal),
+                6..=9 => Some(TaskPriority::Low),
+                _ => return Err(format!("Invalid PRIORITY {:?}.", value)),
+            }
+        }
+        "DUE" => patch.due_at = Some(Some(parse_time(property)?)),
+        "DTSTART" => patch.start_at = Some(Some(parse_time(property)?)),
+        "RRULE" => patch.recurrence = Some(Some(value.to_string())),
+        "CATEGORIES" => todo.tags.extend(
+            split_list(value)
+                .into_iter()
+                .filter(|tag| !tag.trim().is_empty()),
+        ),
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Split a comma-separated list of text values, keeping escaped commas.
+fn split_list(value: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            ',' => {
+                items.push(unescape(&value[start..i]));
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    items.push(unescape(&value[start..]));
+    items
+}
+
+/// iCalendar model access controller.
+pub struct IcalMac;
+
+impl IcalMac {
+    /// Create a task for every `VTODO` of an iCalendar file on behalf of `client`, in
+    /// one transaction. Invalid components are reported and skipped. With `dry_run`,
+    /// components are imported the same way and then rolled back.
+    pub async fn import(
+        db: &Database,
+        text: &str,
+        dry_run: bool,
+        client: Option<&str>,
+    ) -> Result<ImportReport, crate::Error> {
+        let mut report = ImportReport {
+            dry_run,
+            ..Default::default()
+        };
+        let now = Utc::now();
+        let mut tx = db.begin().await?;
+        for (row, todo) in parse_calendar(text)? {
+            let todo = match todo {
+                Ok(todo) => todo,
+                Err(message) => {
+                    report.errors.push(RowError {
+                        row,
+                        id: None,
+                        message,
+                    });
+                    continue;
+                }
+            };
+            // A task and its tags are written together, or not at all.
+            let mut savepoint = tx.begin().await?;
+            let inserted =
+                TaskMac::insert_in(&mut savepoint, None, None, todo.patch, client, now).await;
+            let result = match inserted {
+                Ok(task) => TagMac::set_for_in(&mut savepoint, task.id, &todo.tags, client).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => {
+                    savepoint.commit().await?;
+                    report.created += 1;
+                }
+                Err(crate::Error::Internal(e)) => return Err(crate::Error::Internal(e)),
+                Err(e) => report.errors.push(RowError {
+                    row,
+                    id: None,
+                    message: e.to_string(),
+                }),
+            }
+        }
+        if !dry_run {
+            tx.commit().await?;
+            EventMac::notify(db);
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use crate::model::tag::Tag;
+
+    /// Test that exported tasks read back as the same patches.
+    #[test]
+    fn test_round_trip() {
+        // # Fixture
+        let task = Task {
+            id: 7,
+            name: "Pay rent; then, relax\nand sleep — a long name that needs folding".to_string(),
+            status: TaskStatus::Closed,
+            priority: TaskPriority::High,
+            due_at: "2024-05-01T10:00:00Z".parse().ok(),
+            start_at: "2024-04-28T08:30:00Z".parse().ok(),
+            recurrence: Some("FREQ=MONTHLY;BYMONTHDAY=1".to_string()),
+            tags: vec![Tag {
+                id: 1,
+                name: "home".to_string(),
+            }],
+            ..Default::default()
+        };
+
+        // # Action
+        let calendar = to_calendar(std::slice::from_ref(&task));
+        let todos = parse_calendar(&calendar).unwrap();
+
+        // # Check
+        assert!(calendar.lines().all(|line| line.len() <= MAX_LINE));
+        assert!(calendar.contains("STATUS:COMPLETED\r\n"));
+        assert_eq!(todos.len(), 1);
+        let todo = todos[0].1.as_ref().unwrap();
+        assert_eq!(todo.uid.as_deref(), Some("task-7@taskapp"));
+        assert_eq!(todo.patch.name.as_ref(), Some(&task.name));
+        assert_eq!(todo.patch.status, Some(TaskStatus::Closed));
+        assert_eq!(todo.patch.priority, Some(TaskPriority::High));
+        assert_eq!(todo.patch.due_at, Some(task.due_at));
+        assert_eq!(todo.patch.start_at, Some(task.start_at));
+        assert_eq!(todo.patch.recurrence, Some(task.recurrence));
+        assert_eq!(todo.tags, vec!["home".to_string()]);
+    }
+
+    /// Test reading VTODOs written by other applications.
+    #[test]
+    fn test_parse_foreign() {
+        let text = "BEGIN:VCALENDAR\r\n\
+            BEGIN:VEVENT\r\nSUMMARY:Not a task\r\nEND:VEVENT\r\n\
+            BEGIN:VTODO\r\nSUMMARY:Call \r\n the plumber\r\nSTATUS:IN-PROCESS\r\n\
+            DUE;TZID=Europe/Berlin:20240701T090000\r\n\
+            BEGIN:VALARM\r\nSUMMARY:Alarm\r\nEND:VALARM\r\nEND:VTODO\r\n\
+            BEGIN:VTODO\r\nDUE;VALUE=DATE:20240702\r\nEND:VTODO\r\n\
+            BEGIN:VTODO\r\nSUMMARY:Bad date\r\nDUE:tomorrow\r\nEND:VTODO\r\n\
+            END:VCALENDAR\r\n";
+        let todos = parse_calendar(text).unwrap();
+
+        assert_eq!(todos.len(), 3);
+        let todo = todos[0].1.as_ref().unwrap();
+        assert_eq!(todo.patch.name.as_deref(), Some("Call the plumber"));
+        assert_eq!(todo.patch.status, Some(TaskStatus::Open));
+        assert_eq!(todo.patch.due_at, Some("2024-07-01T07:00:00Z".parse().ok()));
+        assert!(todos[1].1.is_err());
+        assert!(todos[2].1.is_err());
+        assert!(parse_calendar("BEGIN:VCARD\r\nEND:VCARD\r\n").is_err());
+    }
+
+    /// Test importing VTODOs as tasks.
+    #[tokio::test]
+    async fn test_import() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let text = "BEGIN:VCALENDAR\nBEGIN:VTODO\nSUMMARY:Buy stamps\nCATEGORIES:errands,post\n\
+            END:VTODO\nBEGIN:VTODO\nSUMMARY:Never\nRRULE:FREQ=HOURLY\nEND:VTODO\n\
+            BEGIN:VTODO\nSUMMARY:Bad tag\nCATEGORIES:a\\,b\nEND:VTODO\nEND:VCALENDAR\n";
+
+        let report = IcalMac::import(&db, text, true, None).await?;
+        assert_eq!(report.created, 1);
+        let rows: Vec<usize> = report.errors.iter().map(|e| e.row).collect();
+        assert_eq!(rows, vec![2, 3]);
+        assert!(TaskMac::list(&db).await?.is_empty());
+
+        let report = IcalMac::import(&db, text, false, None).await?;
+        assert_eq!((report.created, report.errors.len()), (1, 2));
+        // The task with the invalid tag is not left behind.
+        let tasks = TaskMac::list(&db).await?;
+        assert_eq!(tasks.len(), 1);
+        let tags: Vec<&str> = tasks[0].tags.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(tags, vec!["errands", "post"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,598 @@
+//! Filtered, sorted and paginated task listings.
+//!
+//! Pagination is keyset based: a cursor holds the sort values of the last task of a
+//! page and the next page starts strictly after them. Pages therefore stay stable when
+//! tasks are inserted or deleted between requests, unlike with offsets.
+
+use super::filter::Expr;
+use super::tag::{TagMac, TagMatch};
+use super::task::{task_columns, Task, TaskMac, TaskStatus};
+use crate::database::Database;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{QueryBuilder, Sqlite};
+use std::fmt;
+use std::str::FromStr;
+
+/// Default number of tasks per page.
+pub const DEFAULT_LIMIT: usize = 100;
+/// Largest number of tasks per page.
+pub const MAX_LIMIT: usize = 1000;
+
+/// A task attribute that listings can be sorted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    Id,
+    Name,
+    Status,
+    CreationTime,
+    DueAt,
+    StartAt,
+    Priority,
+}
+
+impl SortField {
+    const ALL: [SortField; 7] = [
+        SortField::Id,
+        SortField::Name,
+        SortField::Status,
+        SortField::CreationTime,
+        SortField::DueAt,
+        SortField::StartAt,
+        SortField::Priority,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            SortField::Id => "id",
+            SortField::Name => "name",
+            SortField::Status => "status",
+            SortField::CreationTime => "creation_time",
+            SortField::DueAt => "due_at",
+            SortField::StartAt => "start_at",
+            SortField::Priority => "priority",
+        }
+    }
+
+    /// SQL expression sorted on. Missing dates sort after every date.
+    fn sql(&self) -> &'static str {
+        match self {
+            SortField::Id => "tasks.id",
+            SortField::Name => "tasks.name COLLATE NOCASE",
+            SortField::Status => "tasks.status",
+            SortField::CreationTime => "tasks.creation_time",
+            SortField::DueAt => "COALESCE(tasks.due_at, 9223372036854775807)",
+            SortField::StartAt => "COALESCE(tasks.start_at, 9223372036854775807)",
+            SortField::Priority => "tasks.priority",
+        }
+    }
+
+    /// Priorities sort most important first unless asked otherwise.
+    fn default_descending(&self) -> bool {
+        *self == SortField::Priority
+    }
+
+    /// The value of the sort expression for a task.
+    fn value(&self, task: &Task) -> SortValue {
+        let missing = |t: Option<DateTime<Utc>>| t.map_or(i64::MAX, |t| t.timestamp());
+        match self {
+            SortField::Id => SortValue::Int(task.id),
+            SortField::Name => SortValue::Text(task.name.clone()),
+            SortField::Status => SortValue::Text(
+                match task.status {
+                    TaskStatus::Open => "open",
+                    TaskStatus::Closed => "closed",
+                }
+                .to_string(),
+            ),
+            SortField::CreationTime => SortValue::Int(task.creation_time.timestamp()),
+            SortField::DueAt => SortValue::Int(missing(task.due_at)),
+            SortField::StartAt => SortValue::Int(missing(task.start_at)),
+            SortField::Priority => SortValue::Int(task.priority as i64),
+        }
+    }
+}
+
+/// One key of a sort order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey {
+    pub field: SortField,
+    pub descending: bool,
+}
+
+/// A multi-key sort order, written as comma-separated `field[:asc|:desc]` keys, e.g.
+/// `priority,due_at:asc`. The id is always the last key, which makes the order total.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sort(Vec<SortKey>);
+
+impl Default for Sort {
+    fn default() -> Self {
+        Sort(vec![SortKey {
+            field: SortField::Id,
+            descending: false,
+        }])
+    }
+}
+
+impl FromStr for Sort {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut keys: Vec<SortKey> = Vec::new();
+        for key in s.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+            let (name, direction) = key.split_once(':').unwrap_or((key, ""));
+            let field = SortField::ALL
+                .into_iter()
+                .find(|f| f.name() == name)
+                .ok_or_else(|| crate::Error::Validation(format!("Cannot sort by {:?}.", name)))?;
+            let descending = match direction {
+                "" => field.default_descending(),
+                "asc" => false,
+                "desc" => true,
+                other => {
+                    return Err(crate::Error::Validation(format!(
+                        "Unknown sort direction {:?}. Use asc or desc.",
+                        other
+                    )))
+                }
+            };
+            if keys.iter().any(|k| k.field == field) {
+                return Err(crate::Error::Validation(format!(
+                    "Sort key {:?} is given twice.",
+                    name
+                )));
+            }
+            keys.push(SortKey { field, descending });
+        }
+        if !keys.iter().any(|k| k.field == SortField::Id) {
+            keys.extend(Sort::default().0);
+        }
+        Ok(Sort(keys))
+    }
+}
+
+impl fmt::Display for Sort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keys: Vec<String> = self
+            .0
+            .iter()
+            .map(|k| {
+                format!(
+                    "{}:{}",
+                    k.field.name(),
+                    if k.descending { "desc" } else { "asc" }
+                )
+            })
+            .collect();
+        f.write_str(&keys.join(","))
+    }
+}
+
+/// A value of a sort key stored in a cursor.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum SortValue {
+    Int(i64),
+    Text(String),
+}
+
+/// Position after the last task of a page. Encoded as URL-safe base64 JSON, which
+/// clients treat as opaque.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Cursor {
+    sort: String,
+    after: Vec<SortValue>,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).expect("cursor serializes to JSON"))
+    }
+
+    /// Decode a cursor, checking that it was issued for the given sort order.
+    fn decode(token: &str, sort: &Sort) -> Result<Cursor, crate::Error> {
+        let invalid = || crate::Error::Validation("Invalid cursor.".to_string());
+        let bytes = URL_SAFE_NO_PAD.decode(token).map_err(|_| invalid())?;
+        let cursor: Cursor = serde_json::from_slice(&bytes).map_err(|_| invalid())?;
+        if cursor.sort != sort.to_string() {
+            return Err(crate::Error::Validation(
+                "The cursor was issued for a different sort order.".to_string(),
+            ));
+        }
+        let types_match = cursor.after.len() == sort.0.len()
+            && sort.0.iter().zip(&cursor.after).all(|(key, value)| {
+                matches!(
+                    (key.field, value),
+                    (SortField::Name | SortField::Status, SortValue::Text(_))
+                        | (
+                            SortField::Id
+                                | SortField::CreationTime
+                                | SortField::DueAt
+                                | SortField::StartAt
+                                | SortField::Priority,
+                            SortValue::Int(_)
+                        )
+                )
+            });
+        if !types_match {
+            return Err(invalid());
+        }
+        Ok(cursor)
+    }
+}
+
+/// Criteria for listing tasks. All given filters must match.
+#[derive(Debug, Clone)]
+pub struct TaskQuery {
+    pub status: Option<TaskStatus>,
+    /// Tasks created at or after this time.
+    pub created_after: Option<DateTime<Utc>>,
+    /// Tasks created before this time.
+    pub created_before: Option<DateTime<Utc>>,
+    /// Case-insensitive substring of the name.
+    pub name_contains: Option<String>,
+    pub tags: Vec<String>,
+    pub tag_match: TagMatch,
+    /// Expression in the filter language of [`super::filter`].
+    pub filter: Option<Expr>,
+    pub sort: Sort,
+    /// Cursor returned with the previous page.
+    pub cursor: Option<String>,
+    pub limit: usize,
+}
+
+impl Default for TaskQuery {
+    fn default() -> Self {
+        TaskQuery {
+            status: None,
+            created_after: None,
+            created_before: None,
+            name_contains: None,
+            tags: Vec::new(),
+            tag_match: TagMatch::default(),
+            filter: None,
+            sort: Sort::default(),
+            cursor: None,
+            limit: DEFAULT_LIMIT,
+        }
+    }
+}
+
+/// One page of a listing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Cursor of the next page, if there is one.
+    pub next_cursor: Option<String>,
+    /// Number of matching items across all pages.
+    pub total: i64,
+}
+
+impl TaskQuery {
+    /// Append the filter conditions to a query selecting from `tasks`.
+    fn push_filters<'a>(&'a self, query: &mut QueryBuilder<'a, Sqlite>, now: DateTime<Utc>) {
+        query.push(" WHERE tasks.deleted_at IS NULL");
+        if let Some(status) = &self.status {
+            query.push(" AND tasks.status = ").push_bind(status.clone());
+        }
+        if let Some(after) = self.created_after {
+            query
+                .push(" AND tasks.creation_time >= ")
+                .push_bind(after.timestamp());
+        }
+        if let Some(before) = self.created_before {
+            query
+                .push(" AND tasks.creation_time < ")
+                .push_bind(before.timestamp());
+        }
+        if let Some(name) = &self.name_contains {
+            query
+                .push(" AND instr(lower(tasks.name), lower(")
+                .push_bind(name)
+                .push(")) > 0");
+        }
+        if !self.tags.is_empty() {
+            let (names, required) = TagMac::match_names(&self.tags, self.tag_match);
+            query
+                .push(
+                    r#" AND tasks.id IN (
+                    SELECT tt.task_id FROM task_tags tt JOIN tags g ON g.id = tt.tag_id
+                    WHERE g.name IN (SELECT value FROM json_each("#,
+                )
+                .push_bind(names)
+                .push(")) GROUP BY tt.task_id HAVING COUNT(DISTINCT g.id) >= ")
+                .push_bind(required)
+                .push(")");
+        }
+        if let Some(filter) = &self.filter {
+            query.push(" AND ");
+            filter.push_sql(query, now);
+        }
+    }
+
+    /// Append the condition selecting tasks strictly after the cursor position.
+    fn push_after<'a>(&self, query: &mut QueryBuilder<'a, Sqlite>, after: &'a [SortValue]) {
+        query.push(" AND (");
+        for (i, key) in self.sort.0.iter().enumerate() {
+            if i > 0 {
+                query.push(" OR ");
+            }
+            query.push("(");
+            for (previous, value) in self.sort.0.iter().zip(after).take(i) {
+                query.push(previous.field.sql()).push(" = ");
+                push_value(query, value);
+                query.push(" AND ");
+            }
+            query
+                .push(key.field.sql())
+                .push(if key.descending { " < " } else { " > " });
+            push_value(query, &after[i]);
+            query.push(")");
+        }
+        query.push(")");
+    }
+}
+
+fn push_value<'a>(query: &mut QueryBuilder<'a, Sqlite>, value: &'a SortValue) {
+    match value {
+        SortValue::Int(value) => query.push_bind(*value),
+        SortValue::Text(value) => query.push_bind(value.as_str()),
+    };
+}
+
+impl TaskMac {
+    /// List one page of the tasks matching a query.
+    pub async fn list_page(db: &Database, query: &TaskQuery) -> Result<Page<Task>, crate::Error> {
+        if !(1..=MAX_LIMIT).contains(&query.limit) {
+            return Err(crate::Error::Validation(format!(
+                "Limit must be between 1 and {}.",
+                MAX_LIMIT
+            )));
+        }
+        let cursor = query
+            .cursor
+            .as_deref()
+            .map(|token| Cursor::decode(token, &query.sort))
+            .transpose()?;
+
+        let now = Utc::now();
+        let mut count = QueryBuilder::new("SELECT COUNT(*) FROM tasks");
+        query.push_filters(&mut count, now);
+        let total: i64 = count.build_query_scalar().fetch_one(db).await?;
+
+        let mut select = QueryBuilder::new(concat!("SELECT ", task_columns!(), " FROM tasks"));
+        query.push_filters(&mut select, now);
+        if let Some(cursor) = &cursor {
+            query.push_after(&mut select, &cursor.after);
+        }
+        select.push(" ORDER BY ");
+        let mut order = select.separated(", ");
+        for key in &query.sort.0 {
+            order.push(format!(
+                "{} {}",
+                key.field.sql(),
+                if key.descending { "DESC" } else { "ASC" }
+            ));
+        }
+        select.push(" LIMIT ").push_bind(query.limit as i64 + 1);
+        let mut tasks: Vec<Task> = select.build_query_as().fetch_all(db).await?;
+
+        let next_cursor = if tasks.len() > query.limit {
+            tasks.truncate(query.limit);
+            tasks.last().map(|last| {
+                Cursor {
+                    sort: query.sort.to_string(),
+                    after: query.sort.0.iter().map(|k| k.field.value(last)).collect(),
+                }
+                .encode()
+            })
+        } else {
+            None
+        };
+        TagMac::load_for(db, &mut tasks).await?;
+        Ok(Page {
+            items: tasks,
+            next_cursor,
+            total,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use crate::model::tag::TagPatch;
+    use crate::model::task::{TaskPatch, TaskPriority};
+
+    async fn insert(
+        db: &Database,
+        name: &str,
+        priority: TaskPriority,
+        status: TaskStatus,
+    ) -> Result<Task, crate::Error> {
+        TaskMac::insert(
+            db,
+            TaskPatch {
+                name: Some(name.to_string()),
+                priority: Some(priority),
+                status: Some(status),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    fn names(tasks: &[Task]) -> Vec<&str> {
+        tasks.iter().map(|t| t.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_parse_sort() {
+        let sort: Sort = "priority,name:desc".parse().unwrap();
+        assert_eq!(sort.to_string(), "priority:desc,name:desc,id:asc");
+        let sort: Sort = "id:desc,due_at".parse().unwrap();
+        assert_eq!(sort.to_string(), "id:desc,due_at:asc");
+        assert_eq!("".parse::<Sort>().unwrap(), Sort::default());
+        for invalid in ["colour", "name:up", "name,name:desc"] {
+            assert!(
+                matches!(invalid.parse::<Sort>(), Err(crate::Error::Validation(_))),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    /// Test filters, multi-key sorting and the total count.
+    #[tokio::test]
+    async fn test_filter_and_sort() -> Result<(), crate::Error> {
+        // # Fixture
+        let db = create_and_connect(DbAddress::Memory).await?;
+        insert(&db, "Write docs", TaskPriority::Low, TaskStatus::Open).await?;
+        insert(&db, "Deploy backend", TaskPriority::High, TaskStatus::Open).await?;
+        insert(&db, "Write tests", TaskPriority::High, TaskStatus::Open).await?;
+        insert(
+            &db,
+            "Write changelog",
+            TaskPriority::Urgent,
+            TaskStatus::Closed,
+        )
+        .await?;
+
+        // # Action
+        let page = TaskMac::list_page(
+            &db,
+            &TaskQuery {
+                status: Some(TaskStatus::Open),
+                name_contains: Some("WRITE".to_string()),
+                sort: "priority,name".parse()?,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        // # Check
+        assert_eq!(names(&page.items), vec!["Write tests", "Write docs"]);
+        assert_eq!(page.total, 2);
+        assert_eq!(page.next_cursor, None);
+
+        let future = Utc::now() + chrono::Duration::days(1);
+        let page = TaskMac::list_page(
+            &db,
+            &TaskQuery {
+                created_after: Some(future),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert!(page.items.is_empty());
+        let page = TaskMac::list_page(
+            &db,
+            &TaskQuery {
+                created_before: Some(future),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(page.total, 4);
+        Ok(())
+    }
+
+    /// Test listing tasks by tag with AND and OR semantics.
+    #[tokio::test]
+    async fn test_tag_match() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let one = insert(&db, "One", TaskPriority::Normal, TaskStatus::Open).await?;
+        let two = insert(&db, "Two", TaskPriority::Normal, TaskStatus::Open).await?;
+        let three = insert(&db, "Three", TaskPriority::Normal, TaskStatus::Open).await?;
+        let tag = |name: &str| TagPatch {
+            name: name.to_string(),
+        };
+        let api = TagMac::insert(&db, tag("api")).await?;
+        let backend = TagMac::insert(&db, tag("backend")).await?;
+        let anger = TagMac::insert(&db, tag("Ärger")).await?;
+        TagMac::attach(&db, one.id, api.id, None).await?;
+        TagMac::attach(&db, two.id, api.id, None).await?;
+        TagMac::attach(&db, two.id, backend.id, None).await?;
+        TagMac::attach(&db, three.id, anger.id, None).await?;
+
+        let mut query = TaskQuery {
+            tags: vec!["API".to_string(), "backend".to_string()],
+            tag_match: TagMatch::All,
+            ..Default::default()
+        };
+        let all = TaskMac::list_page(&db, &query).await?;
+        assert_eq!(names(&all.items), vec!["Two"]);
+        query.tag_match = TagMatch::Any;
+        let any = TaskMac::list_page(&db, &query).await?;
+        assert_eq!(names(&any.items), vec!["One", "Two"]);
+
+        // Only ASCII letters compare case-insensitively, as in the database.
+        query.tags = vec!["Ärger".to_string(), "BACKEND".to_string()];
+        let any = TaskMac::list_page(&db, &query).await?;
+        assert_eq!(names(&any.items), vec!["Two", "Three"]);
+        query.tags = vec!["ärger".to_string()];
+        assert!(TaskMac::list_page(&db, &query).await?.items.is_empty());
+        Ok(())
+    }
+
+    /// Test walking pages with cursors, across inserts between requests.
+    #[tokio::test]
+    async fn test_cursor_pagination() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let priorities = [
+            TaskPriority::Low,
+            TaskPriority::High,
+            TaskPriority::Normal,
+            TaskPriority::High,
+            TaskPriority::Urgent,
+        ];
+        for (i, priority) in priorities.into_iter().enumerate() {
+            insert(&db, &format!("Task {}", i + 1), priority, TaskStatus::Open).await?;
+        }
+        let mut query = TaskQuery {
+            sort: "priority".parse()?,
+            limit: 2,
+            ..Default::default()
+        };
+
+        let first = TaskMac::list_page(&db, &query).await?;
+        assert_eq!(names(&first.items), vec!["Task 5", "Task 2"]);
+        assert_eq!(first.total, 5);
+
+        // A new task sorting before the cursor does not shift the next page.
+        insert(&db, "Task 6", TaskPriority::Urgent, TaskStatus::Open).await?;
+        query.cursor = first.next_cursor;
+        let second = TaskMac::list_page(&db, &query).await?;
+        assert_eq!(names(&second.items), vec!["Task 4", "Task 3"]);
+
+        query.cursor = second.next_cursor;
+        let third = TaskMac::list_page(&db, &query).await?;
+        assert_eq!(names(&third.items), vec!["Task 1"]);
+        assert_eq!(third.next_cursor, None);
+
+        // Cursors are bound to their sort order.
+        query.sort = "name".parse()?;
+        query.cursor = first_cursor(&db).await?;
+        assert!(matches!(
+            TaskMac::list_page(&db, &query).await,
+            Err(crate::Error::Validation(_))
+        ));
+        query.cursor = Some("garbage".to_string());
+        assert!(matches!(
+            TaskMac::list_page(&db, &query).await,
+            Err(crate::Error::Validation(_))
+        ));
+        Ok(())
+    }
+
+    async fn first_cursor(db: &Database) -> Result<Option<String>, crate::Error> {
+        let query = TaskQuery {
+            limit: 1,
+            ..Default::default()
+        };
+        Ok(TaskMac::list_page(db, &query).await?.next_cursor)
+    }
+}
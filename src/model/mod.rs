@@ -1 +1,18 @@
-pub(crate) mod task;
+pub mod dependency;
+pub mod events;
+pub mod filter;
+pub mod hierarchy;
+pub mod history;
+pub mod ical;
+pub mod listing;
+pub mod plaintext;
+pub mod ranking;
+pub mod recurrence;
+pub mod search;
+pub mod sync;
+pub mod tag;
+pub mod task;
+pub mod taskwarrior;
+pub mod transfer;
+pub mod trash;
+pub mod view;
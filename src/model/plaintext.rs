@@ -0,0 +1,527 @@
+//! Tasks as plain text: `todo.txt` lines and Markdown checklists.
+//!
+//! Both formats share the way a task is written after its completion mark:
+//!
+//! ```text
+//! (A) 2024-05-01 Water the plants +garden @home due:2024-05-03 tag:weekly
+//! ```
+//!
+//! - An optional priority: `(A)` is urgent, `(B)` high and `(C)` to `(Z)` low. Tasks
+//!   without a priority are normal.
+//! - The creation date. Creation times are kept to the day.
+//! - The name, followed by the tags: projects (`+garden`) and contexts (`@home`) are
+//!   tags of that name, including the sign, and other tags are written `tag:weekly`.
+//! - `due:` and `t:` (start) with a date, or a time in RFC 3339 form if not midnight
+//!   UTC, and `rrule:` with the recurrence rule.
+//!
+//! Projects, contexts and these keys are recognized anywhere in a line and taken out of
+//! the name; other words are kept, with single spaces between them.
+//!
+//! A backslash escapes what would otherwise be read differently: `\s` is a space that
+//! does not separate words, `\n` a line break, `\u{a0}` any other white space and
+//! `\\` a backslash. Any other character after a backslash stands for itself, so a
+//! name word written `\+1`, `due\:friday` or, first in the name, `\x` or
+//! `\2024-05-01` is kept in the name.
+//!
+//! In `todo.txt`, a closed task starts with `x` and the date of the export, since the
+//! completion date is not stored. In Markdown, tasks are checklist items (`- [ ]`,
+//! `- [x]`) and subtasks are nested two spaces deeper than their parent; other lines
+//! are ignored.
+
+use super::task::{TaskPriority, TaskStatus};
+use super::transfer::{ParsedRecord, TaskRecord};
+use chrono::{NaiveDate, NaiveTime};
+use sqlx::types::chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashSet};
+
+/// Write tasks as `todo.txt` lines.
+pub fn to_todo_txt(records: &[TaskRecord]) -> String {
+    let today = Utc::now().date_naive();
+    let mut out = String::new();
+    for record in records {
+        if record.status == TaskStatus::Closed {
+            out.push_str("x ");
+        }
+        push_priority(&mut out, record.priority);
+        if record.status == TaskStatus::Closed && record.creation_time.is_some() {
+            // The completion date comes first, and must be there for the creation date
+            // to be read as such.
+            out.push_str(&format!("{} ", today));
+        }
+        push_body(&mut out, record);
+        out.push('\n');
+    }
+    out
+}
+
+/// Write tasks as a Markdown checklist, with subtasks nested under their parent.
+pub fn to_markdown(records: &[TaskRecord]) -> String {
+    let ids: HashSet<i64> = records.iter().filter_map(|r| r.id).collect();
+    let mut children: BTreeMap<Option<i64>, Vec<&TaskRecord>> = BTreeMap::new();
+    for record in records {
+        let parent = record.parent_id.filter(|parent| ids.contains(parent));
+        children.entry(parent).or_default().push(record);
+    }
+    let mut out = String::new();
+    // Depth-first, with the items still to write on a stack, last first.
+    let mut stack: Vec<(usize, &TaskRecord)> = children
+        .get(&None)
+        .into_iter()
+        .flatten()
+        .rev()
+        .map(|record| (0, *record))
+        .collect();
+    while let Some((depth, record)) = stack.pop() {
+        out.push_str(&"  ".repeat(depth));
+        out.push_str(match record.status {
+            TaskStatus::Open => "- [ ] ",
+            TaskStatus::Closed => "- [x] ",
+        });
+        push_priority(&mut out, record.priority);
+        push_body(&mut out, record);
+        out.push('\n');
+        if let Some(id) = record.id {
+            let nested = children.get(&Some(id)).into_iter().flatten().rev();
+            stack.extend(nested.map(|child| (depth + 1, *child)));
+        }
+    }
+    out
+}
+
+/// Append the priority of a task, if it has one.
+fn push_priority(out: &mut String, priority: TaskPriority) {
+    let letter = match priority {
+        TaskPriority::Urgent => "(A) ",
+        TaskPriority::High => "(B) ",
+        TaskPriority::Low => "(C) ",
+        TaskPriority::Normal => "",
+    };
+    out.push_str(letter);
+}
+
+/// Append the creation date, name, tags and keys of a task.
+fn push_body(out: &mut String, record: &TaskRecord) {
+    let mut words = Vec::new();
+    if let Some(created) = record.creation_time {
+        words.push(created.date_naive().to_string());
+    }
+    words.push(escape_name(&record.name));
+    for tag in &record.tags {
+        if is_project_or_context(tag) {
+            words.push(escape(tag, false));
+        } else {
+            words.push(format!("tag:{}", escape(tag, false)));
+        }
+    }
+    if let Some(due_at) = record.due_at {
+        words.push(format!("due:{}", format_time(due_at)));
+    }
+    if let Some(start_at) = record.start_at {
+        words.push(format!("t:{}", format_time(start_at)));
+    }
+    if let Some(recurrence) = &record.recurrence {
+        words.push(format!("rrule:{}", escape(recurrence, false)));
+    }
+    out.push_str(&words.join(" "));
+}
+
+fn is_project_or_context(word: &str) -> bool {
+    word.len() > 1 && (word.starts_with('+') || word.starts_with('@'))
+}
+
+/// Keys of [`parse_body`], written `key:value`.
+const KEYS: [&str; 4] = ["due", "t", "tag", "rrule"];
+
+/// Escape backslashes and white space. With `keep_spaces`, a single space between two
+/// other characters is kept, to separate words.
+fn escape(text: &str, keep_spaces: bool) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    for (i, c) in chars.iter().enumerate() {
+        let separates = keep_spaces
+            && *c == ' '
+            && i > 0
+            && !chars[i - 1].is_whitespace()
+            && chars.get(i + 1).is_some_and(|next| !next.is_whitespace());
+        match c {
+            '\\' => out.push_str("\\\\"),
+            ' ' if separates => out.push(' '),
+            ' ' => out.push_str("\\s"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_whitespace() => out.push_str(&format!("\\u{{{:x}}}", *c as u32)),
+            c => out.push(*c),
+        }
+    }
+    out
+}
+
+/// Escape a name, and the words in it that would be read as something else.
+fn escape_name(name: &str) -> String {
+    let escaped = escape(name, true);
+    let words: Vec<String> = escaped
+        .split(' ')
+        .enumerate()
+        .map(|(i, word)| {
+            // A first word could be read as a completion mark, priority or date.
+            let leading = i == 0
+                && (word == "x" || word.starts_with(|c: char| c == '(' || c.is_ascii_digit()));
+            if leading || is_project_or_context(word) {
+                format!("\\{}", word)
+            } else {
+                match word.split_once(':') {
+                    Some((key, value)) if KEYS.contains(&key) => format!("{}\\:{}", key, value),
+                    _ => word.to_string(),
+                }
+            }
+        })
+        .collect();
+    words.join(" ")
+}
+
+/// Undo [`escape`] and [`escape_name`].
+fn unescape(word: &str) -> String {
+    let mut out = String::with_capacity(word.len());
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => out.push(' '),
+            Some('n') => out.push('\n'),
+            Some('u') => {
+                let code = chars
+                    .as_str()
+                    .strip_prefix('{')
+                    .and_then(|rest| rest.split_once('}'))
+                    .and_then(|(hex, rest)| {
+                        let code = u32::from_str_radix(hex, 16).ok()?;
+                        Some((char::from_u32(code)?, rest))
+                    });
+                match code {
+                    Some((code, rest)) => {
+                        out.push(code);
+                        chars = rest.chars();
+                    }
+                    None => out.push('u'),
+                }
+            }
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// A date, or a full time if it is not midnight UTC.
+fn format_time(time: DateTime<Utc>) -> String {
+    if time.time() == NaiveTime::MIN {
+        time.date_naive().to_string()
+    } else {
+        time.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)
+    }
+}
+
+/// Parse a value of [`format_time`].
+fn parse_time(key: &str, value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_time(NaiveTime::MIN).and_utc());
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| format!("Invalid date in {}:{}.", key, value))
+}
+
+/// Take a priority like `(A)` off the front of a line.
+fn take_priority(line: &str) -> (Option<TaskPriority>, &str) {
+    let bytes = line.as_bytes();
+    if bytes.len() >= 4
+        && bytes[0] == b'('
+        && bytes[1].is_ascii_uppercase()
+        && line.get(2..4) == Some(") ")
+    {
+        let priority = match bytes[1] {
+            b'A' => TaskPriority::Urgent,
+            b'B' => TaskPriority::High,
+            _ => TaskPriority::Low,
+        };
+        return (Some(priority), &line[4..]);
+    }
+    (None, line)
+}
+
+/// Take a date like `2024-05-01` off the front of a line.
+fn take_date(line: &str) -> (Option<NaiveDate>, &str) {
+    let (word, rest) = line.split_once(' ').unwrap_or((line, ""));
+    match NaiveDate::parse_from_str(word, "%Y-%m-%d") {
+        Ok(date) if word.len() == 10 => (Some(date), rest),
+        _ => (None, line),
+    }
+}
+
+/// Read the name, tags and keys of a task into a record. Words are recognized as
+/// written, and unescaped once they are.
+fn parse_body(body: &str, record: &mut TaskRecord) -> Result<(), String> {
+    let mut name = Vec::new();
+    for word in body.split_whitespace() {
+        if is_project_or_context(word) {
+            record.tags.push(unescape(word));
+            continue;
+        }
+        match word.split_once(':') {
+            Some(("due", value)) => record.due_at = Some(parse_time("due", value)?),
+            Some(("t", value)) => record.start_at = Some(parse_time("t", value)?),
+            Some(("rrule", value)) if !value.is_empty() => {
+                record.recurrence = Some(unescape(value))
+            }
+            Some(("tag", value)) if !value.is_empty() => record.tags.push(unescape(value)),
+            _ => name.push(unescape(word)),
+        }
+    }
+    record.name = name.join(" ");
+    Ok(())
+}
+
+/// Read a line after its completion mark: priority, creation date and body.
+fn parse_task(line: &str, status: TaskStatus) -> Result<TaskRecord, String> {
+    let mut record = TaskRecord {
+        status,
+        ..Default::default()
+    };
+    let (priority, line) = take_priority(line);
+    record.priority = priority.unwrap_or_default();
+    let (created, line) = take_date(line);
+    record.creation_time = created.map(|date| date.and_time(NaiveTime::MIN).and_utc());
+    parse_body(line, &mut record)?;
+    Ok(record)
+}
+
+/// Read the tasks of a `todo.txt` file, by line number. Blank lines are skipped.
+pub fn parse_todo_txt(text: &str) -> Vec<ParsedRecord> {
+    let mut records = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let record = match line.strip_prefix("x ") {
+            Some(rest) => {
+                // The priority may come before the dates.
+                let (priority, rest) = take_priority(rest);
+                // The first date is the completion date, which is not kept. A second
+                // one is the creation date.
+                let (_, rest) = take_date(rest);
+                parse_task(rest, TaskStatus::Closed).map(|mut record| {
+                    record.priority = priority.unwrap_or(record.priority);
+                    record
+                })
+            }
+            None => parse_task(line, TaskStatus::Open),
+        };
+        records.push((i + 1, record));
+    }
+    records
+}
+
+/// Read the checklist items of a Markdown file, by line number. Each item gets its
+/// line number as id, so that subtasks refer to their parent by it.
+pub fn parse_markdown(text: &str) -> Vec<ParsedRecord> {
+    let mut records = Vec::new();
+    // Indentation and line number of the enclosing items, innermost last.
+    let mut parents: Vec<(usize, i64)> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let content = line.trim_start();
+        let indent = line.len() - content.len();
+        let Some(item) = ["- ", "* ", "+ "]
+            .iter()
+            .find_map(|bullet| content.strip_prefix(bullet))
+        else {
+            continue;
+        };
+        let (status, rest) = match item.get(..4) {
+            Some("[ ] ") => (TaskStatus::Open, &item[4..]),
+            Some("[x] ") | Some("[X] ") => (TaskStatus::Closed, &item[4..]),
+            _ => continue,
+        };
+        while parents.last().is_some_and(|(depth, _)| *depth >= indent) {
+            parents.pop();
+        }
+        let id = i as i64 + 1;
+        let parent_id = parents.last().map(|(_, parent)| *parent);
+        parents.push((indent, id));
+        let record = parse_task(rest.trim(), status).map(|record| TaskRecord {
+            id: Some(id),
+            parent_id,
+            ..record
+        });
+        records.push((i + 1, record));
+    }
+    records
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn records() -> Vec<TaskRecord> {
+        vec![
+            TaskRecord {
+                id: Some(1),
+                name: "Plan the  garden".to_string(),
+                priority: TaskPriority::Urgent,
+                creation_time: "2024-04-01T09:30:00Z".parse().ok(),
+                due_at: "2024-05-03T00:00:00Z".parse().ok(),
+                tags: vec![
+                    "+garden".to_string(),
+                    "@home".to_string(),
+                    "spring".to_string(),
+                ],
+                ..Default::default()
+            },
+            TaskRecord {
+                id: Some(2),
+                name: "Buy seeds".to_string(),
+                status: TaskStatus::Closed,
+                creation_time: "2024-04-02T00:00:00Z".parse().ok(),
+                start_at: "2024-04-20T08:15:00Z".parse().ok(),
+                parent_id: Some(1),
+                recurrence: Some("FREQ=YEARLY".to_string()),
+                ..Default::default()
+            },
+            TaskRecord {
+                id: Some(3),
+                name: "x marks (B) the spot".to_string(),
+                priority: TaskPriority::Low,
+                ..Default::default()
+            },
+        ]
+    }
+
+    /// The fields of a record that a format keeps.
+    fn kept(record: &TaskRecord, keeps_parents: bool) -> TaskRecord {
+        TaskRecord {
+            id: None,
+            creation_time: record
+                .creation_time
+                .map(|t| t.date_naive().and_time(NaiveTime::MIN).and_utc()),
+            parent_id: record.parent_id.filter(|_| keeps_parents),
+            ..record.clone()
+        }
+    }
+
+    /// Test that todo.txt export and import give back the same tasks.
+    #[test]
+    fn test_todo_txt_round_trip() {
+        let text = to_todo_txt(&records());
+        assert!(text.starts_with(
+            "(A) 2024-04-01 Plan the\\s\\sgarden +garden @home tag:spring due:2024-05-03\n"
+        ));
+
+        let parsed: Vec<TaskRecord> = parse_todo_txt(&text)
+            .into_iter()
+            .map(|(_, record)| record.unwrap())
+            .collect();
+        let expected: Vec<TaskRecord> = records().iter().map(|r| kept(r, false)).collect();
+        assert_eq!(parsed, expected);
+        assert_eq!(to_todo_txt(&parsed), text);
+    }
+
+    /// Test that Markdown export and import give back the same tasks and nesting.
+    #[test]
+    fn test_markdown_round_trip() {
+        let text = to_markdown(&records());
+        assert!(text.contains("\n  - [x] 2024-04-02 Buy seeds t:2024-04-20T08:15:00Z"));
+
+        let parsed: Vec<TaskRecord> = parse_markdown(&text)
+            .into_iter()
+            .map(|(_, record)| record.unwrap())
+            .collect();
+        assert_eq!(parsed[1].parent_id, parsed[0].id);
+        let without_ids: Vec<TaskRecord> = parsed.iter().map(|r| kept(r, false)).collect();
+        let expected: Vec<TaskRecord> = records().iter().map(|r| kept(r, false)).collect();
+        assert_eq!(without_ids, expected);
+        assert_eq!(to_markdown(&parsed), text);
+    }
+
+    /// Test that names, tags and rules that look like syntax come back exactly.
+    #[test]
+    fn test_escaped_round_trip() {
+        // # Fixture
+        let records: Vec<TaskRecord> = [
+            ("x 2024-05-01 (A) +1 for @bob", vec![]),
+            ("Ask about due:friday and t:now, tag:x rrule:y", vec![]),
+            (
+                "  Spaced   out\tand\nbroken ",
+                vec!["two words", "+my project"],
+            ),
+            ("C:\\path\\ and \\s", vec!["back\\slash", "@"]),
+            ("(note) 42", vec!["x"]),
+        ]
+        .into_iter()
+        .map(|(name, tags)| TaskRecord {
+            name: name.to_string(),
+            tags: tags.into_iter().map(str::to_string).collect(),
+            ..Default::default()
+        })
+        .collect();
+
+        let exports = [
+            (
+                to_todo_txt(&records),
+                parse_todo_txt as fn(&str) -> Vec<ParsedRecord>,
+            ),
+            (to_markdown(&records), parse_markdown),
+        ];
+        for (text, import) in exports {
+            // # Action
+            let parsed: Vec<TaskRecord> = import(&text)
+                .into_iter()
+                .map(|(_, record)| TaskRecord {
+                    id: None,
+                    ..record.unwrap()
+                })
+                .collect();
+
+            // # Check
+            assert_eq!(text.lines().count(), records.len(), "{}", text);
+            assert_eq!(parsed, records, "{}", text);
+        }
+    }
+
+    /// Test reading files written by hand.
+    #[test]
+    fn test_parse_by_hand() {
+        let todo = "x 2024-05-02 2024-05-01 Call +mom @phone\n\n(B) Fix bike due:soon\nx (A) 2024-05-02 Pay bills\n";
+        let records = parse_todo_txt(todo);
+        let closed = records[0].1.as_ref().unwrap();
+        assert_eq!(closed.status, TaskStatus::Closed);
+        assert_eq!(closed.name, "Call");
+        assert_eq!(closed.creation_time, "2024-05-01T00:00:00Z".parse().ok());
+        assert_eq!(records[1].0, 3);
+        assert!(records[1].1.is_err());
+        let paid = records[2].1.as_ref().unwrap();
+        assert_eq!(paid.priority, TaskPriority::Urgent);
+        assert_eq!(paid.creation_time, None);
+
+        let markdown = "# Trip\n\n* [ ] Pack\n    * [X] Socks\n  - [ ] Shoes\n- [ ] Go\nNotes\n";
+        let records: Vec<TaskRecord> = parse_markdown(markdown)
+            .into_iter()
+            .map(|(_, record)| record.unwrap())
+            .collect();
+        let names: Vec<(&str, Option<i64>)> = records
+            .iter()
+            .map(|r| (r.name.as_str(), r.parent_id))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("Pack", None),
+                ("Socks", Some(3)),
+                ("Shoes", Some(3)),
+                ("Go", None)
+            ]
+        );
+        assert_eq!(records[1].status, TaskStatus::Closed);
+    }
+}
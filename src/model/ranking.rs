@@ -0,0 +1,167 @@
+//! Ranking of open tasks for the "what should I do next" endpoint.
+//!
+//! Every task gets an integer score; higher is more important. The score is the sum of:
+//!
+//! | Component | Points                                                        |
+//! |-----------|---------------------------------------------------------------|
+//! | Priority  | `Low` 0, `Normal` 10, `High` 20, `Urgent` 50                   |
+//! | Due date  | overdue: 50 + 1 per full day overdue (max 10)                  |
+//! |           | due within 1 day: 20, within 3 days: 10, within 7 days: 5      |
+//! |           | later or no due date: 0                                        |
+//! | Age       | 1 per full week since creation (max 5)                         |
+//!
+//! Ties are broken by the earliest due date (tasks without one last), then by the
+//! lowest id. A task that is neither urgent nor overdue scores at most 45, so an urgent
+//! task outranks every task that is not overdue, and an overdue task outranks every
+//! task that is neither urgent nor overdue.
+
+use super::task::{Task, TaskPriority};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::cmp::Reverse;
+
+/// A task with its ranking score.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RankedTask {
+    #[serde(flatten)]
+    pub task: Task,
+    pub score: i64,
+}
+
+/// Points for the priority level.
+fn priority_points(priority: TaskPriority) -> i64 {
+    match priority {
+        TaskPriority::Low => 0,
+        TaskPriority::Normal => 10,
+        TaskPriority::High => 20,
+        TaskPriority::Urgent => 50,
+    }
+}
+
+/// Points for how close, or how far past, the due date is.
+fn due_points(due_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> i64 {
+    let Some(due_at) = due_at else {
+        return 0;
+    };
+    let remaining = due_at - now;
+    if remaining < Duration::zero() {
+        50 + (-remaining).num_days().min(10)
+    } else if remaining <= Duration::days(1) {
+        20
+    } else if remaining <= Duration::days(3) {
+        10
+    } else if remaining <= Duration::days(7) {
+        5
+    } else {
+        0
+    }
+}
+
+/// Points for how long the task has been waiting.
+fn age_points(creation_time: DateTime<Utc>, now: DateTime<Utc>) -> i64 {
+    (now - creation_time).num_weeks().clamp(0, 5)
+}
+
+/// Score of a task at the given time. See the module documentation.
+pub fn score(task: &Task, now: DateTime<Utc>) -> i64 {
+    priority_points(task.priority)
+        + due_points(task.due_at, now)
+        + age_points(task.creation_time, now)
+}
+
+/// Rank tasks by score and return the best `limit` of them.
+pub fn rank(tasks: Vec<Task>, now: DateTime<Utc>, limit: usize) -> Vec<RankedTask> {
+    let mut ranked: Vec<RankedTask> = tasks
+        .into_iter()
+        .map(|task| RankedTask {
+            score: score(&task, now),
+            task,
+        })
+        .collect();
+    ranked.sort_by_key(|r| {
+        (
+            Reverse(r.score),
+            r.task.due_at.is_none(),
+            r.task.due_at,
+            r.task.id,
+        )
+    });
+    ranked.truncate(limit);
+    ranked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn task(id: i64, priority: TaskPriority, due_in: Option<Duration>, now: DateTime<Utc>) -> Task {
+        Task {
+            id,
+            name: format!("Task {}", id),
+            priority,
+            creation_time: now,
+            due_at: due_in.map(|d| now + d),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn score_components() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 6, 12, 0, 0).unwrap();
+
+        assert_eq!(score(&task(1, TaskPriority::Normal, None, now), now), 10);
+        assert_eq!(
+            score(&task(1, TaskPriority::High, Some(Duration::hours(5)), now), now),
+            40
+        );
+        assert_eq!(
+            score(&task(1, TaskPriority::Low, Some(-Duration::days(3)), now), now),
+            53
+        );
+        assert_eq!(
+            score(&task(1, TaskPriority::Low, Some(-Duration::days(100)), now), now),
+            60
+        );
+
+        let mut old = task(1, TaskPriority::Low, None, now);
+        old.creation_time = now - Duration::weeks(3);
+        assert_eq!(score(&old, now), 3);
+    }
+
+    #[test]
+    fn rank_order() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 6, 12, 0, 0).unwrap();
+        let tasks = vec![
+            task(1, TaskPriority::Normal, None, now),
+            task(2, TaskPriority::Urgent, None, now),
+            task(3, TaskPriority::Normal, Some(-Duration::days(1)), now),
+            task(4, TaskPriority::High, Some(Duration::days(10)), now),
+            task(5, TaskPriority::High, Some(Duration::days(20)), now),
+        ];
+
+        let ranked = rank(tasks, now, 4);
+        let ids: Vec<i64> = ranked.iter().map(|r| r.task.id).collect();
+        assert_eq!(ids, vec![3, 2, 4, 5]);
+    }
+
+    #[test]
+    fn urgent_outranks_not_overdue() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 6, 12, 0, 0).unwrap();
+        let mut high = task(1, TaskPriority::High, Some(Duration::hours(1)), now);
+        high.creation_time = now - Duration::weeks(10);
+        let urgent = task(2, TaskPriority::Urgent, None, now);
+
+        assert!(score(&urgent, now) > score(&high, now));
+    }
+
+    #[test]
+    fn overdue_outranks_not_urgent() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 6, 12, 0, 0).unwrap();
+        let mut high = task(1, TaskPriority::High, Some(Duration::hours(1)), now);
+        high.creation_time = now - Duration::weeks(10);
+        let overdue = task(2, TaskPriority::Low, Some(-Duration::minutes(1)), now);
+
+        assert!(score(&overdue, now) > score(&high, now));
+    }
+}
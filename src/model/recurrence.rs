@@ -0,0 +1,735 @@
+//! Recurrence rules in the RFC 5545 `RRULE` syntax.
+//!
+//! The supported subset is `FREQ` (`DAILY`, `WEEKLY`, `MONTHLY`, `YEARLY`), `INTERVAL`,
+//! `COUNT`, `UNTIL`, `BYDAY` (with ordinals such as `-1FR` for `MONTHLY` and `YEARLY`),
+//! `BYMONTHDAY` and `BYMONTH`. Weeks start on Monday. Other parts are rejected.
+//!
+//! Occurrences keep the local time of day of the start in the given time zone, so a
+//! weekly 09:00 chore stays at 09:00 across daylight saving time changes.
+
+use super::task::{Task, TaskMac, TaskPatch};
+use chrono::{
+    DateTime, Datelike, Days, Duration, LocalResult, Months, NaiveDate, NaiveDateTime, TimeZone,
+    Utc, Weekday,
+};
+use chrono_tz::Tz;
+use sqlx::SqliteConnection;
+use std::fmt;
+use std::str::FromStr;
+
+/// Consecutive periods without any occurrence after which iteration gives up. Guards
+/// against rules that can never match, such as `FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=30`.
+const MAX_EMPTY_PERIODS: usize = 1000;
+
+/// Largest `INTERVAL`, which keeps a step of the rule well within the calendar.
+const MAX_INTERVAL: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// A `BYDAY` entry, e.g. `MO`, `2TU` or `-1FR`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ByDay {
+    pub ordinal: Option<i32>,
+    pub weekday: Weekday,
+}
+
+/// A parsed recurrence rule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RRule {
+    pub freq: Frequency,
+    pub interval: u32,
+    pub count: Option<u32>,
+    pub until: Option<DateTime<Utc>>,
+    pub by_day: Vec<ByDay>,
+    pub by_month_day: Vec<i32>,
+    pub by_month: Vec<u32>,
+}
+
+fn invalid(message: impl fmt::Display) -> crate::Error {
+    crate::Error::Validation(format!("Invalid recurrence rule: {}", message))
+}
+
+fn parse_weekday(s: &str) -> Option<Weekday> {
+    Some(match s {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return None,
+    })
+}
+
+fn weekday_code(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+fn parse_list<T: FromStr>(key: &str, value: &str) -> Result<Vec<T>, crate::Error> {
+    value
+        .split(',')
+        .map(|v| {
+            v.parse()
+                .map_err(|_| invalid(format!("bad {} value {:?}", key, v)))
+        })
+        .collect()
+}
+
+fn parse_until(value: &str) -> Result<DateTime<Utc>, crate::Error> {
+    let value = value.trim_end_matches('Z');
+    if let Ok(t) = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S") {
+        return Ok(t.and_utc());
+    }
+    if let Ok(d) = NaiveDate::parse_from_str(value, "%Y%m%d") {
+        // A date-only UNTIL includes the whole day.
+        return Ok(
+            (d + Days::new(1)).and_hms_opt(0, 0, 0).unwrap().and_utc() - Duration::seconds(1)
+        );
+    }
+    Err(invalid(format!("bad UNTIL value {:?}", value)))
+}
+
+impl FromStr for RRule {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = s.strip_prefix("RRULE:").unwrap_or(s);
+        let mut freq = None;
+        let mut rule = RRule {
+            freq: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: Vec::new(),
+            by_month_day: Vec::new(),
+            by_month: Vec::new(),
+        };
+
+        for part in s.split(';').filter(|p| !p.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("expected KEY=VALUE, got {:?}", part)))?;
+            match key.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    freq = Some(match value.to_ascii_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        other => return Err(invalid(format!("unsupported FREQ {}", other))),
+                    })
+                }
+                "INTERVAL" => {
+                    rule.interval = value
+                        .parse()
+                        .ok()
+                        .filter(|i| (1..=MAX_INTERVAL).contains(i))
+                        .ok_or_else(|| {
+                            invalid(format!("INTERVAL must be from 1 to {}", MAX_INTERVAL))
+                        })?
+                }
+                "COUNT" => {
+                    rule.count = Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|c| *c > 0)
+                            .ok_or_else(|| invalid("COUNT must be a positive integer"))?,
+                    )
+                }
+                "UNTIL" => rule.until = Some(parse_until(value)?),
+                "BYDAY" => {
+                    rule.by_day = value
+                        .split(',')
+                        .map(|v| {
+                            let v = v.to_ascii_uppercase();
+                            // The day code is the last two characters, which need not be
+                            // ASCII in invalid input.
+                            let split = v.char_indices().rev().nth(1).map_or(0, |(i, _)| i);
+                            let (ordinal, code) = v.split_at(split);
+                            let weekday = parse_weekday(code)
+                                .ok_or_else(|| invalid(format!("bad BYDAY value {:?}", v)))?;
+                            let ordinal = match ordinal.trim_start_matches('+') {
+                                "" => None,
+                                n => Some(
+                                    n.parse::<i32>()
+                                        .ok()
+                                        .filter(|n| *n != 0 && n.abs() <= 53)
+                                        .ok_or_else(|| {
+                                            invalid(format!("bad BYDAY value {:?}", v))
+                                        })?,
+                                ),
+                            };
+                            Ok(ByDay { ordinal, weekday })
+                        })
+                        .collect::<Result<_, crate::Error>>()?
+                }
+                "BYMONTHDAY" => {
+                    rule.by_month_day = parse_list(key, value)?;
+                    if rule.by_month_day.iter().any(|d| *d == 0 || d.abs() > 31) {
+                        return Err(invalid("BYMONTHDAY must be within 1..31 or -31..-1"));
+                    }
+                }
+                "BYMONTH" => {
+                    rule.by_month = parse_list(key, value)?;
+                    if rule.by_month.iter().any(|m| !(1..=12).contains(m)) {
+                        return Err(invalid("BYMONTH must be within 1..12"));
+                    }
+                }
+                "WKST" if value.eq_ignore_ascii_case("MO") => (),
+                other => return Err(invalid(format!("unsupported part {}", other))),
+            }
+        }
+
+        rule.freq = freq.ok_or_else(|| invalid("FREQ is required"))?;
+        if rule.count.is_some() && rule.until.is_some() {
+            return Err(invalid("COUNT and UNTIL are mutually exclusive"));
+        }
+        if rule.freq == Frequency::Weekly && !rule.by_month_day.is_empty() {
+            return Err(invalid("BYMONTHDAY cannot be used with FREQ=WEEKLY"));
+        }
+        if matches!(rule.freq, Frequency::Daily | Frequency::Weekly)
+            && rule.by_day.iter().any(|d| d.ordinal.is_some())
+        {
+            return Err(invalid("BYDAY ordinals need FREQ=MONTHLY or FREQ=YEARLY"));
+        }
+        Ok(rule)
+    }
+}
+
+impl fmt::Display for RRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let freq = match self.freq {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+            Frequency::Yearly => "YEARLY",
+        };
+        write!(f, "FREQ={}", freq)?;
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={}", count)?;
+        }
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}", until.format("%Y%m%dT%H%M%SZ"))?;
+        }
+        if !self.by_day.is_empty() {
+            let days: Vec<String> = self
+                .by_day
+                .iter()
+                .map(|d| match d.ordinal {
+                    Some(n) => format!("{}{}", n, weekday_code(d.weekday)),
+                    None => weekday_code(d.weekday).to_string(),
+                })
+                .collect();
+            write!(f, ";BYDAY={}", days.join(","))?;
+        }
+        if !self.by_month_day.is_empty() {
+            let days: Vec<String> = self.by_month_day.iter().map(|d| d.to_string()).collect();
+            write!(f, ";BYMONTHDAY={}", days.join(","))?;
+        }
+        if !self.by_month.is_empty() {
+            let months: Vec<String> = self.by_month.iter().map(|m| m.to_string()).collect();
+            write!(f, ";BYMONTH={}", months.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if NaiveDate::from_ymd_opt(year, 2, 29).is_some() => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Convert a local time to UTC. Times in a DST gap move forward by the gap.
+fn to_utc(local: NaiveDateTime, tz: Tz) -> DateTime<Utc> {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(t) | LocalResult::Ambiguous(t, _) => t.with_timezone(&Utc),
+        LocalResult::None => to_utc(local + Duration::hours(1), tz),
+    }
+}
+
+impl RRule {
+    /// Candidate days within one month for `MONTHLY` and `YEARLY` rules.
+    fn month_days(&self, year: i32, month: u32, start: NaiveDate) -> Vec<NaiveDate> {
+        let length = days_in_month(year, month) as i32;
+        let date = |day: i32| NaiveDate::from_ymd_opt(year, month, day as u32);
+
+        let by_month_day: Vec<NaiveDate> = self
+            .by_month_day
+            .iter()
+            .map(|d| if *d < 0 { length + 1 + d } else { *d })
+            .filter(|d| (1..=length).contains(d))
+            .filter_map(date)
+            .collect();
+
+        let mut by_day: Vec<NaiveDate> = Vec::new();
+        for spec in &self.by_day {
+            let matching: Vec<NaiveDate> = (1..=length)
+                .filter_map(date)
+                .filter(|d| d.weekday() == spec.weekday)
+                .collect();
+            match spec.ordinal {
+                None => by_day.extend(matching),
+                Some(n) if n > 0 => by_day.extend(matching.get(n as usize - 1)),
+                Some(n) => by_day.extend(
+                    matching
+                        .len()
+                        .checked_sub(n.unsigned_abs() as usize)
+                        .and_then(|i| matching.get(i)),
+                ),
+            }
+        }
+
+        let mut days = match (self.by_month_day.is_empty(), self.by_day.is_empty()) {
+            (true, true) => date(start.day() as i32).into_iter().collect(),
+            (false, true) => by_month_day,
+            (true, false) => by_day,
+            (false, false) => by_month_day
+                .into_iter()
+                .filter(|d| by_day.contains(d))
+                .collect(),
+        };
+        days.sort();
+        days.dedup();
+        days
+    }
+
+    /// Candidate days of the period starting at `period`, in order.
+    fn period_days(&self, period: NaiveDate, start: NaiveDate) -> Vec<NaiveDate> {
+        let weekday_ok = |d: &NaiveDate| {
+            self.by_day.is_empty() || self.by_day.iter().any(|b| b.weekday == d.weekday())
+        };
+        let month_ok =
+            |d: &NaiveDate| self.by_month.is_empty() || self.by_month.contains(&d.month());
+        match self.freq {
+            Frequency::Daily => {
+                let length = days_in_month(period.year(), period.month()) as i32;
+                let month_day_ok = self.by_month_day.is_empty()
+                    || self.by_month_day.iter().any(|d| {
+                        let d = if *d < 0 { length + 1 + d } else { *d };
+                        d == period.day() as i32
+                    });
+                if weekday_ok(&period) && month_ok(&period) && month_day_ok {
+                    vec![period]
+                } else {
+                    vec![]
+                }
+            }
+            Frequency::Weekly => (0..7)
+                .filter_map(|i| period.checked_add_days(Days::new(i)))
+                .filter(|d| {
+                    if self.by_day.is_empty() {
+                        d.weekday() == start.weekday()
+                    } else {
+                        weekday_ok(d)
+                    }
+                })
+                .filter(month_ok)
+                .collect(),
+            Frequency::Monthly => {
+                if month_ok(&period) {
+                    self.month_days(period.year(), period.month(), start)
+                } else {
+                    vec![]
+                }
+            }
+            Frequency::Yearly => {
+                let months = if self.by_month.is_empty() {
+                    vec![start.month()]
+                } else {
+                    let mut months = self.by_month.clone();
+                    months.sort();
+                    months.dedup();
+                    months
+                };
+                months
+                    .into_iter()
+                    .flat_map(|m| self.month_days(period.year(), m, start))
+                    .collect()
+            }
+        }
+    }
+
+    /// First day of the period containing `date`.
+    fn period_start(&self, date: NaiveDate) -> NaiveDate {
+        match self.freq {
+            Frequency::Daily => date,
+            Frequency::Weekly => date - Days::new(date.weekday().num_days_from_monday() as u64),
+            Frequency::Monthly => date.with_day(1).unwrap(),
+            Frequency::Yearly => date.with_day(1).unwrap().with_month(1).unwrap(),
+        }
+    }
+
+    /// First day of the period `interval` periods after `period`, or `None` past the
+    /// end of the calendar.
+    fn next_period(&self, period: NaiveDate) -> Option<NaiveDate> {
+        let interval = self.interval as u64;
+        match self.freq {
+            Frequency::Daily => period.checked_add_days(Days::new(interval)),
+            Frequency::Weekly => period.checked_add_days(Days::new(7 * interval)),
+            Frequency::Monthly => period.checked_add_months(Months::new(self.interval)),
+            Frequency::Yearly => {
+                let months = self.interval.checked_mul(12)?;
+                period.checked_add_months(Months::new(months))
+            }
+        }
+    }
+
+    /// Iterate the occurrences of the rule starting at `start`, evaluated in `tz`.
+    /// `start` itself is the first occurrence if it matches the rule.
+    pub fn occurrences(
+        &self,
+        start: DateTime<Utc>,
+        tz: Tz,
+    ) -> impl Iterator<Item = DateTime<Utc>> + '_ {
+        let local_start = start.with_timezone(&tz).naive_local();
+        let start_date = local_start.date();
+        let time = local_start.time();
+        let mut period = Some(self.period_start(start_date));
+        let mut pending: Vec<DateTime<Utc>> = Vec::new();
+        let mut emitted = 0;
+        let mut empty_periods = 0;
+
+        std::iter::from_fn(move || loop {
+            if self.count.is_some_and(|count| emitted >= count) {
+                return None;
+            }
+            if !pending.is_empty() {
+                let next = pending.remove(0);
+                if self.until.is_some_and(|until| next > until) {
+                    return None;
+                }
+                emitted += 1;
+                return Some(next);
+            }
+            let current = period?;
+            period = self.next_period(current);
+            pending = self
+                .period_days(current, start_date)
+                .into_iter()
+                .map(|d| d.and_time(time))
+                .filter(|t| *t >= local_start)
+                .map(|t| to_utc(t, tz))
+                .collect();
+            if pending.is_empty() {
+                empty_periods += 1;
+                if empty_periods >= MAX_EMPTY_PERIODS {
+                    return None;
+                }
+            } else {
+                empty_periods = 0;
+            }
+        })
+    }
+
+    /// The first occurrence strictly after `after`, for a series starting at `start`.
+    pub fn next_after(
+        &self,
+        start: DateTime<Utc>,
+        tz: Tz,
+        after: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        self.occurrences(start, tz).find(|t| *t > after)
+    }
+}
+
+impl TaskMac {
+    const COPY_TAGS_SQL: &'static str =
+        "INSERT INTO task_tags (task_id, tag_id) SELECT ?, tag_id FROM task_tags WHERE task_id = ?";
+
+    /// Create the occurrence following a recurring task that is being closed. The series
+    /// is anchored at the due date of the task, or its creation time if it has none, and
+    /// evaluated in UTC. Occurrences that already passed are skipped. Returns `None`
+    /// once the series is exhausted.
+    pub(super) async fn spawn_next_occurrence(
+        conn: &mut SqliteConnection,
+        task: &Task,
+        now: DateTime<Utc>,
+        client: Option<&str>,
+    ) -> Result<Option<Task>, crate::Error> {
+        let Some(recurrence) = &task.recurrence else {
+            return Ok(None);
+        };
+        let mut rule: RRule = recurrence.parse()?;
+        // COUNT includes the occurrence being closed.
+        let remaining = match rule.count {
+            Some(1) => return Ok(None),
+            Some(count) => Some(count - 1),
+            None => None,
+        };
+        rule.count = None;
+
+        let anchor = task.due_at.unwrap_or(task.creation_time);
+        let Some(next) = rule.next_after(anchor, Tz::UTC, anchor.max(now)) else {
+            return Ok(None);
+        };
+        rule.count = remaining;
+
+        let shift = next - anchor;
+        let patch = TaskPatch {
+            name: Some(task.name.clone()),
+            priority: Some(task.priority),
+            due_at: Some(task.due_at.map(|_| next)),
+            start_at: Some(task.start_at.map(|t| t + shift)),
+            parent_id: Some(task.parent_id),
+            recurrence: Some(Some(rule.to_string())),
+            ..Default::default()
+        };
+        let created = TaskMac::insert_in(conn, None, None, patch, client, Utc::now()).await?;
+        sqlx::query(Self::COPY_TAGS_SQL)
+            .bind(created.id)
+            .bind(task.id)
+            .execute(&mut *conn)
+            .await?;
+        TaskMac::get_in(conn, created.id).await.map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(y: i32, m: u32, d: u32, h: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, 0, 0).unwrap()
+    }
+
+    fn preview(rule: &str, start: DateTime<Utc>, n: usize) -> Vec<DateTime<Utc>> {
+        let rule: RRule = rule.parse().unwrap();
+        rule.occurrences(start, Tz::UTC).take(n).collect()
+    }
+
+    #[test]
+    fn parse_round_trip() {
+        for rule in [
+            "FREQ=WEEKLY;BYDAY=MO",
+            "FREQ=MONTHLY;INTERVAL=2;COUNT=3;BYDAY=-1FR",
+            "FREQ=YEARLY;UNTIL=20301231T235959Z;BYMONTHDAY=1,15;BYMONTH=1,7",
+        ] {
+            assert_eq!(rule.parse::<RRule>().unwrap().to_string(), rule);
+        }
+        assert_eq!(
+            "RRULE:freq=daily;WKST=MO"
+                .parse::<RRule>()
+                .unwrap()
+                .to_string(),
+            "FREQ=DAILY"
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        for rule in [
+            "",
+            "BYDAY=MO",
+            "FREQ=HOURLY",
+            "FREQ=DAILY;INTERVAL=0",
+            "FREQ=DAILY;INTERVAL=4000000000",
+            "FREQ=YEARLY;INTERVAL=400000000",
+            "FREQ=DAILY;BYSETPOS=1",
+            "FREQ=WEEKLY;BYDAY=1MO",
+            "FREQ=WEEKLY;BYMONTHDAY=1",
+            "FREQ=MONTHLY;BYMONTHDAY=32",
+            "FREQ=DAILY;COUNT=2;UNTIL=20300101",
+            "FREQ=DAILY;nonsense",
+            "FREQ=WEEKLY;BYDAY=éA",
+            "FREQ=WEEKLY;BYDAY=MÖ",
+            "FREQ=MONTHLY;BYDAY=1é",
+        ] {
+            assert!(
+                matches!(rule.parse::<RRule>(), Err(crate::Error::Validation(_))),
+                "{:?}",
+                rule
+            );
+        }
+    }
+
+    #[test]
+    fn weekly_on_mondays() {
+        // Wednesday March 6th, 2024
+        let start = utc(2024, 3, 6, 9);
+        assert_eq!(
+            preview("FREQ=WEEKLY;BYDAY=MO,WE", start, 3),
+            vec![utc(2024, 3, 6, 9), utc(2024, 3, 11, 9), utc(2024, 3, 13, 9)]
+        );
+        assert_eq!(
+            preview("FREQ=WEEKLY;INTERVAL=2", start, 2),
+            vec![utc(2024, 3, 6, 9), utc(2024, 3, 20, 9)]
+        );
+    }
+
+    #[test]
+    fn monthly_rules() {
+        let start = utc(2024, 1, 31, 9);
+        // Months without a 31st are skipped.
+        assert_eq!(
+            preview("FREQ=MONTHLY", start, 3),
+            vec![
+                utc(2024, 1, 31, 9),
+                utc(2024, 3, 31, 9),
+                utc(2024, 5, 31, 9)
+            ]
+        );
+        assert_eq!(
+            preview("FREQ=MONTHLY;BYMONTHDAY=-1", start, 2),
+            vec![utc(2024, 1, 31, 9), utc(2024, 2, 29, 9)]
+        );
+        assert_eq!(
+            preview("FREQ=MONTHLY;BYDAY=-1FR", start, 2),
+            vec![utc(2024, 2, 23, 9), utc(2024, 3, 29, 9)]
+        );
+        assert_eq!(
+            preview("FREQ=MONTHLY;BYDAY=2TU;COUNT=2", start, 10),
+            vec![utc(2024, 2, 13, 9), utc(2024, 3, 12, 9)]
+        );
+    }
+
+    #[test]
+    fn yearly_and_until() {
+        let start = utc(2024, 2, 29, 9);
+        assert_eq!(
+            preview("FREQ=YEARLY", start, 2),
+            vec![utc(2024, 2, 29, 9), utc(2028, 2, 29, 9)]
+        );
+        assert_eq!(
+            preview("FREQ=DAILY;UNTIL=20240302", start, 10),
+            vec![utc(2024, 2, 29, 9), utc(2024, 3, 1, 9), utc(2024, 3, 2, 9)]
+        );
+        assert!(preview("FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=30", start, 1).is_empty());
+    }
+
+    #[test]
+    fn end_of_calendar() {
+        // Iteration stops where the calendar ends, at the end of year 262142.
+        let start = utc(260_000, 1, 1, 9);
+        assert_eq!(
+            preview("FREQ=YEARLY;INTERVAL=1000", start, 5),
+            vec![
+                utc(260_000, 1, 1, 9),
+                utc(261_000, 1, 1, 9),
+                utc(262_000, 1, 1, 9)
+            ]
+        );
+        let start = utc(262_142, 12, 25, 9);
+        assert_eq!(preview("FREQ=WEEKLY;BYDAY=MO,FR", start, 5).len(), 2);
+        assert_eq!(preview("FREQ=DAILY;INTERVAL=1000", start, 5).len(), 1);
+    }
+
+    #[test]
+    fn local_time_across_dst() {
+        let tz: Tz = "Europe/Copenhagen".parse().unwrap();
+        let rule: RRule = "FREQ=WEEKLY".parse().unwrap();
+        // Monday March 25th, 2024, 09:00 CET. DST starts on the 31st.
+        let start = utc(2024, 3, 25, 8);
+        let occurrences: Vec<_> = rule.occurrences(start, tz).take(2).collect();
+        assert_eq!(occurrences, vec![utc(2024, 3, 25, 8), utc(2024, 4, 1, 7)]);
+    }
+
+    #[test]
+    fn next_after() {
+        let rule: RRule = "FREQ=WEEKLY;BYDAY=MO".parse().unwrap();
+        let start = utc(2024, 3, 4, 9);
+        assert_eq!(
+            rule.next_after(start, Tz::UTC, utc(2024, 3, 4, 9)),
+            Some(utc(2024, 3, 11, 9))
+        );
+        assert_eq!(
+            rule.next_after(start, Tz::UTC, utc(2024, 3, 20, 0)),
+            Some(utc(2024, 3, 25, 9))
+        );
+    }
+
+    /// Test that closing a recurring task creates the next occurrence until the
+    /// series runs out.
+    #[tokio::test]
+    async fn test_close_spawns_next_occurrence() -> Result<(), crate::Error> {
+        use crate::database::{create_and_connect, DbAddress};
+        use crate::model::tag::{TagMac, TagPatch};
+        use crate::model::task::TaskStatus;
+
+        // # Fixture
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let due = Utc.timestamp_opt(Utc::now().timestamp(), 0).unwrap() + Duration::days(1);
+        let task = TaskMac::insert(
+            &db,
+            TaskPatch {
+                name: Some("Water plants".to_string()),
+                due_at: Some(Some(due)),
+                start_at: Some(Some(due - Duration::hours(2))),
+                recurrence: Some(Some("freq=daily;count=2".to_string())),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(task.recurrence.as_deref(), Some("FREQ=DAILY;COUNT=2"));
+        let tag = TagMac::insert(
+            &db,
+            TagPatch {
+                name: "home".to_string(),
+            },
+        )
+        .await?;
+        TagMac::attach(&db, task.id, tag.id, None).await?;
+        let close = || TaskPatch {
+            status: Some(TaskStatus::Closed),
+            ..Default::default()
+        };
+
+        // # Action
+        let update = TaskMac::update(&db, task.id, close()).await?;
+
+        // # Check
+        assert_eq!(update.task.recurrence, None);
+        let next = update.next_occurrence.expect("a next occurrence");
+        assert_eq!(next.name, "Water plants");
+        assert_eq!(next.status, TaskStatus::Open);
+        assert_eq!(next.due_at, Some(due + Duration::days(1)));
+        assert_eq!(next.start_at, Some(due + Duration::hours(22)));
+        assert_eq!(next.recurrence.as_deref(), Some("FREQ=DAILY;COUNT=1"));
+        assert_eq!(next.tags, vec![tag]);
+
+        // The last occurrence ends the series.
+        let update = TaskMac::update(&db, next.id, close()).await?;
+        assert_eq!(update.next_occurrence, None);
+        Ok(())
+    }
+
+    /// Test that invalid rules are rejected when saving a task.
+    #[tokio::test]
+    async fn test_invalid_rule() -> Result<(), crate::Error> {
+        let db = crate::database::create_and_connect(crate::database::DbAddress::Memory).await?;
+        let result = TaskMac::insert(
+            &db,
+            TaskPatch {
+                name: Some("Nope".to_string()),
+                recurrence: Some(Some("FREQ=SECONDLY".to_string())),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(matches!(result, Err(crate::Error::Validation(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+//! Full-text search over task names, backed by the `tasks_fts` FTS5 index that
+//! triggers keep in sync with `tasks`.
+//!
+//! Queries are made of whitespace-separated terms that must all match. A term ending
+//! in `*` matches as a prefix and a double-quoted term matches as a phrase, e.g.
+//! `deploy back* "release notes"`. Everything else is matched literally, so FTS5
+//! operators typed by users cannot produce syntax errors.
+
+use super::tag::TagMac;
+use super::task::{task_columns, Task, TaskMac};
+use crate::database::Database;
+use serde::Serialize;
+use sqlx::FromRow;
+
+/// A task matching a search, best matches first.
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub task: Task,
+    /// HTML excerpt of the name: the text is escaped and matched terms are wrapped in
+    /// `<mark>` tags.
+    pub snippet: String,
+    /// BM25 relevance. Lower is better.
+    pub rank: f64,
+}
+
+/// Markers that FTS5 puts around matched terms in snippets, replaced by `<mark>` tags
+/// once the text is escaped.
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+
+/// Turn a snippet with match markers into HTML.
+fn snippet_html(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len());
+    for c in snippet.chars() {
+        match c {
+            MATCH_START => html.push_str("<mark>"),
+            MATCH_END => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+/// Translate a user query into an FTS5 match expression. Returns `None` if the query
+/// has no terms.
+fn match_expression(query: &str) -> Option<String> {
+    let mut terms = Vec::new();
+    let mut rest = query.trim();
+    while !rest.is_empty() {
+        let (term, phrase) = match rest.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                rest = quoted.get(end + 1..).unwrap_or("");
+                (&quoted[..end], true)
+            }
+            None => {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                let term = &rest[..end];
+                rest = &rest[end..];
+                (term, false)
+            }
+        };
+        rest = rest.trim_start();
+
+        let (term, prefix) = match term.strip_suffix('*') {
+            Some(stem) if !phrase => (stem, true),
+            _ => (term, false),
+        };
+        if term.trim().is_empty() {
+            continue;
+        }
+        let quoted = format!("\"{}\"", term.replace('"', "\"\""));
+        terms.push(if prefix { quoted + "*" } else { quoted });
+    }
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+impl TaskMac {
+    const SEARCH_SQL: &'static str = concat!(
+        r#"WITH hits AS (
+            SELECT rowid, rank, snippet(tasks_fts, 0, char(2), char(3), '…', 16) AS snippet
+            FROM tasks_fts WHERE tasks_fts MATCH ?
+        )
+        SELECT "#,
+        task_columns!(),
+        ", hits.snippet, hits.rank FROM tasks JOIN hits ON hits.rowid = tasks.id
+        WHERE tasks.deleted_at IS NULL ORDER BY hits.rank, tasks.id LIMIT ?"
+    );
+
+    /// Search task names, returning at most `limit` hits ranked by relevance.
+    pub async fn search(
+        db: &Database,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<SearchHit>, crate::Error> {
+        let expression = match_expression(query).ok_or_else(|| {
+            crate::Error::Validation("Search query must contain at least one term.".to_string())
+        })?;
+        let hits = sqlx::query_as::<_, SearchHit>(Self::SEARCH_SQL)
+            .bind(expression)
+            .bind(i64::try_from(limit).unwrap_or(i64::MAX))
+            .fetch_all(db)
+            .await?;
+
+        let (mut tasks, matches): (Vec<Task>, Vec<(String, f64)>) = hits
+            .into_iter()
+            .map(|hit| (hit.task, (hit.snippet, hit.rank)))
+            .unzip();
+        TagMac::load_for(db, &mut tasks).await?;
+        Ok(tasks
+            .into_iter()
+            .zip(matches)
+            .map(|(task, (snippet, rank))| SearchHit {
+                task,
+                snippet: snippet_html(&snippet),
+                rank,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use crate::model::task::TaskPatch;
+
+    async fn insert(db: &Database, name: &str) -> Result<Task, crate::Error> {
+        TaskMac::insert(
+            db,
+            TaskPatch {
+                name: Some(name.to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    fn names(hits: &[SearchHit]) -> Vec<&str> {
+        hits.iter().map(|h| h.task.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_match_expression() {
+        assert_eq!(match_expression("deploy"), Some(r#""deploy""#.to_string()));
+        assert_eq!(
+            match_expression(r#"back* "release notes""#),
+            Some(r#""back"* "release notes""#.to_string())
+        );
+        assert_eq!(
+            match_expression(r#"a OR b" NEAR("#),
+            Some(r#""a" "OR" "b""" "NEAR(""#.to_string())
+        );
+        assert_eq!(match_expression(r#"  * "" "#), None);
+    }
+
+    /// Test prefix and phrase queries, ranking and snippets.
+    #[tokio::test]
+    async fn test_search() -> Result<(), crate::Error> {
+        // # Fixture
+        let db = create_and_connect(DbAddress::Memory).await?;
+        insert(&db, "Write release notes").await?;
+        insert(&db, "Notes on the release of the backend").await?;
+        insert(&db, "Deploy backend").await?;
+        insert(&db, "Backup database").await?;
+
+        // # Check
+        let hits = TaskMac::search(&db, "back*", 10).await?;
+        assert_eq!(hits.len(), 3);
+        let hits = TaskMac::search(&db, "\"release notes\"", 10).await?;
+        assert_eq!(names(&hits), vec!["Write release notes"]);
+        assert_eq!(hits[0].snippet, "Write <mark>release notes</mark>");
+
+        let hits = TaskMac::search(&db, "release notes", 10).await?;
+        assert_eq!(hits.len(), 2);
+        assert!(hits[0].rank <= hits[1].rank);
+        assert_eq!(hits[0].task.name, "Write release notes");
+
+        assert!(matches!(
+            TaskMac::search(&db, "  ", 10).await,
+            Err(crate::Error::Validation(_))
+        ));
+        assert_eq!(TaskMac::search(&db, "deploy", usize::MAX).await?.len(), 1);
+        Ok(())
+    }
+
+    /// Test that names are escaped in snippets.
+    #[tokio::test]
+    async fn test_snippet_escaping() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        insert(&db, "Fix <img src=x onerror=alert(1)> & \"quotes\"").await?;
+
+        let hits = TaskMac::search(&db, "fix", 10).await?;
+        assert_eq!(
+            hits[0].snippet,
+            "<mark>Fix</mark> &lt;img src=x onerror=alert(1)&gt; &amp; &quot;quotes&quot;"
+        );
+        Ok(())
+    }
+
+    /// Test that the index follows renames and deletions.
+    #[tokio::test]
+    async fn test_index_sync() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let task = insert(&db, "Draft proposal").await?;
+
+        TaskMac::update(
+            &db,
+            task.id,
+            TaskPatch {
+                name: Some("Final proposal".to_string()),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert!(TaskMac::search(&db, "draft", 10).await?.is_empty());
+        assert_eq!(TaskMac::search(&db, "final", 10).await?.len(), 1);
+
+        TaskMac::delete(&db, task.id).await?;
+        assert!(TaskMac::search(&db, "proposal", 10).await?.is_empty());
+        Ok(())
+    }
+}
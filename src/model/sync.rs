@@ -0,0 +1,428 @@
+//! Delta sync for offline clients. A client sends the sync token of its last sync,
+//! and receives the current state of every task changed since, plus tombstones for
+//! tasks that were deleted. Mutations made offline are merged field by field: a field
+//! keeps the value that was written last, going by the time the client made the
+//! change and the time the server last changed the field. The server wins ties, and
+//! client times in the future count as now.
+
+use super::events::EventMac;
+use super::hierarchy::ChildPolicy;
+use super::tag::TagMac;
+use super::task::{task_columns, Task, TaskMac, TaskPatch};
+use crate::database::Database;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::SqliteConnection;
+
+/// A change a client made while offline.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Mutation {
+    Create {
+        /// When the client made the change.
+        at: DateTime<Utc>,
+        fields: TaskPatch,
+    },
+    Update {
+        id: i64,
+        at: DateTime<Utc>,
+        fields: TaskPatch,
+    },
+    Delete {
+        id: i64,
+        at: DateTime<Utc>,
+        #[serde(default)]
+        children: ChildPolicy,
+    },
+}
+
+impl Mutation {
+    /// The task that the mutation changes, unless it creates one.
+    pub fn task_id(&self) -> Option<i64> {
+        match self {
+            Mutation::Create { .. } => None,
+            Mutation::Update { id, .. } | Mutation::Delete { id, .. } => Some(*id),
+        }
+    }
+}
+
+/// What became of a mutation, by its position in the batch.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MutationResult {
+    pub index: usize,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum Outcome {
+    /// The mutation was applied. `ignored` lists the fields that the server changed
+    /// later than the client, which keep the server value.
+    Applied { id: i64, ignored: Vec<String> },
+    /// The mutation was not applied at all.
+    Rejected { id: Option<i64>, reason: String },
+}
+
+/// Changes since a sync token, and the token to use next time.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SyncResponse {
+    pub token: String,
+    /// Current state of the tasks that were created or changed.
+    pub tasks: Vec<Task>,
+    /// Ids of the tasks that were deleted.
+    pub tombstones: Vec<i64>,
+    /// Outcome of each submitted mutation, in order.
+    pub results: Vec<MutationResult>,
+}
+
+/// Sync model access controller.
+pub struct SyncMac;
+
+impl SyncMac {
+    const CHANGED_SQL: &'static str =
+        "SELECT DISTINCT task_id FROM task_history WHERE id > ? AND id <= ? ORDER BY task_id";
+    const LIVE_SQL: &'static str = concat!(
+        "SELECT ",
+        task_columns!(),
+        " FROM tasks WHERE deleted_at IS NULL AND id IN (SELECT value FROM json_each(?)) ORDER BY id"
+    );
+    const ALL_SQL: &'static str = concat!(
+        "SELECT ",
+        task_columns!(),
+        " FROM tasks WHERE deleted_at IS NULL ORDER BY id"
+    );
+    const FIELD_TIMES_SQL: &'static str =
+        "SELECT field_times, modified_at FROM tasks WHERE id = ? AND deleted_at IS NULL";
+
+    /// Apply a batch of mutations in order, then return the changes since `token`.
+    /// Without a token, all tasks are returned.
+    pub async fn sync(
+        db: &Database,
+        token: Option<&str>,
+        mutations: Vec<Mutation>,
+        client: Option<&str>,
+    ) -> Result<SyncResponse, crate::Error> {
+        let after = match token {
+            Some(token) => Some(token.parse::<i64>().map_err(|_| {
+                crate::Error::Validation(format!("Invalid sync token {:?}.", token))
+            })?),
+            None => None,
+        };
+        let mut results = Vec::with_capacity(mutations.len());
+        for (index, mutation) in mutations.into_iter().enumerate() {
+            let id = mutation.task_id();
+            let outcome = match Self::apply(db, mutation, client).await {
+                Ok(outcome) => outcome,
+                Err(crate::Error::Internal(e)) => return Err(crate::Error::Internal(e)),
+                Err(e) => Outcome::Rejected {
+                    id,
+                    reason: e.to_string(),
+                },
+            };
+            results.push(MutationResult { index, outcome });
+        }
+
+        let latest = EventMac::latest_id(db).await?;
+        let (mut tasks, tombstones) = match after {
+            Some(after) => {
+                let changed: Vec<i64> = sqlx::query_scalar(Self::CHANGED_SQL)
+                    .bind(after)
+                    .bind(latest)
+                    .fetch_all(db)
+                    .await?;
+                let tasks = sqlx::query_as::<_, Task>(Self::LIVE_SQL)
+                    .bind(serde_json::to_string(&changed).unwrap_or_default())
+                    .fetch_all(db)
+                    .await?;
+                let tombstones = changed
+                    .into_iter()
+                    .filter(|id| !tasks.iter().any(|t| t.id == *id))
+                    .collect();
+                (tasks, tombstones)
+            }
+            None => {
+                let tasks = sqlx::query_as::<_, Task>(Self::ALL_SQL)
+                    .fetch_all(db)
+                    .await?;
+                (tasks, Vec::new())
+            }
+        };
+        TagMac::load_for(db, &mut tasks).await?;
+        Ok(SyncResponse {
+            token: latest.to_string(),
+            tasks,
+            tombstones,
+            results,
+        })
+    }
+
+    /// Apply one mutation, in a transaction of its own. A time in the future counts
+    /// as now, and a field that the server changed at the same time as the client keeps
+    /// the server value.
+    async fn apply(
+        db: &Database,
+        mutation: Mutation,
+        client: Option<&str>,
+    ) -> Result<Outcome, crate::Error> {
+        let now = Utc::now();
+        let mut tx = db.begin().await?;
+        let outcome = match mutation {
+            Mutation::Create { at, mut fields } => {
+                fields.version = None;
+                let task =
+                    TaskMac::insert_in(&mut tx, None, None, fields, client, at.min(now)).await?;
+                Outcome::Applied {
+                    id: task.id,
+                    ignored: Vec::new(),
+                }
+            }
+            Mutation::Update { id, at, mut fields } => {
+                let at = at.min(now);
+                let mut ignored = Vec::new();
+                for (field, time) in Self::field_times(&mut tx, id).await? {
+                    if server_wins(time, at) && clear_field(&mut fields, &field) {
+                        ignored.push(field);
+                    }
+                }
+                fields.version = None;
+                TaskMac::update_in(&mut tx, id, fields, client, at).await?;
+                Outcome::Applied { id, ignored }
+            }
+            Mutation::Delete { id, at, children } => {
+                let at = at.min(now);
+                let times = Self::field_times(&mut tx, id).await?;
+                if let Some((field, _)) = times.into_iter().find(|(_, time)| server_wins(*time, at))
+                {
+                    return Ok(Outcome::Rejected {
+                        id: Some(id),
+                        reason: format!(
+                            "Task {} was changed ({}) after it was deleted.",
+                            id, field
+                        ),
+                    });
+                }
+                TaskMac::delete_in(&mut tx, id, children, client).await?;
+                Outcome::Applied {
+                    id,
+                    ignored: Vec::new(),
+                }
+            }
+        };
+        tx.commit().await?;
+        EventMac::notify(db);
+        Ok(outcome)
+    }
+
+    /// Last modification time of each tracked field of a task, in milliseconds. Fields
+    /// without a recorded time count as modified with the task.
+    async fn field_times(
+        conn: &mut SqliteConnection,
+        id: i64,
+    ) -> Result<Vec<(String, i64)>, crate::Error> {
+        let (times, modified_at): (String, i64) = sqlx::query_as(Self::FIELD_TIMES_SQL)
+            .bind(id)
+            .fetch_optional(&mut *conn)
+            .await?
+            .ok_or_else(|| crate::Error::NotFound(format!("Task {} not found.", id)))?;
+        let times: Map<String, Value> = serde_json::from_str(&times).unwrap_or_default();
+        Ok(super::history::TRACKED_FIELDS
+            .iter()
+            .map(|field| {
+                let time = times.get(*field).and_then(Value::as_i64);
+                (field.to_string(), time.unwrap_or(modified_at * 1000))
+            })
+            .collect())
+    }
+}
+
+/// Whether a server change at `time`, in milliseconds, wins over a client change at
+/// `at`. Ties go to the server.
+fn server_wins(time: i64, at: DateTime<Utc>) -> bool {
+    time >= at.timestamp_millis()
+}
+
+/// Drop a field from a patch. Returns whether it was set.
+fn clear_field(patch: &mut TaskPatch, field: &str) -> bool {
+    match field {
+        "name" => patch.name.take().is_some(),
+        "status" => patch.status.take().is_some(),
+        "due_at" => patch.due_at.take().is_some(),
+        "start_at" => patch.start_at.take().is_some(),
+        "priority" => patch.priority.take().is_some(),
+        "parent_id" => patch.parent_id.take().is_some(),
+        "recurrence" => patch.recurrence.take().is_some(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use crate::model::tag::TagPatch;
+    use crate::model::task::TaskStatus;
+    use chrono::Duration;
+
+    fn named(name: &str) -> TaskPatch {
+        TaskPatch {
+            name: Some(name.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Test that changes since a token include updates and tombstones.
+    #[tokio::test]
+    async fn test_changes_since() -> Result<(), crate::Error> {
+        // # Fixture
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let kept = TaskMac::insert(&db, named("Kept")).await?;
+        let gone = TaskMac::insert(&db, named("Gone")).await?;
+        let first = SyncMac::sync(&db, None, Vec::new(), None).await?;
+        assert_eq!(first.tasks.len(), 2);
+
+        // # Action
+        TaskMac::update(&db, kept.id, named("Kept and renamed")).await?;
+        TaskMac::delete(&db, gone.id).await?;
+        let second = SyncMac::sync(&db, Some(&first.token), Vec::new(), None).await?;
+
+        // # Check
+        assert_eq!(second.tasks.len(), 1);
+        assert_eq!(second.tasks[0].name, "Kept and renamed");
+        assert_eq!(second.tombstones, vec![gone.id]);
+
+        let third = SyncMac::sync(&db, Some(&second.token), Vec::new(), None).await?;
+        assert!(third.tasks.is_empty() && third.tombstones.is_empty());
+        assert!(matches!(
+            SyncMac::sync(&db, Some("yesterday"), Vec::new(), None).await,
+            Err(crate::Error::Validation(_))
+        ));
+        Ok(())
+    }
+
+    /// Test that attaching a tag counts as a change since a token.
+    #[tokio::test]
+    async fn test_tag_changes_since() -> Result<(), crate::Error> {
+        // # Fixture
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let task = TaskMac::insert(&db, named("Tagged later")).await?;
+        let tag = TagMac::insert(
+            &db,
+            TagPatch {
+                name: "home".to_string(),
+            },
+        )
+        .await?;
+        let first = SyncMac::sync(&db, None, Vec::new(), None).await?;
+
+        // # Action
+        TagMac::attach(&db, task.id, tag.id, None).await?;
+        let second = SyncMac::sync(&db, Some(&first.token), Vec::new(), None).await?;
+
+        // # Check
+        assert_eq!(second.tasks.len(), 1);
+        assert_eq!(second.tasks[0].id, task.id);
+        assert_eq!(second.tasks[0].tags[0].name, "home");
+        Ok(())
+    }
+
+    /// Test that a client time in the future counts as now, so that it cannot hold a
+    /// field against later server changes.
+    #[tokio::test]
+    async fn test_future_time_clamped() -> Result<(), crate::Error> {
+        // # Fixture
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let task = TaskMac::insert(&db, named("Draft")).await?;
+        let update = Mutation::Update {
+            id: task.id,
+            at: Utc::now() + Duration::days(365),
+            fields: named("From the future"),
+        };
+        SyncMac::sync(&db, None, vec![update], Some("phone")).await?;
+        // Ties go to the server, so let the clock move on.
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+
+        // # Action
+        let update = Mutation::Update {
+            id: task.id,
+            at: Utc::now() + Duration::seconds(1),
+            fields: named("From now"),
+        };
+        let response = SyncMac::sync(&db, None, vec![update], Some("laptop")).await?;
+
+        // # Check
+        assert_eq!(
+            response.results[0].outcome,
+            Outcome::Applied {
+                id: task.id,
+                ignored: Vec::new()
+            }
+        );
+        assert_eq!(TaskMac::get(&db, task.id).await?.name, "From now");
+        Ok(())
+    }
+
+    /// Test per-field last-writer-wins merging of offline mutations.
+    #[tokio::test]
+    async fn test_last_writer_wins() -> Result<(), crate::Error> {
+        // # Fixture
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let before = Utc::now() - Duration::hours(1);
+        let task =
+            TaskMac::insert_at(&db, named("Draft"), None, before - Duration::hours(1)).await?;
+        // The server renames the task after the client went offline.
+        TaskMac::update(&db, task.id, named("Server name")).await?;
+
+        // # Action
+        let mutations = vec![
+            Mutation::Update {
+                id: task.id,
+                at: before,
+                fields: TaskPatch {
+                    name: Some("Offline name".to_string()),
+                    status: Some(TaskStatus::Closed),
+                    ..Default::default()
+                },
+            },
+            Mutation::Create {
+                at: before,
+                fields: named("Made offline"),
+            },
+            Mutation::Update {
+                id: 999,
+                at: Utc::now(),
+                fields: named("Nobody"),
+            },
+            Mutation::Delete {
+                id: task.id,
+                at: before,
+                children: ChildPolicy::Refuse,
+            },
+        ];
+        let response = SyncMac::sync(&db, None, mutations, Some("phone")).await?;
+
+        // # Check
+        let outcomes: Vec<&Outcome> = response.results.iter().map(|r| &r.outcome).collect();
+        assert_eq!(
+            outcomes[0],
+            &Outcome::Applied {
+                id: task.id,
+                ignored: vec!["name".to_string()]
+            }
+        );
+        assert!(matches!(outcomes[1], Outcome::Applied { .. }));
+        assert!(matches!(
+            outcomes[2],
+            Outcome::Rejected { id: Some(999), .. }
+        ));
+        // Closing the task happened after the delete was made.
+        assert!(matches!(outcomes[3], Outcome::Rejected { id: Some(_), .. }));
+
+        let merged = TaskMac::get(&db, task.id).await?;
+        assert_eq!(merged.name, "Server name");
+        assert_eq!(merged.status, TaskStatus::Closed);
+        assert_eq!(response.tasks.len(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,538 @@
+use super::events::EventMac;
+use super::history::HistoryMac;
+use super::task::{Task, TaskMac};
+use crate::database::Database;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{FromRow, SqliteConnection, SqliteExecutor};
+use std::collections::HashMap;
+
+/// Tag model. Tag names are unique, ignoring case.
+#[derive(Debug, Default, FromRow, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tag {
+    pub id: i64,
+    pub name: String,
+}
+
+/// Patch type for creating or renaming a tag.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct TagPatch {
+    pub name: String,
+}
+
+/// How several tags are combined when listing tasks by tag.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagMatch {
+    /// Tasks carrying every tag.
+    #[default]
+    All,
+    /// Tasks carrying at least one of the tags.
+    Any,
+}
+
+/// Tag model access controller.
+pub struct TagMac;
+
+impl TagMac {
+    const INSERT_SQL: &'static str = "INSERT INTO tags (name) VALUES (?) RETURNING id, name";
+    const GET_SQL: &'static str = "SELECT id, name FROM tags WHERE id = ?";
+    const LIST_SQL: &'static str = "SELECT id, name FROM tags ORDER BY name";
+    const RENAME_SQL: &'static str = "UPDATE tags SET name = ? WHERE id = ? RETURNING id, name";
+    const DELETE_SQL: &'static str = "DELETE FROM tags WHERE id = ?";
+    const ATTACH_SQL: &'static str =
+        "INSERT OR IGNORE INTO task_tags (task_id, tag_id) VALUES (?, ?)";
+    const DETACH_SQL: &'static str = "DELETE FROM task_tags WHERE task_id = ? AND tag_id = ?";
+    const DETACH_ALL_SQL: &'static str = "DELETE FROM task_tags WHERE task_id = ?";
+    const ENSURE_SQL: &'static str = "INSERT OR IGNORE INTO tags (name) VALUES (?)";
+    const ATTACH_NAMED_SQL: &'static str =
+        "INSERT OR IGNORE INTO task_tags (task_id, tag_id) SELECT ?, id FROM tags WHERE name = ?";
+    const MERGE_LINKS_SQL: &'static str = r#"INSERT OR IGNORE INTO task_tags (task_id, tag_id)
+        SELECT task_id, ? FROM task_tags WHERE tag_id = ?"#;
+    const TAGS_FOR_TASKS_SQL: &'static str = r#"SELECT tt.task_id, g.id, g.name
+        FROM task_tags tt JOIN tags g ON g.id = tt.tag_id
+        WHERE tt.task_id IN (SELECT value FROM json_each(?))
+        ORDER BY g.name"#;
+    const TASKS_FOR_TAG_SQL: &'static str =
+        "SELECT task_id FROM task_tags WHERE tag_id = ? ORDER BY task_id";
+    const NAMES_FOR_TASK_SQL: &'static str = r#"SELECT g.name
+        FROM task_tags tt JOIN tags g ON g.id = tt.tag_id
+        WHERE tt.task_id = ? ORDER BY g.name"#;
+
+    /// Create a new tag.
+    pub async fn insert(db: &Database, data: TagPatch) -> Result<Tag, crate::Error> {
+        let name = Self::validate(&data)?;
+        let tag = sqlx::query_as::<_, Tag>(Self::INSERT_SQL)
+            .bind(name)
+            .fetch_one(db)
+            .await
+            .map_err(name_taken(name))?;
+        Ok(tag)
+    }
+
+    /// Get a tag by id.
+    pub async fn get(db: &Database, id: i64) -> Result<Tag, crate::Error> {
+        let tag = sqlx::query_as::<_, Tag>(Self::GET_SQL)
+            .bind(id)
+            .fetch_one(db)
+            .await
+            .map_err(not_found(id))?;
+        Ok(tag)
+    }
+
+    /// List all tags by name.
+    pub async fn list(db: &Database) -> Result<Vec<Tag>, crate::Error> {
+        let tags = sqlx::query_as::<_, Tag>(Self::LIST_SQL).fetch_all(db).await?;
+        Ok(tags)
+    }
+
+    /// Rename a tag on behalf of `client`, and record the change in the history of
+    /// every task carrying it. Renaming onto an existing name is a conflict; use
+    /// [`TagMac::merge`].
+    pub async fn rename(
+        db: &Database,
+        id: i64,
+        data: TagPatch,
+        client: Option<&str>,
+    ) -> Result<Tag, crate::Error> {
+        let name = Self::validate(&data)?;
+        let mut tx = db.begin().await?;
+        let affected = Self::names_by_task(&mut tx, id).await?;
+        let tag = sqlx::query_as::<_, Tag>(Self::RENAME_SQL)
+            .bind(name)
+            .bind(id)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => not_found(id)(e),
+                e => name_taken(name)(e),
+            })?;
+        Self::record_changes(&mut tx, affected, client).await?;
+        tx.commit().await?;
+        EventMac::notify(db);
+        Ok(tag)
+    }
+
+    /// Merge tag `id` into tag `into` on behalf of `client`. Tasks carrying the former
+    /// carry the latter afterwards, and the former is deleted.
+    pub async fn merge(
+        db: &Database,
+        id: i64,
+        into: i64,
+        client: Option<&str>,
+    ) -> Result<Tag, crate::Error> {
+        if id == into {
+            return Err(crate::Error::Validation(
+                "Cannot merge a tag into itself.".to_string(),
+            ));
+        }
+        Self::get(db, id).await?;
+        let target = Self::get(db, into).await?;
+
+        let mut tx = db.begin().await?;
+        let affected = Self::names_by_task(&mut tx, id).await?;
+        sqlx::query(Self::MERGE_LINKS_SQL)
+            .bind(into)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(Self::DELETE_SQL).bind(id).execute(&mut *tx).await?;
+        Self::record_changes(&mut tx, affected, client).await?;
+        tx.commit().await?;
+        EventMac::notify(db);
+        Ok(target)
+    }
+
+    /// Delete a tag on behalf of `client`. It is removed from all tasks.
+    pub async fn delete(db: &Database, id: i64, client: Option<&str>) -> Result<(), crate::Error> {
+        let mut tx = db.begin().await?;
+        let affected = Self::names_by_task(&mut tx, id).await?;
+        let result = sqlx::query(Self::DELETE_SQL).bind(id).execute(&mut *tx).await?;
+        if result.rows_affected() == 0 {
+            return Err(not_found(id)(sqlx::Error::RowNotFound));
+        }
+        Self::record_changes(&mut tx, affected, client).await?;
+        tx.commit().await?;
+        EventMac::notify(db);
+        Ok(())
+    }
+
+    /// Attach a tag to a task. Attaching a tag twice has no effect.
+    pub async fn attach(
+        db: &Database,
+        task_id: i64,
+        tag_id: i64,
+        client: Option<&str>,
+    ) -> Result<Task, crate::Error> {
+        Self::change_link(db, Self::ATTACH_SQL, task_id, tag_id, client).await
+    }
+
+    /// Detach a tag from a task.
+    pub async fn detach(
+        db: &Database,
+        task_id: i64,
+        tag_id: i64,
+        client: Option<&str>,
+    ) -> Result<Task, crate::Error> {
+        Self::change_link(db, Self::DETACH_SQL, task_id, tag_id, client).await
+    }
+
+    /// Attach or detach a tag, and record the change in the history of the task.
+    async fn change_link(
+        db: &Database,
+        sql: &str,
+        task_id: i64,
+        tag_id: i64,
+        client: Option<&str>,
+    ) -> Result<Task, crate::Error> {
+        TaskMac::get(db, task_id).await?;
+        Self::get(db, tag_id).await?;
+        let mut tx = db.begin().await?;
+        let before = Self::names_for(&mut tx, task_id).await?;
+        sqlx::query(sql)
+            .bind(task_id)
+            .bind(tag_id)
+            .execute(&mut *tx)
+            .await?;
+        Self::record_change(&mut tx, task_id, before, client).await?;
+        tx.commit().await?;
+        EventMac::notify(db);
+        TaskMac::get(db, task_id).await
+    }
+
+    /// Names of the tags of a task.
+    async fn names_for(
+        conn: &mut SqliteConnection,
+        task_id: i64,
+    ) -> Result<Vec<String>, crate::Error> {
+        let names = sqlx::query_scalar(Self::NAMES_FOR_TASK_SQL)
+            .bind(task_id)
+            .fetch_all(conn)
+            .await?;
+        Ok(names)
+    }
+
+    /// The tasks carrying a tag, with the names of all their tags.
+    async fn names_by_task(
+        conn: &mut SqliteConnection,
+        tag_id: i64,
+    ) -> Result<Vec<(i64, Vec<String>)>, crate::Error> {
+        let task_ids: Vec<i64> = sqlx::query_scalar(Self::TASKS_FOR_TAG_SQL)
+            .bind(tag_id)
+            .fetch_all(&mut *conn)
+            .await?;
+        let mut affected = Vec::with_capacity(task_ids.len());
+        for task_id in task_ids {
+            affected.push((task_id, Self::names_for(conn, task_id).await?));
+        }
+        Ok(affected)
+    }
+
+    /// Record in the history of each task that its tags changed from the given ones.
+    async fn record_changes(
+        conn: &mut SqliteConnection,
+        affected: Vec<(i64, Vec<String>)>,
+        client: Option<&str>,
+    ) -> Result<(), crate::Error> {
+        for (task_id, before) in affected {
+            Self::record_change(conn, task_id, before, client).await?;
+        }
+        Ok(())
+    }
+
+    /// Record in the history of a task that its tags changed from `before`.
+    async fn record_change(
+        conn: &mut SqliteConnection,
+        task_id: i64,
+        before: Vec<String>,
+        client: Option<&str>,
+    ) -> Result<(), crate::Error> {
+        let after = Self::names_for(conn, task_id).await?;
+        HistoryMac::record_relation(conn, task_id, "tags", json!(before), json!(after), client)
+            .await
+    }
+
+    /// Normalize tag names for matching. Returns the names as a JSON array, and how
+    /// many of them a task must carry.
+    pub(super) fn match_names(names: &[String], matching: TagMatch) -> (String, i64) {
+        // Names compare case-insensitively, so duplicates would never all match. Like
+        // `COLLATE NOCASE`, only ASCII letters are folded.
+        let mut names: Vec<String> = names
+            .iter()
+            .map(|n| n.trim().to_ascii_lowercase())
+            .collect();
+        names.sort();
+        names.dedup();
+        let required = match matching {
+            TagMatch::All => names.len() as i64,
+            TagMatch::Any => 1,
+        };
+        let names = serde_json::to_string(&names).expect("names serialize to JSON");
+        (names, required)
+    }
+
+    /// Replace the tags of a task with the named ones, creating missing tags, within
+    /// the transaction of the caller.
+    pub(super) async fn set_for_in(
+        conn: &mut SqliteConnection,
+        task_id: i64,
+        names: &[String],
+        client: Option<&str>,
+    ) -> Result<(), crate::Error> {
+        let mut names = names
+            .iter()
+            .map(|name| Self::validate(&TagPatch { name: name.clone() }).map(str::to_string))
+            .collect::<Result<Vec<_>, _>>()?;
+        names.dedup();
+        let before = Self::names_for(conn, task_id).await?;
+        sqlx::query(Self::DETACH_ALL_SQL)
+            .bind(task_id)
+            .execute(&mut *conn)
+            .await?;
+        for name in &names {
+            sqlx::query(Self::ENSURE_SQL)
+                .bind(name)
+                .execute(&mut *conn)
+                .await?;
+            sqlx::query(Self::ATTACH_NAMED_SQL)
+                .bind(task_id)
+                .bind(name)
+                .execute(&mut *conn)
+                .await?;
+        }
+        Self::record_change(conn, task_id, before, client).await
+    }
+
+    /// Fill in the tags of the given tasks.
+    pub async fn load_for(
+        db: impl SqliteExecutor<'_>,
+        tasks: &mut [Task],
+    ) -> Result<(), crate::Error> {
+        if tasks.is_empty() {
+            return Ok(());
+        }
+        let ids: Vec<i64> = tasks.iter().map(|t| t.id).collect();
+        let rows = sqlx::query_as::<_, (i64, i64, String)>(Self::TAGS_FOR_TASKS_SQL)
+            .bind(serde_json::to_string(&ids).expect("ids serialize to JSON"))
+            .fetch_all(db)
+            .await?;
+
+        let mut by_task: HashMap<i64, Vec<Tag>> = HashMap::new();
+        for (task_id, id, name) in rows {
+            by_task.entry(task_id).or_default().push(Tag { id, name });
+        }
+        for task in tasks {
+            task.tags = by_task.remove(&task.id).unwrap_or_default();
+        }
+        Ok(())
+    }
+
+    /// Check a tag patch and return the normalized name.
+    pub(super) fn validate(data: &TagPatch) -> Result<&str, crate::Error> {
+        let name = data.name.trim();
+        if name.is_empty() {
+            return Err(crate::Error::Validation(
+                "Tag name must not be empty.".to_string(),
+            ));
+        }
+        if name.contains(',') {
+            return Err(crate::Error::Validation(
+                "Tag name must not contain commas.".to_string(),
+            ));
+        }
+        Ok(name)
+    }
+}
+
+/// Map a missing row to a `NotFound` error for the tag with the given id.
+fn not_found(id: i64) -> impl FnOnce(sqlx::Error) -> crate::Error {
+    move |e| match e {
+        sqlx::Error::RowNotFound => crate::Error::NotFound(format!("Tag {} not found.", id)),
+        e => e.into(),
+    }
+}
+
+/// Map a unique violation of the tag name to a conflict naming the tag.
+fn name_taken(name: &str) -> impl FnOnce(sqlx::Error) -> crate::Error + '_ {
+    move |e| match e {
+        sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+            crate::Error::Conflict(format!("Tag {:?} already exists.", name))
+        }
+        e => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use crate::model::task::TaskPatch;
+
+    async fn insert_task(db: &Database, name: &str) -> Result<Task, crate::Error> {
+        TaskMac::insert(
+            db,
+            TaskPatch {
+                name: Some(name.to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    async fn insert_tag(db: &Database, name: &str) -> Result<Tag, crate::Error> {
+        TagMac::insert(
+            db,
+            TagPatch {
+                name: name.to_string(),
+            },
+        )
+        .await
+    }
+
+    /// Test that tag names are unique regardless of case.
+    #[tokio::test]
+    async fn test_insert_and_rename() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let backend = insert_tag(&db, " backend ").await?;
+        assert_eq!(backend.name, "backend");
+        let frontend = insert_tag(&db, "frontend").await?;
+
+        assert_eq!(
+            insert_tag(&db, "Backend").await.unwrap_err().to_string(),
+            "Tag \"Backend\" already exists."
+        );
+        let renamed = TagMac::rename(
+            &db,
+            frontend.id,
+            TagPatch {
+                name: "BACKEND".to_string(),
+            },
+            None,
+        )
+        .await;
+        assert!(matches!(renamed, Err(crate::Error::Conflict(_))));
+
+        let renamed = TagMac::rename(
+            &db,
+            frontend.id,
+            TagPatch {
+                name: "ui".to_string(),
+            },
+            None,
+        )
+        .await?;
+        assert_eq!(TagMac::get(&db, frontend.id).await?, renamed);
+        Ok(())
+    }
+
+    /// Test attaching and detaching tags, and that they are embedded in tasks.
+    #[tokio::test]
+    async fn test_attach_detach() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let task = insert_task(&db, "Hello world").await?;
+        let tag = insert_tag(&db, "backend").await?;
+
+        let tagged = TagMac::attach(&db, task.id, tag.id, None).await?;
+        assert_eq!(tagged.tags, vec![tag.clone()]);
+        // Attaching twice is a no-op.
+        TagMac::attach(&db, task.id, tag.id, None).await?;
+        assert_eq!(TaskMac::get(&db, task.id).await?.tags, vec![tag.clone()]);
+        assert_eq!(TaskMac::list(&db).await?[0].tags, vec![tag.clone()]);
+
+        let untagged = TagMac::detach(&db, task.id, tag.id, None).await?;
+        assert!(untagged.tags.is_empty());
+
+        assert!(matches!(
+            TagMac::attach(&db, task.id, 42, None).await,
+            Err(crate::Error::NotFound(_))
+        ));
+        Ok(())
+    }
+
+    /// Test merging one tag into another.
+    #[tokio::test]
+    async fn test_merge() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let one = insert_task(&db, "One").await?;
+        let two = insert_task(&db, "Two").await?;
+        let api = insert_tag(&db, "api").await?;
+        let backend = insert_tag(&db, "backend").await?;
+        TagMac::attach(&db, one.id, api.id, None).await?;
+        TagMac::attach(&db, two.id, api.id, None).await?;
+        TagMac::attach(&db, two.id, backend.id, None).await?;
+
+        TagMac::merge(&db, api.id, backend.id, None).await?;
+
+        assert_eq!(TagMac::list(&db).await?, vec![backend.clone()]);
+        assert_eq!(TaskMac::get(&db, one.id).await?.tags, vec![backend.clone()]);
+        assert_eq!(TaskMac::get(&db, two.id).await?.tags, vec![backend.clone()]);
+        Ok(())
+    }
+
+    /// Test that renaming, merging and deleting a tag are recorded in the history of
+    /// the tasks carrying it, and notified.
+    #[tokio::test]
+    async fn test_tag_changes_recorded() -> Result<(), crate::Error> {
+        // # Fixture
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let task = insert_task(&db, "Hello world").await?;
+        let other = insert_task(&db, "Untagged").await?;
+        let api = insert_tag(&db, "api").await?;
+        let backend = insert_tag(&db, "backend").await?;
+        TagMac::attach(&db, task.id, api.id, None).await?;
+        let mut changes = EventMac::subscribe(&db);
+        changes.mark_unchanged();
+
+        // # Action
+        let rename = TagPatch {
+            name: "rest".to_string(),
+        };
+        TagMac::rename(&db, api.id, rename, Some("alice")).await?;
+        assert!(changes.has_changed().unwrap());
+        TagMac::merge(&db, api.id, backend.id, Some("bob")).await?;
+        TagMac::delete(&db, backend.id, None).await?;
+
+        // # Check
+        let history = HistoryMac::for_task(&db, task.id).await?;
+        let entries: Vec<_> = history[2..]
+            .iter()
+            .map(|e| json!([e.old_values, e.new_values, e.client]))
+            .collect();
+        assert_eq!(
+            json!(entries),
+            json!([
+                [{"tags": ["api"]}, {"tags": ["rest"]}, "alice"],
+                [{"tags": ["rest"]}, {"tags": ["backend"]}, "bob"],
+                [{"tags": ["backend"]}, {"tags": []}, null],
+            ])
+        );
+        assert_eq!(HistoryMac::for_task(&db, other.id).await?.len(), 1);
+        Ok(())
+    }
+
+    /// Test that deleting a tag removes it from tasks, and purging a task its links.
+    #[tokio::test]
+    async fn test_delete() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let task = insert_task(&db, "Hello world").await?;
+        let tag = insert_tag(&db, "backend").await?;
+        TagMac::attach(&db, task.id, tag.id, None).await?;
+
+        TagMac::delete(&db, tag.id, None).await?;
+        assert!(TaskMac::get(&db, task.id).await?.tags.is_empty());
+        assert!(matches!(
+            TagMac::delete(&db, tag.id, None).await,
+            Err(crate::Error::NotFound(_))
+        ));
+
+        let tag = insert_tag(&db, "backend").await?;
+        TagMac::attach(&db, task.id, tag.id, None).await?;
+        TaskMac::delete(&db, task.id).await?;
+        TaskMac::purge(&db, task.id, None).await?;
+        let links: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM task_tags")
+            .fetch_one(&db)
+            .await?;
+        assert_eq!(links, 0);
+        Ok(())
+    }
+}
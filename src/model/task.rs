@@ -1,18 +1,44 @@
+use super::events::EventMac;
+use super::hierarchy::ChildPolicy;
+use super::history::{HistoryMac, TRACKED_FIELDS};
+use super::ranking::{self, RankedTask};
+use super::recurrence::RRule;
+use super::tag::{Tag, TagMac};
 use crate::database::Database;
+use chrono::{Datelike, Days, LocalResult, NaiveDate, TimeZone};
+use chrono_tz::Tz;
 use log::warn;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use sqlx::{
     types::chrono::{DateTime, Utc},
-    FromRow,
+    FromRow, SqliteConnection,
 };
 
 /// Task model.
-#[derive(Debug, Default, FromRow, Clone, PartialEq, Serialize)]
+#[derive(Debug, Default, FromRow, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Task {
     pub id: i64,
     pub name: String,
     pub status: TaskStatus,
     pub creation_time: DateTime<Utc>,
+    pub due_at: Option<DateTime<Utc>>,
+    pub start_at: Option<DateTime<Utc>>,
+    pub priority: TaskPriority,
+    /// Parent task, if this is a subtask.
+    pub parent_id: Option<i64>,
+    /// Whether any task blocking this one is still open.
+    pub blocked: bool,
+    /// Recurrence rule in RRULE syntax, e.g. `FREQ=WEEKLY;BYDAY=MO`.
+    pub recurrence: Option<String>,
+    /// Incremented on every update of the task.
+    pub version: i64,
+    /// When the task was moved to the trash. Only set for tasks in the trash.
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// When the task was last changed.
+    pub modified_at: DateTime<Utc>,
+    /// Tags of the task, by name. Loaded separately from the `tasks` row.
+    #[sqlx(skip)]
+    pub tags: Vec<Tag>,
 }
 
 #[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize, sqlx::Type)]
@@ -24,32 +50,225 @@ pub enum TaskStatus {
     Closed,
 }
 
+/// Priority level of a task. Stored as an integer so that it sorts naturally.
+#[derive(
+    Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize, sqlx::Type,
+)]
+#[repr(i64)]
+pub enum TaskPriority {
+    Low = 0,
+    #[default]
+    Normal = 1,
+    High = 2,
+    Urgent = 3,
+}
+
 /// Patch type for creating or updating a task.
-#[derive(Debug, Default, Clone, Deserialize)]
+///
+/// Optional timestamps use two levels of `Option`: a missing field leaves the value
+/// untouched, while an explicit `null` clears it.
+///
+/// Serializing leaves out missing fields, so that a serialized patch means the same
+/// to the server.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct TaskPatch {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub status: Option<TaskStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<TaskPriority>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub due_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_at: Option<Option<DateTime<Utc>>>,
+    /// Reparents the task. `null` makes it a top-level task.
+    #[serde(default, deserialize_with = "deserialize_some")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recurrence: Option<Option<String>>,
+    /// Version the change is based on. If the task has been updated since, the
+    /// update fails with [`crate::Error::StaleVersion`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>,
+}
+
+/// Deserialize a present field, including `null`, as `Some`.
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// A window of due dates, evaluated in the user's time zone.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DueWindow {
+    /// Due before now.
+    Overdue,
+    /// Due at any time during the current calendar day.
+    Today,
+    /// Due at any time during the current ISO week (Monday to Sunday).
+    Week,
 }
 
+impl DueWindow {
+    /// Half-open `[start, end)` range of due dates in the window. `start` is `None` for
+    /// windows without a lower bound.
+    pub fn bounds(&self, now: DateTime<Utc>, tz: Tz) -> (Option<DateTime<Utc>>, DateTime<Utc>) {
+        let today = now.with_timezone(&tz).date_naive();
+        match self {
+            DueWindow::Overdue => (None, now),
+            DueWindow::Today => (
+                Some(start_of_day(today, tz)),
+                start_of_day(today + Days::new(1), tz),
+            ),
+            DueWindow::Week => {
+                let monday = today - Days::new(today.weekday().num_days_from_monday() as u64);
+                (
+                    Some(start_of_day(monday, tz)),
+                    start_of_day(monday + Days::new(7), tz),
+                )
+            }
+        }
+    }
+}
+
+/// The first instant of a local calendar day.
+///
+/// Days where midnight falls into a DST gap start at the first valid local time.
+fn start_of_day(date: NaiveDate, tz: Tz) -> DateTime<Utc> {
+    let mut time = date.and_hms_opt(0, 0, 0).expect("midnight is a valid time");
+    loop {
+        match tz.from_local_datetime(&time) {
+            LocalResult::Single(t) | LocalResult::Ambiguous(t, _) => {
+                return t.with_timezone(&Utc)
+            }
+            LocalResult::None => time += chrono::Duration::minutes(15),
+        }
+    }
+}
+
+/// Result of updating a task.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TaskUpdate {
+    #[serde(flatten)]
+    pub task: Task,
+    /// Tasks that were blocked before the update and are not anymore.
+    pub unblocked: Vec<Task>,
+    /// The next occurrence created by closing a recurring task.
+    pub next_occurrence: Option<Task>,
+}
+
+/// Condition that holds while any blocker of the current row of `tasks` is open.
+/// Blockers in the trash do not count.
+macro_rules! blocked_condition {
+    () => {
+        r#"EXISTS (
+            SELECT 1 FROM task_dependencies d JOIN tasks b ON b.id = d.blocker_id
+            WHERE d.blocked_id = tasks.id AND b.status = 'open' AND b.deleted_at IS NULL
+        )"#
+    };
+}
+pub(crate) use blocked_condition;
+
+/// Columns selected for a [`Task`], in declaration order. `blocked` is derived from
+/// the open blockers of the task.
+macro_rules! task_columns {
+    () => {
+        concat!(
+            "id, name, status, creation_time, due_at, start_at, priority, parent_id, recurrence, version, deleted_at, modified_at, ",
+            $crate::model::task::blocked_condition!(),
+            " AS blocked"
+        )
+    };
+}
+pub(crate) use task_columns;
+
 /// Task model access controller.
 pub struct TaskMac;
 
 impl TaskMac {
     const TABLE_NAME: &'static str = "tasks";
-    const COLUMNS: &'static [&'static str] = &["id", "name", "status", "creation_time"];
-    const INSERT_SQL: &'static str = r#"INSERT INTO tasks (
-        name, status, creation_time
+    const INSERT_SQL: &'static str = concat!(
+        r#"INSERT INTO tasks (
+        id, name, status, creation_time, due_at, start_at, priority, parent_id, recurrence,
+        modified_at, field_times
     ) VALUES (
         ?,
         ?,
-        strftime('%s', ?)
-    ) RETURNING id, name, status, creation_time"#;
-    const GET_SQL: &'static str = r"SELECT id, name, status, creation_time FROM tasks WHERE id = ?";
-    const DELETE_SQL: &'static str = "DELETE FROM tasks WHERE id = ?";
-    const LIST_SQL: &'static str = "SELECT id, name, status, creation_time FROM tasks";
+        ?,
+        strftime('%s', ?),
+        ?,
+        ?,
+        ?,
+        ?,
+        ?,
+        strftime('%s', ?),
+        ?
+    ) RETURNING "#,
+        task_columns!()
+    );
+    pub(super) const GET_SQL: &'static str =
+        concat!("SELECT ", task_columns!(), " FROM tasks WHERE id = ? AND deleted_at IS NULL");
+    const LIST_SQL: &'static str =
+        concat!("SELECT ", task_columns!(), " FROM tasks WHERE deleted_at IS NULL");
+    const LIST_DUE_SQL: &'static str = concat!(
+        "SELECT ",
+        task_columns!(),
+        r#" FROM tasks
+        WHERE status = 'open' AND deleted_at IS NULL
+            AND due_at IS NOT NULL AND due_at >= ? AND due_at < ?
+        ORDER BY due_at, id"#
+    );
+    const LIST_ACTIONABLE_SQL: &'static str = concat!(
+        "SELECT ",
+        task_columns!(),
+        r#" FROM tasks WHERE status = 'open' AND deleted_at IS NULL
+        AND (start_at IS NULL OR start_at <= ?)"#
+    );
 
     /// Insert a new task into the database.
     pub async fn insert(db: &Database, data: TaskPatch) -> Result<Task, crate::Error> {
+        Self::insert_by(db, data, None).await
+    }
+
+    /// Insert a new task into the database, recording `client` as its creator.
+    pub async fn insert_by(
+        db: &Database,
+        data: TaskPatch,
+        client: Option<&str>,
+    ) -> Result<Task, crate::Error> {
+        Self::insert_at(db, data, client, Utc::now()).await
+    }
+
+    /// Insert a new task whose fields were set at time `at`, for last-writer-wins
+    /// merging of later changes.
+    pub(super) async fn insert_at(
+        db: &Database,
+        data: TaskPatch,
+        client: Option<&str>,
+        at: DateTime<Utc>,
+    ) -> Result<Task, crate::Error> {
+        Self::insert_record(db, None, None, data, client, at).await
+    }
+
+    /// Insert a new task under the given id and creation time, or the next free id and
+    /// the current time if `None`.
+    pub(super) async fn insert_record(
+        db: &Database,
+        id: Option<i64>,
+        created: Option<DateTime<Utc>>,
+        data: TaskPatch,
+        client: Option<&str>,
+        at: DateTime<Utc>,
+    ) -> Result<Task, crate::Error> {
         // let query = format!(
         //     "INSERT INTO {0} (name, status, creation_time) VALUES (?, ?, strftime('%s', ?)) RETURNING {1}",
         //     Self::TABLE_NAME,
@@ -57,26 +276,185 @@ impl TaskMac {
         // );
 
         // let task_name = &data.name.unwrap_or_else(||{ warn!("Got empty task name. Defaulting to \"untitled\"."); "untitled".to_string()});
+        let mut tx = db.begin().await?;
+        let task = Self::insert_in(&mut tx, id, created, data, client, at).await?;
+        tx.commit().await?;
+        EventMac::notify(db);
+        Ok(task)
+    }
+
+    /// Insert a new task as [`TaskMac::insert_record`] does, as part of a transaction.
+    pub(super) async fn insert_in(
+        conn: &mut SqliteConnection,
+        id: Option<i64>,
+        created: Option<DateTime<Utc>>,
+        data: TaskPatch,
+        client: Option<&str>,
+        at: DateTime<Utc>,
+    ) -> Result<Task, crate::Error> {
+        if data.name.is_none() {
+            return Err(crate::Error::Validation("Task name is required.".to_string()));
+        }
+        Self::validate(&data)?;
+        if let Some(parent_id) = data.parent_id.flatten() {
+            Self::validate_parent(conn, None, parent_id).await?;
+        }
         let task_status = &data.status.unwrap_or(TaskStatus::Open);
+        let now = Utc::now().naive_utc();
+        let field_times: serde_json::Map<String, serde_json::Value> = TRACKED_FIELDS
+            .iter()
+            .map(|field| (field.to_string(), at.timestamp_millis().into()))
+            .collect();
 
         let response = sqlx::query_as::<_, Task>(Self::INSERT_SQL)
+            .bind(id)
             .bind(&data.name)
             .bind(task_status)
-            .bind(Utc::now().naive_utc());
+            .bind(created.map(|t| t.naive_utc()).unwrap_or(now))
+            .bind(data.due_at.flatten().map(|t| t.timestamp()))
+            .bind(data.start_at.flatten().map(|t| t.timestamp()))
+            .bind(data.priority.unwrap_or_default())
+            .bind(data.parent_id.flatten())
+            .bind(normalize_recurrence(data.recurrence.flatten())?)
+            .bind(now)
+            .bind(serde_json::Value::Object(field_times).to_string());
 
-        let task = response.fetch_one(db).await?;
+        let task = response.fetch_one(&mut *conn).await?;
+        HistoryMac::record(conn, None, Some(&task), client).await?;
         Ok(task)
     }
 
     /// Get a task from the database by id.
     pub async fn get(db: &Database, id: i64) -> Result<Task, crate::Error> {
+        Self::get_in(&mut *db.acquire().await?, id).await
+    }
+
+    /// Get a task by id as part of a transaction.
+    pub(super) async fn get_in(conn: &mut SqliteConnection, id: i64) -> Result<Task, crate::Error> {
         let response = sqlx::query_as::<_, Task>(Self::GET_SQL).bind(id);
-        let task = response.fetch_one(db).await?;
+        let mut task = response
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(not_found(id))?;
+        TagMac::load_for(conn, std::slice::from_mut(&mut task)).await?;
         Ok(task)
     }
 
-    /// Update a task in the database.
-    pub async fn update(db: &Database, id: i64, data: TaskPatch) -> Result<Task, crate::Error> {
+    /// Update a task in the database, and report the tasks that closing it unblocked.
+    pub async fn update(
+        db: &Database,
+        id: i64,
+        data: TaskPatch,
+    ) -> Result<TaskUpdate, crate::Error> {
+        Self::update_by(db, id, data, None).await
+    }
+
+    /// Update a task in the database on behalf of `client`. See [`TaskMac::update`].
+    pub async fn update_by(
+        db: &Database,
+        id: i64,
+        data: TaskPatch,
+        client: Option<&str>,
+    ) -> Result<TaskUpdate, crate::Error> {
+        Self::update_at(db, id, data, client, Utc::now()).await
+    }
+
+    /// Update a task with changes made at time `at`, and report the tasks that closing
+    /// it unblocked.
+    pub(super) async fn update_at(
+        db: &Database,
+        id: i64,
+        data: TaskPatch,
+        client: Option<&str>,
+        at: DateTime<Utc>,
+    ) -> Result<TaskUpdate, crate::Error> {
+        // Closing a recurring task and creating its next occurrence succeed or fail
+        // together, so that exactly one occurrence stays open.
+        let mut tx = db.begin().await?;
+        let update = Self::update_in(&mut tx, id, data, client, at).await?;
+        tx.commit().await?;
+        EventMac::notify(db);
+        Ok(update)
+    }
+
+    /// Update a task within the transaction of the caller, as [`TaskMac::update_at`].
+    pub(super) async fn update_in(
+        conn: &mut SqliteConnection,
+        id: i64,
+        data: TaskPatch,
+        client: Option<&str>,
+        at: DateTime<Utc>,
+    ) -> Result<TaskUpdate, crate::Error> {
+        let closed_from = match data.status {
+            Some(TaskStatus::Closed) => Some(Self::get_in(conn, id).await?.status),
+            _ => None,
+        };
+        let blocked_before: Vec<i64> = match data.status {
+            Some(TaskStatus::Closed) => Self::dependents_in(conn, id)
+                .await?
+                .into_iter()
+                .filter(|t| t.blocked)
+                .map(|t| t.id)
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let mut task = Self::update_fields(conn, id, data, client, at).await?;
+
+        let mut next_occurrence = None;
+        if closed_from == Some(TaskStatus::Open) && task.recurrence.is_some() {
+            next_occurrence = Self::spawn_next_occurrence(conn, &task, Utc::now(), client).await?;
+            // The series continues with the next occurrence only.
+            task = Self::update_fields(
+                conn,
+                id,
+                TaskPatch {
+                    recurrence: Some(None),
+                    ..Default::default()
+                },
+                client,
+                at,
+            )
+            .await?;
+        }
+
+        let unblocked = if blocked_before.is_empty() {
+            Vec::new()
+        } else {
+            Self::dependents_in(conn, id)
+                .await?
+                .into_iter()
+                .filter(|t| !t.blocked && blocked_before.contains(&t.id))
+                .collect()
+        };
+        Ok(TaskUpdate {
+            task,
+            unblocked,
+            next_occurrence,
+        })
+    }
+
+    /// Write the fields that are set in a patch, recording `at` as their modification
+    /// time.
+    async fn update_fields(
+        conn: &mut SqliteConnection,
+        id: i64,
+        data: TaskPatch,
+        client: Option<&str>,
+        at: DateTime<Utc>,
+    ) -> Result<Task, crate::Error> {
+        Self::validate(&data)?;
+        if data.due_at.is_some() || data.start_at.is_some() {
+            let current = TaskMac::get_in(conn, id).await?;
+            Self::validate_dates(
+                data.start_at.unwrap_or(current.start_at),
+                data.due_at.unwrap_or(current.due_at),
+            )?;
+        }
+        if let Some(Some(parent_id)) = data.parent_id {
+            Self::validate_parent(conn, Some(id), parent_id).await?;
+        }
+
         let mut query = format!("UPDATE {0} SET ", Self::TABLE_NAME);
         let mut set_statements = Vec::new();
 
@@ -87,20 +465,52 @@ impl TaskMac {
         if data.status.is_some() {
             set_statements.push("status = ?");
         }
+        if data.priority.is_some() {
+            set_statements.push("priority = ?");
+        }
+        if data.due_at.is_some() {
+            set_statements.push("due_at = ?");
+        }
+        if data.start_at.is_some() {
+            set_statements.push("start_at = ?");
+        }
+        if data.parent_id.is_some() {
+            set_statements.push("parent_id = ?");
+        }
+        if data.recurrence.is_some() {
+            set_statements.push("recurrence = ?");
+        }
 
         // Early return if nothing to update
         if set_statements.is_empty() {
             warn!("No fields to update for task with id {}", id);
-            return TaskMac::get(db, id).await;
+            let task = TaskMac::get_in(conn, id).await?;
+            return match data.version {
+                Some(version) if version != task.version => {
+                    Err(crate::Error::StaleVersion(Box::new(task)))
+                }
+                _ => Ok(task),
+            };
         }
+        let changed_fields = set_statements.len();
+        let field_times = set_statements
+            .iter()
+            .map(|statement| format!("'$.{}', ?", statement.trim_end_matches(" = ?")))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let field_times = format!("field_times = json_set(field_times, {})", field_times);
+        set_statements.push("modified_at = ?");
+        set_statements.push(&field_times);
+        set_statements.push("version = version + 1");
 
         // Add SET clause
         query.push_str(&set_statements.join(", "));
         // Add WHERE clause
-        query.push_str(&format!(
-            " WHERE id = ? RETURNING {0}",
-            Self::COLUMNS.join(", ")
-        ));
+        query.push_str(" WHERE id = ? AND deleted_at IS NULL");
+        if data.version.is_some() {
+            query.push_str(" AND version = ?");
+        }
+        query.push_str(concat!(" RETURNING ", task_columns!()));
 
         let mut response = sqlx::query_as::<_, Task>(&query);
 
@@ -111,30 +521,142 @@ impl TaskMac {
         if let Some(task_status) = &data.status {
             response = response.bind(task_status);
         }
+        if let Some(priority) = &data.priority {
+            response = response.bind(priority);
+        }
+        if let Some(due_at) = &data.due_at {
+            response = response.bind(due_at.map(|t| t.timestamp()));
+        }
+        if let Some(start_at) = &data.start_at {
+            response = response.bind(start_at.map(|t| t.timestamp()));
+        }
+        if let Some(parent_id) = &data.parent_id {
+            response = response.bind(parent_id);
+        }
+        if let Some(recurrence) = &data.recurrence {
+            response = response.bind(normalize_recurrence(recurrence.clone())?);
+        }
+        response = response.bind(Utc::now().timestamp());
+        for _ in 0..changed_fields {
+            response = response.bind(at.timestamp_millis());
+        }
         response = response.bind(id);
+        if let Some(version) = data.version {
+            response = response.bind(version);
+        }
 
-        let task = response.fetch_one(db).await?;
+        let before = sqlx::query_as::<_, Task>(Self::GET_SQL)
+            .bind(id)
+            .fetch_optional(&mut *conn)
+            .await?;
+        let mut task = match response.fetch_optional(&mut *conn).await? {
+            Some(task) => task,
+            // The task is missing, or it changed since the given version.
+            None => {
+                return Err(match data.version {
+                    Some(_) => {
+                        crate::Error::StaleVersion(Box::new(TaskMac::get_in(conn, id).await?))
+                    }
+                    None => not_found(id)(sqlx::Error::RowNotFound),
+                })
+            }
+        };
+        HistoryMac::record(conn, before.as_ref(), Some(&task), client).await?;
+        TagMac::load_for(conn, std::slice::from_mut(&mut task)).await?;
         Ok(task)
     }
 
-    /// Delete a task from the database.
+    /// Move a task to the trash. Fails with a conflict if the task has subtasks; use
+    /// [`TaskMac::delete_with`] to decide what happens to them.
     pub async fn delete(db: &Database, id: i64) -> Result<(), crate::Error> {
-        sqlx::query(Self::DELETE_SQL).bind(id).execute(db).await?;
-        Ok(())
+        Self::delete_with(db, id, ChildPolicy::Refuse, None).await
     }
 
     /// List all tasks from the database.
     pub async fn list(db: &Database) -> Result<Vec<Task>, crate::Error> {
         let response = sqlx::query_as::<_, Task>(Self::LIST_SQL);
-        let tasks = response.fetch_all(db).await?;
+        let mut tasks = response.fetch_all(db).await?;
+        TagMac::load_for(db, &mut tasks).await?;
         Ok(tasks)
     }
+
+    /// List open tasks that have started, ranked by [`ranking::score`], best first.
+    pub async fn list_next(
+        db: &Database,
+        now: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<RankedTask>, crate::Error> {
+        let response = sqlx::query_as::<_, Task>(Self::LIST_ACTIONABLE_SQL).bind(now.timestamp());
+        let mut tasks = response.fetch_all(db).await?;
+        TagMac::load_for(db, &mut tasks).await?;
+        Ok(ranking::rank(tasks, now, limit))
+    }
+
+    /// List open tasks that are due within a window, earliest first.
+    pub async fn list_due(
+        db: &Database,
+        window: DueWindow,
+        now: DateTime<Utc>,
+        tz: Tz,
+    ) -> Result<Vec<Task>, crate::Error> {
+        let (start, end) = window.bounds(now, tz);
+        let response = sqlx::query_as::<_, Task>(Self::LIST_DUE_SQL)
+            .bind(start.map(|t| t.timestamp()).unwrap_or(i64::MIN))
+            .bind(end.timestamp());
+        let mut tasks = response.fetch_all(db).await?;
+        TagMac::load_for(db, &mut tasks).await?;
+        Ok(tasks)
+    }
+
+    /// Check the fields that are set in a patch.
+    pub(super) fn validate(data: &TaskPatch) -> Result<(), crate::Error> {
+        if let Some(name) = &data.name {
+            if name.trim().is_empty() {
+                return Err(crate::Error::Validation(
+                    "Task name must not be empty.".to_string(),
+                ));
+            }
+        }
+        if let Some(Some(recurrence)) = &data.recurrence {
+            recurrence.parse::<RRule>()?;
+        }
+        Self::validate_dates(data.start_at.flatten(), data.due_at.flatten())
+    }
+
+    /// A task cannot be due before it starts.
+    fn validate_dates(
+        start_at: Option<DateTime<Utc>>,
+        due_at: Option<DateTime<Utc>>,
+    ) -> Result<(), crate::Error> {
+        match (start_at, due_at) {
+            (Some(start_at), Some(due_at)) if start_at > due_at => Err(
+                crate::Error::Validation("Task cannot be due before it starts.".to_string()),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parse a recurrence rule and return it in canonical form.
+fn normalize_recurrence(recurrence: Option<String>) -> Result<Option<String>, crate::Error> {
+    recurrence
+        .map(|r| r.parse::<RRule>().map(|r| r.to_string()))
+        .transpose()
+}
+
+/// Map a missing row to a `NotFound` error for the task with the given id.
+pub(super) fn not_found(id: i64) -> impl FnOnce(sqlx::Error) -> crate::Error {
+    move |e| match e {
+        sqlx::Error::RowNotFound => crate::Error::NotFound(format!("Task {} not found.", id)),
+        e => e.into(),
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::database::{create_and_connect, DbAddress};
+    use crate::model::listing::TaskQuery;
     use crate::model::task::TaskStatus;
 
     /// Test insertion of a new task
@@ -145,6 +667,7 @@ mod test {
         let task_fixture = TaskPatch {
             name: Some("Hello world".to_string()),
             status: None,
+            ..Default::default()
         };
 
         let task = TaskMac::insert(&db, task_fixture).await?;
@@ -164,6 +687,7 @@ mod test {
         let task_fixture = TaskPatch {
             name: Some("Hello world".to_string()),
             status: Some(TaskStatus::Open),
+            ..Default::default()
         };
 
         // # Action
@@ -185,6 +709,7 @@ mod test {
         let task_fixture = TaskPatch {
             name: Some("Hello world".to_string()),
             status: Some(TaskStatus::Open),
+            ..Default::default()
         };
         let inserted_task = TaskMac::insert(&db, task_fixture).await?;
 
@@ -195,9 +720,11 @@ mod test {
             TaskPatch {
                 name: Some("Updated".to_string()),
                 status: None,
+                ..Default::default()
             },
         )
-        .await?;
+        .await?
+        .task;
 
         // # Check
         assert_eq!(updated_task.name, "Updated");
@@ -216,6 +743,7 @@ mod test {
         let task_fixture = TaskPatch {
             name: Some("Hello world".to_string()),
             status: Some(TaskStatus::Open),
+            ..Default::default()
         };
         let inserted_task = TaskMac::insert(&db, task_fixture).await?;
 
@@ -226,9 +754,11 @@ mod test {
             TaskPatch {
                 name: None,
                 status: None,
+                ..Default::default()
             },
         )
-        .await?;
+        .await?
+        .task;
 
         // # Check
         assert_eq!(updated_task, inserted_task);
@@ -245,6 +775,7 @@ mod test {
         let task_fixture = TaskPatch {
             name: Some("Hello world".to_string()),
             status: Some(TaskStatus::Open),
+            ..Default::default()
         };
         let inserted_task = TaskMac::insert(&db, task_fixture).await?;
 
@@ -255,9 +786,11 @@ mod test {
             TaskPatch {
                 name: None,
                 status: Some(TaskStatus::Closed),
+                ..Default::default()
             },
         )
-        .await?;
+        .await?
+        .task;
 
         // # Check
         assert_eq!(updated_task.name, "Hello world");
@@ -266,6 +799,270 @@ mod test {
         Ok(())
     }
 
+    /// Test that updates based on an outdated version are refused.
+    #[tokio::test]
+    async fn test_stale_version() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let task = TaskMac::insert(
+            &db,
+            TaskPatch {
+                name: Some("Draft".to_string()),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(task.version, 1);
+        let rename = |name: &str, version| TaskPatch {
+            name: Some(name.to_string()),
+            version,
+            ..Default::default()
+        };
+
+        let first = TaskMac::update(&db, task.id, rename("First", Some(1)))
+            .await?
+            .task;
+        assert_eq!(first.version, 2);
+
+        match TaskMac::update(&db, task.id, rename("Second", Some(1))).await {
+            Err(crate::Error::StaleVersion(current)) => assert_eq!(*current, first),
+            other => panic!("expected a stale version, got {:?}", other),
+        }
+        assert!(matches!(
+            TaskMac::update(&db, task.id, rename("Second", None)).await,
+            Ok(TaskUpdate {
+                task: Task { version: 3, .. },
+                ..
+            })
+        ));
+        assert!(matches!(
+            TaskMac::update(&db, 42, rename("Missing", Some(1))).await,
+            Err(crate::Error::NotFound(_))
+        ));
+        Ok(())
+    }
+
+    /// Test that missing tasks are reported as not found.
+    #[tokio::test]
+    async fn test_not_found() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+
+        assert!(matches!(TaskMac::get(&db, 42).await, Err(crate::Error::NotFound(_))));
+        assert!(matches!(
+            TaskMac::update(&db, 42, TaskPatch { name: Some("x".to_string()), status: None, ..Default::default() }).await,
+            Err(crate::Error::NotFound(_))
+        ));
+        assert!(matches!(TaskMac::delete(&db, 42).await, Err(crate::Error::NotFound(_))));
+        Ok(())
+    }
+
+    /// Test that tasks without a name are rejected.
+    #[tokio::test]
+    async fn test_validation() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+
+        assert!(matches!(
+            TaskMac::insert(&db, TaskPatch::default()).await,
+            Err(crate::Error::Validation(_))
+        ));
+        assert!(matches!(
+            TaskMac::insert(&db, TaskPatch { name: Some("  ".to_string()), status: None, ..Default::default() }).await,
+            Err(crate::Error::Validation(_))
+        ));
+        Ok(())
+    }
+
+    /// Test setting and clearing due and start dates.
+    #[tokio::test]
+    async fn test_update_dates() -> Result<(), crate::Error> {
+        // # Setup
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let start = Utc.with_ymd_and_hms(2024, 3, 1, 9, 0, 0).unwrap();
+        let due = Utc.with_ymd_and_hms(2024, 3, 4, 17, 0, 0).unwrap();
+
+        // # Fixture
+        let inserted_task = TaskMac::insert(
+            &db,
+            TaskPatch {
+                name: Some("Hello world".to_string()),
+                start_at: Some(Some(start)),
+                due_at: Some(Some(due)),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(inserted_task.start_at, Some(start));
+        assert_eq!(inserted_task.due_at, Some(due));
+
+        // # Action
+        let updated_task = TaskMac::update(
+            &db,
+            inserted_task.id,
+            TaskPatch {
+                due_at: Some(None),
+                ..Default::default()
+            },
+        )
+        .await?
+        .task;
+
+        // # Check
+        assert_eq!(updated_task.start_at, Some(start));
+        assert_eq!(updated_task.due_at, None);
+        Ok(())
+    }
+
+    /// Test that a task cannot be due before it starts, also across patches.
+    #[tokio::test]
+    async fn test_due_before_start() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let start = Utc.with_ymd_and_hms(2024, 3, 4, 9, 0, 0).unwrap();
+        let task = TaskMac::insert(
+            &db,
+            TaskPatch {
+                name: Some("Hello world".to_string()),
+                start_at: Some(Some(start)),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let result = TaskMac::update(
+            &db,
+            task.id,
+            TaskPatch {
+                due_at: Some(Some(start - chrono::Duration::hours(1))),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(matches!(result, Err(crate::Error::Validation(_))));
+        Ok(())
+    }
+
+    /// Test that "today" follows the user's calendar day rather than UTC.
+    #[test]
+    fn test_due_window_time_zone() {
+        let tz: Tz = "America/New_York".parse().unwrap();
+        // 22:00 on March 4th in New York.
+        let now = Utc.with_ymd_and_hms(2024, 3, 5, 3, 0, 0).unwrap();
+
+        let (start, end) = DueWindow::Today.bounds(now, tz);
+        assert_eq!(start, Some(Utc.with_ymd_and_hms(2024, 3, 4, 5, 0, 0).unwrap()));
+        assert_eq!(end, Utc.with_ymd_and_hms(2024, 3, 5, 5, 0, 0).unwrap());
+
+        // The week from Monday March 4th spans the switch to daylight saving time.
+        let (start, end) = DueWindow::Week.bounds(now, tz);
+        assert_eq!(start, Some(Utc.with_ymd_and_hms(2024, 3, 4, 5, 0, 0).unwrap()));
+        assert_eq!(end, Utc.with_ymd_and_hms(2024, 3, 11, 4, 0, 0).unwrap());
+
+        assert_eq!(DueWindow::Overdue.bounds(now, tz), (None, now));
+    }
+
+    /// Test that days starting in a DST gap begin at the first valid local time.
+    #[test]
+    fn test_due_window_dst_gap() {
+        // Midnight does not exist in Santiago on 2024-09-08.
+        let tz: Tz = "America/Santiago".parse().unwrap();
+        let now = Utc.with_ymd_and_hms(2024, 9, 8, 12, 0, 0).unwrap();
+
+        let (start, _) = DueWindow::Today.bounds(now, tz);
+        assert_eq!(start, Some(Utc.with_ymd_and_hms(2024, 9, 8, 4, 0, 0).unwrap()));
+    }
+
+    /// Test listing overdue and due tasks.
+    #[tokio::test]
+    async fn test_list_due() -> Result<(), crate::Error> {
+        // # Setup
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let now = Utc.with_ymd_and_hms(2024, 3, 6, 12, 0, 0).unwrap();
+
+        // # Fixture
+        let fixture = [
+            ("Late", TaskStatus::Open, Some(now - chrono::Duration::days(1))),
+            ("Done", TaskStatus::Closed, Some(now - chrono::Duration::days(1))),
+            ("Tonight", TaskStatus::Open, Some(now + chrono::Duration::hours(6))),
+            ("Sunday", TaskStatus::Open, Some(now + chrono::Duration::days(4))),
+            ("Someday", TaskStatus::Open, None),
+        ];
+        for (name, status, due_at) in fixture {
+            TaskMac::insert(
+                &db,
+                TaskPatch {
+                    name: Some(name.to_string()),
+                    status: Some(status),
+                    due_at: Some(due_at),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        }
+
+        // # Check
+        let names = |tasks: Vec<Task>| tasks.into_iter().map(|t| t.name).collect::<Vec<_>>();
+        let overdue = TaskMac::list_due(&db, DueWindow::Overdue, now, Tz::UTC).await?;
+        assert_eq!(names(overdue), vec!["Late"]);
+        let today = TaskMac::list_due(&db, DueWindow::Today, now, Tz::UTC).await?;
+        assert_eq!(names(today), vec!["Tonight"]);
+        let week = TaskMac::list_due(&db, DueWindow::Week, now, Tz::UTC).await?;
+        assert_eq!(names(week), vec!["Late", "Tonight", "Sunday"]);
+        Ok(())
+    }
+
+    /// Test sorting tasks by priority.
+    #[tokio::test]
+    async fn test_list_by_priority() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        for (name, priority) in [
+            ("Low", Some(TaskPriority::Low)),
+            ("Default", None),
+            ("Urgent", Some(TaskPriority::Urgent)),
+            ("Normal", Some(TaskPriority::Normal)),
+        ] {
+            TaskMac::insert(
+                &db,
+                TaskPatch {
+                    name: Some(name.to_string()),
+                    priority,
+                    ..Default::default()
+                },
+            )
+            .await?;
+        }
+
+        let query = TaskQuery {
+            sort: "priority".parse()?,
+            ..Default::default()
+        };
+        let tasks = TaskMac::list_page(&db, &query).await?.items;
+        let names: Vec<String> = tasks.into_iter().map(|t| t.name).collect();
+        assert_eq!(names, vec!["Urgent", "Default", "Normal", "Low"]);
+        Ok(())
+    }
+
+    /// Test that tasks that have not started yet are not suggested.
+    #[tokio::test]
+    async fn test_list_next() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let now = Utc::now();
+        for (name, start_at) in [("Started", None), ("Future", Some(now + chrono::Duration::days(1)))] {
+            TaskMac::insert(
+                &db,
+                TaskPatch {
+                    name: Some(name.to_string()),
+                    priority: Some(TaskPriority::High),
+                    start_at: Some(start_at),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        }
+
+        let next = TaskMac::list_next(&db, now, 10).await?;
+        assert_eq!(next.len(), 1);
+        assert_eq!(next[0].task.name, "Started");
+        Ok(())
+    }
+
     /// Test listing all tasks.
     #[tokio::test]
     async fn test_list() -> Result<(), crate::Error> {
@@ -277,10 +1074,12 @@ mod test {
             TaskPatch {
                 name: Some("One".to_string()),
                 status: Some(TaskStatus::Open),
+                ..Default::default()
             },
             TaskPatch {
                 name: Some("Two".to_string()),
                 status: Some(TaskStatus::Closed),
+                ..Default::default()
             },
         ];
 
@@ -0,0 +1,533 @@
+//! Compatibility with Taskwarrior's `task export` and `task import` JSON.
+//!
+//! | Taskwarrior           | Task                                             |
+//! | --------------------- | ------------------------------------------------ |
+//! | `uuid`                | Kept in `taskwarrior_links`                      |
+//! | `description`         | `name`                                           |
+//! | `status`              | `pending` and `waiting` are open, `completed` closed |
+//! | `entry`               | `creation_time`                                  |
+//! | `due`, `scheduled`    | `due_at`, `start_at`                             |
+//! | `priority`            | `H` urgent, `M` high, none normal, `L` low       |
+//! | `project`             | A tag named `+project`                           |
+//! | `tags`                | Tags                                             |
+//! | `annotations`         | Kept in `taskwarrior_links`                      |
+//!
+//! Deleted tasks and recurring templates are skipped; the instances of a recurring
+//! task are imported as plain tasks. An import matches tasks by UUID. A task changed
+//! both here and in Taskwarrior since it was last synced is a conflict, and is left
+//! alone.
+//!
+//! Exporting writes nothing: a task that was never synced is exported under a UUID
+//! made from its id, and is linked to it when the export is imported back.
+
+use super::events::EventMac;
+use super::tag::{TagMac, TagPatch};
+use super::task::{Task, TaskMac, TaskPatch, TaskPriority, TaskStatus};
+use crate::database::Database;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::types::Json;
+use sqlx::{Connection, SqliteConnection};
+use std::collections::{HashMap, HashSet};
+
+/// A task in Taskwarrior's JSON format. Fields this app has no use for are dropped.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TwTask {
+    pub uuid: String,
+    pub description: String,
+    pub status: String,
+    /// Times are in Taskwarrior's form, e.g. `20240501T100000Z`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entry: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub due: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheduled: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub annotations: Vec<Annotation>,
+}
+
+/// A note on a Taskwarrior task.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    pub entry: String,
+    pub description: String,
+}
+
+/// Why a Taskwarrior task was not mapped.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IssueKind {
+    Skipped,
+    /// Not changed in Taskwarrior since the last sync.
+    Unchanged,
+    Conflict,
+}
+
+/// A Taskwarrior task that was not mapped.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Issue {
+    /// 0-based position of the task in the file.
+    pub index: usize,
+    pub uuid: Option<String>,
+    pub kind: IssueKind,
+    pub reason: String,
+}
+
+/// Outcome of a Taskwarrior import.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct TwImportReport {
+    /// Tasks created or updated.
+    pub mapped: usize,
+    pub skipped: usize,
+    pub unchanged: usize,
+    pub conflicts: usize,
+    pub issues: Vec<Issue>,
+}
+
+impl TwImportReport {
+    fn push(&mut self, index: usize, uuid: Option<&str>, kind: IssueKind, reason: String) {
+        match kind {
+            IssueKind::Skipped => self.skipped += 1,
+            IssueKind::Unchanged => self.unchanged += 1,
+            IssueKind::Conflict => self.conflicts += 1,
+        }
+        self.issues.push(Issue {
+            index,
+            uuid: uuid.map(str::to_string),
+            kind,
+            reason,
+        });
+    }
+}
+
+/// The task linked to a Taskwarrior UUID.
+#[derive(Debug, sqlx::FromRow)]
+struct Link {
+    task_id: i64,
+    /// Version of the task when it was last synced.
+    synced_version: i64,
+    /// Taskwarrior's modification time of the task when it was last synced, in seconds.
+    synced_modified: i64,
+    version: i64,
+    trashed: bool,
+}
+
+/// Start of the UUID of a task that was never synced.
+const DERIVED_UUID_PREFIX: &str = "00000000-0000-4000-8000-";
+
+/// The UUID under which a task that was never synced is exported.
+fn derived_uuid(task_id: i64) -> String {
+    format!("{}{:012x}", DERIVED_UUID_PREFIX, task_id)
+}
+
+/// The task id in a UUID made by [`derived_uuid`].
+fn derived_id(uuid: &str) -> Option<i64> {
+    let uuid = uuid.to_ascii_lowercase();
+    let hex = uuid.strip_prefix(DERIVED_UUID_PREFIX)?;
+    if hex.len() != 12 {
+        return None;
+    }
+    i64::from_str_radix(hex, 16).ok()
+}
+
+/// A Taskwarrior time, e.g. `20240501T100000Z`.
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Parse a Taskwarrior time. RFC 3339 is accepted as well.
+fn parse_time(field: &str, value: &Option<String>) -> Result<Option<DateTime<Utc>>, String> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%SZ")
+        .map(|time| time.and_utc())
+        .or_else(|_| DateTime::parse_from_rfc3339(value).map(|time| time.with_timezone(&Utc)))
+        .map(Some)
+        .map_err(|_| format!("Invalid {} {:?}.", field, value))
+}
+
+/// Read the tasks of a Taskwarrior export: a JSON array, or one object per line as
+/// older versions write it.
+pub fn parse_export(data: &[u8]) -> Result<Vec<Value>, crate::Error> {
+    let text = std::str::from_utf8(data)
+        .map_err(|_| crate::Error::Validation("Taskwarrior export must be UTF-8.".to_string()))?;
+    let invalid = |e: serde_json::Error| {
+        crate::Error::Validation(format!("Invalid Taskwarrior export: {}", e))
+    };
+    if text.trim_start().starts_with('[') {
+        return serde_json::from_str(text).map_err(invalid);
+    }
+    text.lines()
+        .map(|line| line.trim().trim_end_matches(','))
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_str(line).map_err(invalid))
+        .collect()
+}
+
+/// Taskwarrior model access controller.
+pub struct TaskwarriorMac;
+
+impl TaskwarriorMac {
+    const LINKS_SQL: &'static str = "SELECT task_id, uuid, annotations FROM taskwarrior_links";
+    const LINK_SQL: &'static str = r#"SELECT l.task_id, l.version AS synced_version,
+        l.modified_at AS synced_modified, t.version, t.deleted_at IS NOT NULL AS trashed
+        FROM taskwarrior_links l JOIN tasks t ON t.id = l.task_id
+        WHERE l.uuid = ?"#;
+    /// A task without a link, as if it was synced in its current state.
+    const UNLINKED_SQL: &'static str = r#"SELECT id AS task_id, version AS synced_version,
+        modified_at AS synced_modified, version, deleted_at IS NOT NULL AS trashed
+        FROM tasks
+        WHERE id = ? AND id NOT IN (SELECT task_id FROM taskwarrior_links)"#;
+    const UPSERT_LINK_SQL: &'static str = r#"INSERT INTO taskwarrior_links
+        (task_id, uuid, annotations, version, modified_at) VALUES (?, ?, ?, ?, ?)
+        ON CONFLICT (task_id) DO UPDATE
+        SET annotations = excluded.annotations, version = excluded.version,
+            modified_at = excluded.modified_at"#;
+
+    /// All tasks in Taskwarrior's format. Tasks that were never synced get a UUID made
+    /// from their id, so that importing them back updates them.
+    pub async fn export(db: &Database) -> Result<Vec<TwTask>, crate::Error> {
+        let links: HashMap<i64, (String, Json<Vec<Annotation>>)> =
+            sqlx::query_as::<_, (i64, String, Json<Vec<Annotation>>)>(Self::LINKS_SQL)
+                .fetch_all(db)
+                .await?
+                .into_iter()
+                .map(|(task_id, uuid, annotations)| (task_id, (uuid, annotations)))
+                .collect();
+        let tasks = TaskMac::list(db).await?;
+        Ok(tasks
+            .into_iter()
+            .map(|task| match links.get(&task.id) {
+                Some((uuid, annotations)) => to_tw_task(task, uuid.clone(), annotations.0.clone()),
+                None => {
+                    let uuid = derived_uuid(task.id);
+                    to_tw_task(task, uuid, Vec::new())
+                }
+            })
+            .collect())
+    }
+
+    /// Import the tasks of a Taskwarrior export on behalf of `client`.
+    pub async fn import(
+        db: &Database,
+        tasks: Vec<Value>,
+        client: Option<&str>,
+    ) -> Result<TwImportReport, crate::Error> {
+        let mut report = TwImportReport::default();
+        let mut seen = HashSet::new();
+        let mut tx = db.begin().await?;
+        for (index, value) in tasks.into_iter().enumerate() {
+            let tw_task: TwTask = match serde_json::from_value(value) {
+                Ok(tw_task) => tw_task,
+                Err(e) => {
+                    report.push(index, None, IssueKind::Skipped, e.to_string());
+                    continue;
+                }
+            };
+            let uuid = Some(tw_task.uuid.as_str());
+            if !seen.insert(tw_task.uuid.to_ascii_lowercase()) {
+                let reason = "Duplicate UUID in the export.".to_string();
+                report.push(index, uuid, IssueKind::Skipped, reason);
+                continue;
+            }
+            // A task, its tags and its link are written together, or not at all.
+            let mut savepoint = tx.begin().await?;
+            match Self::import_one(&mut savepoint, &tw_task, client).await {
+                Ok(None) => {
+                    savepoint.commit().await?;
+                    report.mapped += 1;
+                }
+                Ok(Some((kind, reason))) => {
+                    savepoint.commit().await?;
+                    report.push(index, uuid, kind, reason);
+                }
+                Err(crate::Error::Internal(e)) => return Err(crate::Error::Internal(e)),
+                Err(e) => report.push(index, uuid, IssueKind::Skipped, e.to_string()),
+            }
+        }
+        tx.commit().await?;
+        EventMac::notify(db);
+        Ok(report)
+    }
+
+    /// Create or update the task for a Taskwarrior task, or say why not.
+    async fn import_one(
+        conn: &mut SqliteConnection,
+        tw_task: &TwTask,
+        client: Option<&str>,
+    ) -> Result<Option<(IssueKind, String)>, crate::Error> {
+        let status = match tw_task.status.as_str() {
+            "pending" | "waiting" => TaskStatus::Open,
+            "completed" => TaskStatus::Closed,
+            "deleted" => return Ok(Some((IssueKind::Skipped, "Deleted.".to_string()))),
+            "recurring" => {
+                let reason = "Recurring template; its instances are imported.".to_string();
+                return Ok(Some((IssueKind::Skipped, reason)));
+            }
+            other => {
+                let reason = format!("Unknown status {:?}.", other);
+                return Ok(Some((IssueKind::Skipped, reason)));
+            }
+        };
+        let times = (|| {
+            Ok::<_, String>((
+                parse_time("entry", &tw_task.entry)?,
+                parse_time("modified", &tw_task.modified)?,
+                parse_time("due", &tw_task.due)?,
+                parse_time("scheduled", &tw_task.scheduled)?,
+            ))
+        })();
+        let (entry, modified, due, scheduled) = match times {
+            Ok(times) => times,
+            Err(reason) => return Ok(Some((IssueKind::Skipped, reason))),
+        };
+        let priority = match tw_task.priority.as_deref() {
+            Some("H") => TaskPriority::Urgent,
+            Some("M") => TaskPriority::High,
+            Some("L") => TaskPriority::Low,
+            _ => TaskPriority::Normal,
+        };
+        let patch = TaskPatch {
+            name: Some(tw_task.description.clone()),
+            status: Some(status),
+            priority: Some(priority),
+            due_at: Some(due),
+            start_at: Some(scheduled),
+            ..Default::default()
+        };
+        let mut tags = tw_task.tags.clone();
+        if let Some(project) = tw_task.project.as_deref().filter(|p| !p.is_empty()) {
+            tags.push(format!("+{}", project));
+        }
+        for name in &tags {
+            if let Err(e) = TagMac::validate(&TagPatch { name: name.clone() }) {
+                return Ok(Some((IssueKind::Skipped, e.to_string())));
+            }
+        }
+        let modified = modified.or(entry).unwrap_or_else(Utc::now);
+
+        let mut link = sqlx::query_as::<_, Link>(Self::LINK_SQL)
+            .bind(&tw_task.uuid)
+            .fetch_optional(&mut *conn)
+            .await?;
+        let linked = link.is_some();
+        if let (None, Some(task_id)) = (&link, derived_id(&tw_task.uuid)) {
+            link = sqlx::query_as::<_, Link>(Self::UNLINKED_SQL)
+                .bind(task_id)
+                .fetch_optional(&mut *conn)
+                .await?;
+        }
+        let task = match link {
+            None => TaskMac::insert_in(conn, None, entry, patch, client, modified).await?,
+            Some(link) if link.trashed => {
+                let reason = format!("Task {} is in the trash.", link.task_id);
+                return Ok(Some((IssueKind::Conflict, reason)));
+            }
+            Some(link) => {
+                if modified.timestamp() <= link.synced_modified {
+                    if !linked {
+                        Self::link(conn, link.task_id, tw_task, link.version, modified).await?;
+                    }
+                    let reason = "Unchanged since the last sync.".to_string();
+                    return Ok(Some((IssueKind::Unchanged, reason)));
+                }
+                if link.version > link.synced_version {
+                    let reason = format!(
+                        "Task {} was changed here and in Taskwarrior since the last sync.",
+                        link.task_id
+                    );
+                    return Ok(Some((IssueKind::Conflict, reason)));
+                }
+                TaskMac::update_in(conn, link.task_id, patch, client, modified)
+                    .await?
+                    .task
+            }
+        };
+        TagMac::set_for_in(conn, task.id, &tags, client).await?;
+        Self::link(conn, task.id, tw_task, task.version, modified).await?;
+        Ok(None)
+    }
+
+    /// Record that a task was synced with a Taskwarrior task at the given version of
+    /// the task and Taskwarrior modification time.
+    async fn link(
+        conn: &mut SqliteConnection,
+        task_id: i64,
+        tw_task: &TwTask,
+        version: i64,
+        modified: DateTime<Utc>,
+    ) -> Result<(), crate::Error> {
+        sqlx::query(Self::UPSERT_LINK_SQL)
+            .bind(task_id)
+            .bind(&tw_task.uuid)
+            .bind(Json(&tw_task.annotations))
+            .bind(version)
+            .bind(modified.timestamp())
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+}
+
+/// A task in Taskwarrior's format. The first tag that starts with `+` is the project.
+fn to_tw_task(task: Task, uuid: String, annotations: Vec<Annotation>) -> TwTask {
+    let mut project = None;
+    let mut tags = Vec::new();
+    for tag in task.tags {
+        match tag.name.strip_prefix('+') {
+            Some(name) if project.is_none() => project = Some(name.to_string()),
+            _ => tags.push(tag.name),
+        }
+    }
+    let priority = match task.priority {
+        TaskPriority::Urgent => Some("H"),
+        TaskPriority::High => Some("M"),
+        TaskPriority::Normal => None,
+        TaskPriority::Low => Some("L"),
+    };
+    TwTask {
+        uuid,
+        description: task.name,
+        status: match task.status {
+            TaskStatus::Open => "pending",
+            TaskStatus::Closed => "completed",
+        }
+        .to_string(),
+        entry: Some(format_time(task.creation_time)),
+        modified: Some(format_time(task.modified_at)),
+        due: task.due_at.map(format_time),
+        scheduled: task.start_at.map(format_time),
+        priority: priority.map(str::to_string),
+        project,
+        tags,
+        annotations,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use serde_json::json;
+
+    /// Test importing a Taskwarrior export, and exporting it back.
+    #[tokio::test]
+    async fn test_import_export() -> Result<(), crate::Error> {
+        // # Fixture
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let export = br#"
+            {"uuid":"a1b2c3d4-0000-4000-8000-000000000001","description":"Fix the fence","status":"pending","entry":"20240501T100000Z","modified":"20240502T100000Z","due":"20240510T000000Z","priority":"M","project":"garden","tags":["outside"],"annotations":[{"entry":"20240502T100000Z","description":"Buy nails first"}],"urgency":8.2},
+            {"uuid":"a1b2c3d4-0000-4000-8000-000000000002","description":"Old","status":"deleted","entry":"20240501T100000Z"},
+            {"uuid":"a1b2c3d4-0000-4000-8000-000000000003","description":"Done","status":"completed","entry":"someday"}
+        "#;
+
+        // # Action
+        let report = TaskwarriorMac::import(&db, parse_export(export)?, Some("tw")).await?;
+
+        // # Check
+        assert_eq!((report.mapped, report.skipped, report.conflicts), (1, 2, 0));
+        let task = &TaskMac::list(&db).await?[0];
+        assert_eq!(task.name, "Fix the fence");
+        assert_eq!(task.priority, TaskPriority::High);
+        assert_eq!(
+            task.creation_time,
+            "2024-05-01T10:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        let tags: Vec<&str> = task.tags.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(tags, vec!["+garden", "outside"]);
+
+        let exported = TaskwarriorMac::export(&db).await?;
+        assert_eq!(exported.len(), 1);
+        assert_eq!(exported[0].uuid, "a1b2c3d4-0000-4000-8000-000000000001");
+        assert_eq!(exported[0].project.as_deref(), Some("garden"));
+        assert_eq!(exported[0].tags, vec!["outside".to_string()]);
+        assert_eq!(exported[0].annotations[0].description, "Buy nails first");
+        assert_eq!(exported[0].due.as_deref(), Some("20240510T000000Z"));
+        Ok(())
+    }
+
+    /// Test that a task with an invalid tag is skipped without leaving anything behind.
+    #[tokio::test]
+    async fn test_invalid_tags() -> Result<(), crate::Error> {
+        // # Fixture
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let export = br#"[
+            {"uuid":"b1b2c3d4-0000-4000-8000-000000000001","description":"Blank tag","status":"pending","tags":[" "]},
+            {"uuid":"b1b2c3d4-0000-4000-8000-000000000002","description":"Bad project","status":"pending","project":"a,b"}
+        ]"#;
+
+        for _ in 0..2 {
+            // # Action
+            let report = TaskwarriorMac::import(&db, parse_export(export)?, None).await?;
+
+            // # Check
+            assert_eq!((report.mapped, report.skipped), (0, 2));
+            assert!(TaskMac::list(&db).await?.is_empty());
+        }
+        Ok(())
+    }
+
+    /// Test that a second import updates, skips or flags tasks by what changed where.
+    #[tokio::test]
+    async fn test_reimport() -> Result<(), crate::Error> {
+        // # Fixture
+        let db = create_and_connect(DbAddress::Memory).await?;
+        for name in ["Here", "There"] {
+            let patch = TaskPatch {
+                name: Some(name.to_string()),
+                ..Default::default()
+            };
+            TaskMac::insert(&db, patch).await?;
+        }
+        let exported = TaskwarriorMac::export(&db).await?;
+        assert!(exported[0].uuid.len() == 36 && exported[0].uuid != exported[1].uuid);
+        // Exporting links nothing, and gives the same UUIDs again.
+        let links: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM taskwarrior_links")
+            .fetch_one(&db)
+            .await?;
+        assert_eq!(links, 0);
+        assert_eq!(TaskwarriorMac::export(&db).await?, exported);
+
+        // # Unchanged
+        let values = |tasks: &[TwTask]| tasks.iter().map(|t| json!(t)).collect::<Vec<_>>();
+        let report = TaskwarriorMac::import(&db, values(&exported), None).await?;
+        assert_eq!((report.mapped, report.unchanged, report.skipped), (0, 2, 0));
+        assert_eq!(report.issues[0].kind, IssueKind::Unchanged);
+
+        // # Changed in Taskwarrior, and here as well for the first task
+        let mut changed = exported.clone();
+        for tw_task in &mut changed {
+            tw_task.description.push_str(" (edited)");
+            tw_task.modified = Some("20990101T000000Z".to_string());
+        }
+        let rename = TaskPatch {
+            name: Some("Here, renamed".to_string()),
+            ..Default::default()
+        };
+        TaskMac::update(&db, 1, rename).await?;
+        let report = TaskwarriorMac::import(&db, values(&changed), None).await?;
+
+        // # Check
+        assert_eq!((report.mapped, report.conflicts), (1, 1));
+        assert_eq!(report.issues[0].index, 0);
+        let names: Vec<String> = TaskMac::list(&db)
+            .await?
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
+        assert_eq!(names, vec!["Here, renamed", "There (edited)"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,677 @@
+//! Bulk export and import of tasks as JSON or CSV. Both formats hold the same
+//! records: one per task, with its tags by name. CSV joins the tags with commas,
+//! which tag names cannot contain.
+
+use super::events::EventMac;
+use super::plaintext;
+use super::tag::TagMac;
+use super::task::{task_columns, Task, TaskMac, TaskPatch, TaskPriority, TaskStatus};
+use crate::database::Database;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{Connection, SqliteConnection};
+use std::collections::{HashMap, HashSet};
+
+/// File format of an export or import.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferFormat {
+    #[default]
+    Json,
+    Csv,
+    /// `todo.txt` lines, see [`super::plaintext`].
+    Todotxt,
+    /// A Markdown checklist, see [`super::plaintext`].
+    Markdown,
+}
+
+impl TransferFormat {
+    /// Whether records carry task ids. Plain text formats do not, so importing them
+    /// always creates new tasks.
+    pub fn keeps_ids(&self) -> bool {
+        matches!(self, TransferFormat::Json | TransferFormat::Csv)
+    }
+}
+
+/// How imported records become tasks.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    /// Update the task with the record's id, or create it under that id.
+    #[default]
+    Upsert,
+    /// Create a new task for every record. Record ids only link subtasks to their
+    /// parents within the file.
+    Create,
+}
+
+/// A task as exported and imported.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TaskRecord {
+    #[serde(default)]
+    pub id: Option<i64>,
+    pub name: String,
+    #[serde(default)]
+    pub status: TaskStatus,
+    /// Kept for new tasks only; updates leave the creation time alone.
+    #[serde(default)]
+    pub creation_time: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub priority: TaskPriority,
+    #[serde(default)]
+    pub due_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub start_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub parent_id: Option<i64>,
+    #[serde(default)]
+    pub recurrence: Option<String>,
+    /// Tag names.
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl From<Task> for TaskRecord {
+    fn from(task: Task) -> Self {
+        TaskRecord {
+            id: Some(task.id),
+            name: task.name,
+            status: task.status,
+            creation_time: Some(task.creation_time),
+            priority: task.priority,
+            due_at: task.due_at,
+            start_at: task.start_at,
+            parent_id: task.parent_id,
+            recurrence: task.recurrence,
+            tags: task.tags.into_iter().map(|tag| tag.name).collect(),
+        }
+    }
+}
+
+/// A task record as a CSV row. Same fields as [`TaskRecord`], with the tags in one
+/// column.
+#[derive(Debug, Serialize, Deserialize)]
+struct CsvRecord {
+    id: Option<i64>,
+    name: String,
+    #[serde(default)]
+    status: Option<TaskStatus>,
+    #[serde(default)]
+    creation_time: Option<DateTime<Utc>>,
+    #[serde(default)]
+    priority: Option<TaskPriority>,
+    #[serde(default)]
+    due_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    start_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    parent_id: Option<i64>,
+    #[serde(default)]
+    recurrence: Option<String>,
+    #[serde(default)]
+    tags: String,
+}
+
+/// Column names of a CSV export, in the order of [`CsvRecord`].
+const CSV_HEADERS: [&str; 10] = [
+    "id",
+    "name",
+    "status",
+    "creation_time",
+    "priority",
+    "due_at",
+    "start_at",
+    "parent_id",
+    "recurrence",
+    "tags",
+];
+
+impl From<TaskRecord> for CsvRecord {
+    fn from(record: TaskRecord) -> Self {
+        CsvRecord {
+            id: record.id,
+            name: record.name,
+            status: Some(record.status),
+            creation_time: record.creation_time,
+            priority: Some(record.priority),
+            due_at: record.due_at,
+            start_at: record.start_at,
+            parent_id: record.parent_id,
+            recurrence: record.recurrence,
+            tags: record.tags.join(","),
+        }
+    }
+}
+
+impl From<CsvRecord> for TaskRecord {
+    fn from(row: CsvRecord) -> Self {
+        TaskRecord {
+            id: row.id,
+            name: row.name,
+            status: row.status.unwrap_or_default(),
+            creation_time: row.creation_time,
+            priority: row.priority.unwrap_or_default(),
+            due_at: row.due_at,
+            start_at: row.start_at,
+            parent_id: row.parent_id,
+            recurrence: row.recurrence.filter(|r| !r.is_empty()),
+            tags: row
+                .tags
+                .split(',')
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+}
+
+/// Header line of a CSV export.
+pub fn csv_header() -> Vec<u8> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record(CSV_HEADERS)
+        .expect("writing to memory cannot fail");
+    writer.into_inner().unwrap_or_default()
+}
+
+/// Lines of a CSV export for the given records, without the header.
+pub fn csv_rows(records: Vec<TaskRecord>) -> Vec<u8> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    for record in records {
+        writer
+            .serialize(CsvRecord::from(record))
+            .expect("writing to memory cannot fail");
+    }
+    writer.into_inner().unwrap_or_default()
+}
+
+/// A record of an import by its 1-based row number, or why it could not be parsed.
+pub type ParsedRecord = (usize, Result<TaskRecord, String>);
+
+/// Parse the records of an import. A record that cannot be parsed is reported with
+/// its 1-based row number, or line number for plain text; the file as a whole fails
+/// only if it is not a JSON array, not CSV or not text at all.
+pub fn parse_records(
+    format: TransferFormat,
+    data: &[u8],
+) -> Result<Vec<ParsedRecord>, crate::Error> {
+    match format {
+        TransferFormat::Json => {
+            let values: Vec<Value> = serde_json::from_slice(data).map_err(|e| {
+                crate::Error::Validation(format!("Import must be a JSON array: {}", e))
+            })?;
+            Ok(values
+                .into_iter()
+                .enumerate()
+                .map(|(i, value)| {
+                    (
+                        i + 1,
+                        serde_json::from_value(value).map_err(|e| e.to_string()),
+                    )
+                })
+                .collect())
+        }
+        TransferFormat::Csv => {
+            let mut reader = csv::Reader::from_reader(data);
+            let headers = reader
+                .headers()
+                .map_err(|e| crate::Error::Validation(format!("Invalid CSV header: {}", e)))?;
+            if !headers.iter().any(|header| header == "name") {
+                return Err(crate::Error::Validation(
+                    "CSV import needs a name column.".to_string(),
+                ));
+            }
+            Ok(reader
+                .deserialize::<CsvRecord>()
+                .enumerate()
+                .map(|(i, row)| (i + 1, row.map(TaskRecord::from).map_err(|e| e.to_string())))
+                .collect())
+        }
+        TransferFormat::Todotxt | TransferFormat::Markdown => {
+            let text = std::str::from_utf8(data)
+                .map_err(|_| crate::Error::Validation("Import must be UTF-8.".to_string()))?;
+            Ok(match format {
+                TransferFormat::Todotxt => plaintext::parse_todo_txt(text),
+                _ => plaintext::parse_markdown(text),
+            })
+        }
+    }
+}
+
+/// A record that could not be imported.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RowError {
+    /// 1-based position of the record in the file.
+    pub row: usize,
+    /// Id of the record, if it has one.
+    pub id: Option<i64>,
+    pub message: String,
+}
+
+/// Outcome of an import.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct ImportReport {
+    /// Whether the import was rolled back instead of committed.
+    pub dry_run: bool,
+    pub created: usize,
+    pub updated: usize,
+    pub errors: Vec<RowError>,
+}
+
+/// Where a record goes, once checked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Target {
+    /// Create a task, under the given id if any.
+    Create(Option<i64>),
+    /// Update the task with this id.
+    Update(i64),
+}
+
+/// Export and import model access controller.
+pub struct TransferMac;
+
+impl TransferMac {
+    const BATCH_SQL: &'static str = concat!(
+        "SELECT ",
+        task_columns!(),
+        " FROM tasks WHERE deleted_at IS NULL AND id > ? ORDER BY id LIMIT ?"
+    );
+    const STATE_SQL: &'static str = "SELECT deleted_at IS NOT NULL FROM tasks WHERE id = ?";
+
+    /// Up to `limit` tasks with an id greater than `after`, in id order, for an export.
+    pub async fn export_batch(
+        db: &Database,
+        after: i64,
+        limit: usize,
+    ) -> Result<Vec<TaskRecord>, crate::Error> {
+        let mut tasks = sqlx::query_as::<_, Task>(Self::BATCH_SQL)
+            .bind(after)
+            .bind(limit as i64)
+            .fetch_all(db)
+            .await?;
+        TagMac::load_for(db, &mut tasks).await?;
+        Ok(tasks.into_iter().map(TaskRecord::from).collect())
+    }
+
+    /// All tasks, in id order, for an export that cannot be written in batches.
+    pub async fn export_all(db: &Database) -> Result<Vec<TaskRecord>, crate::Error> {
+        let mut records = Vec::new();
+        loop {
+            let after = records.last().and_then(|r: &TaskRecord| r.id).unwrap_or(0);
+            let batch = Self::export_batch(db, after, 1000).await?;
+            if batch.is_empty() {
+                return Ok(records);
+            }
+            records.extend(batch);
+        }
+    }
+
+    /// Import parsed records on behalf of `client`, in one transaction. Invalid records
+    /// are reported and skipped; the others are imported. With `dry_run`, records are
+    /// imported the same way and then rolled back.
+    ///
+    /// Parents are set once all records are in, so that a subtask may come before its
+    /// parent. A record whose parent cannot be set is imported as a top-level task and
+    /// reported.
+    pub async fn import(
+        db: &Database,
+        records: Vec<ParsedRecord>,
+        mode: ImportMode,
+        dry_run: bool,
+        client: Option<&str>,
+    ) -> Result<ImportReport, crate::Error> {
+        let mut report = ImportReport {
+            dry_run,
+            ..Default::default()
+        };
+        let file_ids: HashSet<i64> = records
+            .iter()
+            .filter_map(|(_, record)| record.as_ref().ok().and_then(|r| r.id))
+            .collect();
+        let mut tx = db.begin().await?;
+
+        // Check every record first.
+        let mut seen = HashSet::new();
+        let mut checked = Vec::new();
+        for (row, record) in records {
+            let record = match record {
+                Ok(record) => record,
+                Err(message) => {
+                    report.errors.push(RowError {
+                        row,
+                        id: None,
+                        message,
+                    });
+                    continue;
+                }
+            };
+            let id = record.id;
+            let target = match Self::check(&mut tx, &record, mode, &file_ids, &mut seen).await {
+                Ok(target) => target,
+                Err(crate::Error::Internal(e)) => return Err(crate::Error::Internal(e)),
+                Err(e) => {
+                    report.errors.push(RowError {
+                        row,
+                        id,
+                        message: e.to_string(),
+                    });
+                    continue;
+                }
+            };
+            checked.push((row, record, target));
+        }
+
+        // Write the tasks without their parents, remembering where each record went.
+        // Each record is written in a savepoint, so that a failed one leaves nothing
+        // behind.
+        let now = Utc::now();
+        let mut imported: HashMap<i64, i64> = HashMap::new();
+        let mut parents = Vec::new();
+        for (row, record, target) in checked {
+            let mut patch = TaskPatch {
+                name: Some(record.name),
+                status: Some(record.status),
+                priority: Some(record.priority),
+                due_at: Some(record.due_at),
+                start_at: Some(record.start_at),
+                parent_id: None,
+                recurrence: Some(record.recurrence),
+                version: None,
+            };
+            let mut savepoint = tx.begin().await?;
+            let result = match target {
+                Target::Create(id) => {
+                    TaskMac::insert_in(&mut savepoint, id, record.creation_time, patch, client, now)
+                        .await
+                        .map(|task| (task.id, true))
+                }
+                Target::Update(id) => {
+                    if record.parent_id.is_none() {
+                        patch.parent_id = Some(None);
+                    }
+                    TaskMac::update_in(&mut savepoint, id, patch, client, now)
+                        .await
+                        .map(|_| (id, false))
+                }
+            };
+            let result = match result {
+                Ok((id, created)) => TagMac::set_for_in(&mut savepoint, id, &record.tags, client)
+                    .await
+                    .map(|_| (id, created)),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok((id, created)) => {
+                    savepoint.commit().await?;
+                    if created {
+                        report.created += 1;
+                    } else {
+                        report.updated += 1;
+                    }
+                    if let Some(record_id) = record.id {
+                        imported.insert(record_id, id);
+                    }
+                    if let Some(parent_id) = record.parent_id {
+                        parents.push((row, record.id, id, parent_id));
+                    }
+                }
+                Err(crate::Error::Internal(e)) => return Err(crate::Error::Internal(e)),
+                Err(e) => report.errors.push(RowError {
+                    row,
+                    id: record.id,
+                    message: e.to_string(),
+                }),
+            }
+        }
+
+        // Link subtasks to their parents, which now all exist.
+        for (row, record_id, id, parent_id) in parents {
+            let parent_id = match imported.get(&parent_id) {
+                Some(imported_id) => *imported_id,
+                None if file_ids.contains(&parent_id) => {
+                    report.errors.push(RowError {
+                        row,
+                        id: record_id,
+                        message: format!("Parent {} was not imported.", parent_id),
+                    });
+                    continue;
+                }
+                None => parent_id,
+            };
+            let patch = TaskPatch {
+                parent_id: Some(Some(parent_id)),
+                ..Default::default()
+            };
+            let mut savepoint = tx.begin().await?;
+            match TaskMac::update_in(&mut savepoint, id, patch, client, now).await {
+                Ok(_) => savepoint.commit().await?,
+                Err(crate::Error::Internal(e)) => return Err(crate::Error::Internal(e)),
+                Err(e) => report.errors.push(RowError {
+                    row,
+                    id: record_id,
+                    message: e.to_string(),
+                }),
+            }
+        }
+        if !dry_run {
+            tx.commit().await?;
+            EventMac::notify(db);
+        }
+        report.errors.sort_by_key(|e| e.row);
+        Ok(report)
+    }
+
+    /// Check a record and decide where it goes.
+    async fn check(
+        conn: &mut SqliteConnection,
+        record: &TaskRecord,
+        mode: ImportMode,
+        file_ids: &HashSet<i64>,
+        seen: &mut HashSet<i64>,
+    ) -> Result<Target, crate::Error> {
+        let patch = TaskPatch {
+            name: Some(record.name.clone()),
+            due_at: Some(record.due_at),
+            start_at: Some(record.start_at),
+            recurrence: Some(record.recurrence.clone()),
+            ..Default::default()
+        };
+        TaskMac::validate(&patch)?;
+        for name in &record.tags {
+            TagMac::validate(&super::tag::TagPatch { name: name.clone() })?;
+        }
+        if let Some(id) = record.id {
+            if !seen.insert(id) {
+                return Err(crate::Error::Validation(format!(
+                    "Duplicate id {} in the import.",
+                    id
+                )));
+            }
+        }
+        if let Some(parent_id) = record.parent_id {
+            if Some(parent_id) == record.id {
+                return Err(crate::Error::Validation(
+                    "A task cannot be its own parent.".to_string(),
+                ));
+            }
+            if !file_ids.contains(&parent_id)
+                && Self::trashed(conn, parent_id).await? != Some(false)
+            {
+                return Err(crate::Error::Validation(format!(
+                    "Parent task {} not found.",
+                    parent_id
+                )));
+            }
+        }
+        match (mode, record.id) {
+            (ImportMode::Create, _) | (ImportMode::Upsert, None) => Ok(Target::Create(None)),
+            (ImportMode::Upsert, Some(id)) if id <= 0 => Err(crate::Error::Validation(format!(
+                "Task id {} must be positive.",
+                id
+            ))),
+            (ImportMode::Upsert, Some(id)) => match Self::trashed(conn, id).await? {
+                None => Ok(Target::Create(Some(id))),
+                Some(false) => Ok(Target::Update(id)),
+                Some(true) => Err(crate::Error::Validation(format!(
+                    "Task {} is in the trash.",
+                    id
+                ))),
+            },
+        }
+    }
+
+    /// Whether the task with this id is in the trash, or `None` if there is no such
+    /// task.
+    async fn trashed(conn: &mut SqliteConnection, id: i64) -> Result<Option<bool>, crate::Error> {
+        let trashed = sqlx::query_scalar(Self::STATE_SQL)
+            .bind(id)
+            .fetch_optional(&mut *conn)
+            .await?;
+        Ok(trashed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+
+    fn record(id: i64, name: &str, parent_id: Option<i64>) -> TaskRecord {
+        TaskRecord {
+            id: Some(id),
+            name: name.to_string(),
+            parent_id,
+            ..Default::default()
+        }
+    }
+
+    /// Test that an export imports into an empty database unchanged, in both formats.
+    #[tokio::test]
+    async fn test_round_trip() -> Result<(), crate::Error> {
+        // # Fixture
+        let source = create_and_connect(DbAddress::Memory).await?;
+        let records = vec![
+            TaskRecord {
+                status: TaskStatus::Closed,
+                priority: TaskPriority::High,
+                due_at: "2024-05-01T10:00:00Z".parse().ok(),
+                recurrence: Some("FREQ=WEEKLY".to_string()),
+                tags: vec!["home".to_string(), "weekly".to_string()],
+                ..record(4, "Water the plants, then rest", Some(9))
+            },
+            record(9, "Garden", None),
+        ];
+        let report = TransferMac::import(
+            &source,
+            records.into_iter().map(|r| (0, Ok(r))).collect(),
+            ImportMode::Upsert,
+            false,
+            None,
+        )
+        .await?;
+        assert_eq!(report.created, 2);
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        let exported = TransferMac::export_batch(&source, 0, 100).await?;
+
+        let files = [
+            (TransferFormat::Json, serde_json::to_vec(&exported).unwrap()),
+            (
+                TransferFormat::Csv,
+                [csv_header(), csv_rows(exported.clone())].concat(),
+            ),
+        ];
+        for (format, data) in files {
+            // # Action
+            let target = create_and_connect(DbAddress::Memory).await?;
+            let records = parse_records(format, &data)?;
+            TransferMac::import(&target, records, ImportMode::Upsert, false, None).await?;
+
+            // # Check
+            assert_eq!(TransferMac::export_batch(&target, 0, 100).await?, exported);
+        }
+        Ok(())
+    }
+
+    /// Test that a dry run reports every invalid row and writes nothing.
+    #[tokio::test]
+    async fn test_dry_run() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let data = br#"[
+            {"id": 1, "name": "Fine"},
+            {"id": 2, "name": " "},
+            {"name": "Orphan", "parent_id": 77},
+            {"id": 1, "name": "Twice"},
+            {"name": 5},
+            {"id": 0, "name": "Zero"},
+            {"id": -3, "name": "Negative"}
+        ]"#;
+        let records = parse_records(TransferFormat::Json, data)?;
+        let report = TransferMac::import(&db, records, ImportMode::Upsert, true, None).await?;
+
+        let rows: Vec<usize> = report.errors.iter().map(|e| e.row).collect();
+        assert_eq!(rows, vec![2, 3, 4, 5, 6, 7]);
+        assert_eq!(report.created, 1);
+        assert!(TransferMac::export_batch(&db, 0, 100).await?.is_empty());
+        Ok(())
+    }
+
+    /// Test that a dry run reports the same errors as the real import, including those
+    /// only found while writing and linking.
+    #[tokio::test]
+    async fn test_dry_run_matches_import() -> Result<(), crate::Error> {
+        // # Fixture
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let records = || {
+            vec![
+                (1, Ok(record(1, "Egg", Some(2)))),
+                (2, Ok(record(2, "Chicken", Some(1)))),
+                (3, Ok(record(3, " ", None))),
+                (4, Ok(record(4, "Child", Some(3)))),
+            ]
+        };
+
+        // # Action
+        let dry = TransferMac::import(&db, records(), ImportMode::Upsert, true, None).await?;
+        assert!(TransferMac::export_batch(&db, 0, 100).await?.is_empty());
+        let real = TransferMac::import(&db, records(), ImportMode::Upsert, false, None).await?;
+
+        // # Check
+        let rows: Vec<usize> = real.errors.iter().map(|e| e.row).collect();
+        assert_eq!(rows, vec![2, 3, 4]);
+        assert_eq!(real.errors[2].message, "Parent 3 was not imported.");
+        assert_eq!(
+            dry,
+            ImportReport {
+                dry_run: true,
+                ..real
+            }
+        );
+        Ok(())
+    }
+
+    /// Test that create mode makes new tasks and links parents within the file.
+    #[tokio::test]
+    async fn test_create_mode() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let records = || {
+            vec![
+                (1, Ok(record(2, "Child", Some(1)))),
+                (2, Ok(record(1, "Parent", None))),
+            ]
+        };
+        TransferMac::import(&db, records(), ImportMode::Create, false, None).await?;
+        let report = TransferMac::import(&db, records(), ImportMode::Create, false, None).await?;
+
+        assert_eq!(report.created, 2);
+        let tasks = TransferMac::export_batch(&db, 0, 100).await?;
+        assert_eq!(tasks.len(), 4);
+        // The second child belongs to the second parent.
+        assert_eq!(tasks[2].name, "Child");
+        assert_eq!(tasks[2].parent_id, tasks[3].id);
+        Ok(())
+    }
+}
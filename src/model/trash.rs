@@ -0,0 +1,352 @@
+//! Trash. Deleting a task moves it to the trash, where it can be restored or purged.
+//! Tasks in the trash are left out of every listing and search. Items older than the
+//! retention period are purged in the background.
+
+use super::events::EventMac;
+use super::history::{HistoryAction, HistoryMac};
+use super::tag::TagMac;
+use super::task::{task_columns, Task, TaskMac};
+use crate::database::Database;
+use log::{info, warn};
+use chrono::Duration;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::SqliteConnection;
+use std::sync::Arc;
+
+/// How often the background job purges expired items.
+const PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Ids of a task in the trash and the descendants that were moved to the trash
+/// together with it, in the same batch. Binds the task id twice.
+macro_rules! batch_ids {
+    () => {
+        r#"WITH RECURSIVE batch(id) AS (
+            SELECT id FROM tasks WHERE id = ? AND deleted_at IS NOT NULL
+            UNION
+            SELECT t.id FROM tasks t JOIN batch b ON t.parent_id = b.id
+            WHERE t.deleted_batch = (SELECT deleted_batch FROM tasks WHERE id = ?)
+        )"#
+    };
+}
+
+impl TaskMac {
+    const TRASH_LIST_SQL: &'static str = concat!(
+        "SELECT ",
+        task_columns!(),
+        " FROM tasks WHERE deleted_at IS NOT NULL ORDER BY deleted_batch DESC, id"
+    );
+    const BATCH_SQL: &'static str = concat!(
+        batch_ids!(),
+        " SELECT ",
+        task_columns!(),
+        " FROM tasks WHERE id IN batch ORDER BY id"
+    );
+    const NEXT_BATCH_SQL: &'static str = "SELECT COALESCE(MAX(deleted_batch), 0) + 1 FROM tasks";
+    /// A restored task whose parent is still in the trash becomes a top-level task.
+    const DETACHED_ON_RESTORE_SQL: &'static str = concat!(
+        "SELECT ",
+        task_columns!(),
+        " FROM tasks WHERE id = ? AND parent_id IN (SELECT id FROM tasks WHERE deleted_at IS NOT NULL)"
+    );
+    const RESTORE_SQL: &'static str = concat!(
+        batch_ids!(),
+        " UPDATE tasks SET deleted_at = NULL, deleted_batch = NULL, modified_at = unixepoch(), version = version + 1 WHERE id IN batch RETURNING ",
+        task_columns!()
+    );
+    /// Tasks trashed separately keep their place in the trash when their parent is purged.
+    const DETACHED_ON_PURGE_SQL: &'static str = concat!(
+        batch_ids!(),
+        " SELECT ",
+        task_columns!(),
+        " FROM tasks WHERE parent_id IN batch AND id NOT IN batch"
+    );
+    const PURGE_SQL: &'static str = concat!(batch_ids!(), " DELETE FROM tasks WHERE id IN batch");
+    const EXPIRED_SQL: &'static str = concat!(
+        "SELECT ",
+        task_columns!(),
+        " FROM tasks WHERE deleted_at < ? ORDER BY id"
+    );
+    /// Children of expired tasks that are not expired themselves. Binds the time twice.
+    const DETACHED_ON_EXPIRY_SQL: &'static str = concat!(
+        "SELECT ",
+        task_columns!(),
+        " FROM tasks WHERE parent_id IN (SELECT id FROM tasks WHERE deleted_at < ?)",
+        " AND (deleted_at IS NULL OR deleted_at >= ?)"
+    );
+    const PURGE_EXPIRED_SQL: &'static str = "DELETE FROM tasks WHERE deleted_at < ?";
+    const DETACH_SQL: &'static str = concat!(
+        "UPDATE tasks SET parent_id = NULL, modified_at = unixepoch(), version = version + 1,",
+        " field_times = json_set(field_times, '$.parent_id', CAST(unixepoch('subsec') * 1000 AS INTEGER))",
+        " WHERE id = ? RETURNING ",
+        task_columns!()
+    );
+
+    /// List the tasks in the trash, most recently deleted first.
+    pub async fn trash(db: &Database) -> Result<Vec<Task>, crate::Error> {
+        let mut tasks = sqlx::query_as::<_, Task>(Self::TRASH_LIST_SQL)
+            .fetch_all(db)
+            .await?;
+        TagMac::load_for(db, &mut tasks).await?;
+        Ok(tasks)
+    }
+
+    /// Restore a task from the trash, together with the subtasks deleted with it.
+    pub async fn restore(
+        db: &Database,
+        id: i64,
+        client: Option<&str>,
+    ) -> Result<Task, crate::Error> {
+        let mut tx = db.begin().await?;
+        let detached = sqlx::query_as::<_, Task>(Self::DETACHED_ON_RESTORE_SQL)
+            .bind(id)
+            .fetch_all(&mut *tx)
+            .await?;
+        Self::detach_from_parent(&mut tx, &detached, client).await?;
+        let restored = sqlx::query_as::<_, Task>(Self::RESTORE_SQL)
+            .bind(id)
+            .bind(id)
+            .fetch_all(&mut *tx)
+            .await?;
+        let Some(mut task) = restored.iter().find(|t| t.id == id).cloned() else {
+            return Err(trash_not_found(id));
+        };
+        for task in &restored {
+            HistoryMac::record_trash(&mut tx, HistoryAction::Restored, task, client).await?;
+        }
+        tx.commit().await?;
+        EventMac::notify(db);
+        TagMac::load_for(db, std::slice::from_mut(&mut task)).await?;
+        Ok(task)
+    }
+
+    /// Permanently remove a task from the trash, together with the subtasks deleted
+    /// with it.
+    pub async fn purge(db: &Database, id: i64, client: Option<&str>) -> Result<(), crate::Error> {
+        let mut tx = db.begin().await?;
+        let purged = sqlx::query_as::<_, Task>(Self::BATCH_SQL)
+            .bind(id)
+            .bind(id)
+            .fetch_all(&mut *tx)
+            .await?;
+        if purged.is_empty() {
+            return Err(trash_not_found(id));
+        }
+        let detached = sqlx::query_as::<_, Task>(Self::DETACHED_ON_PURGE_SQL)
+            .bind(id)
+            .bind(id)
+            .fetch_all(&mut *tx)
+            .await?;
+        Self::detach_from_parent(&mut tx, &detached, client).await?;
+        sqlx::query(Self::PURGE_SQL)
+            .bind(id)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        for task in &purged {
+            HistoryMac::record_trash(&mut tx, HistoryAction::Purged, task, client).await?;
+        }
+        tx.commit().await?;
+        EventMac::notify(db);
+        Ok(())
+    }
+
+    /// Permanently remove the tasks that were moved to the trash before `before`.
+    /// Returns the number of purged tasks.
+    pub async fn purge_expired(
+        db: &Database,
+        before: DateTime<Utc>,
+    ) -> Result<usize, crate::Error> {
+        let before = before.timestamp();
+        let mut tx = db.begin().await?;
+        let purged = sqlx::query_as::<_, Task>(Self::EXPIRED_SQL)
+            .bind(before)
+            .fetch_all(&mut *tx)
+            .await?;
+        let detached = sqlx::query_as::<_, Task>(Self::DETACHED_ON_EXPIRY_SQL)
+            .bind(before)
+            .bind(before)
+            .fetch_all(&mut *tx)
+            .await?;
+        Self::detach_from_parent(&mut tx, &detached, None).await?;
+        sqlx::query(Self::PURGE_EXPIRED_SQL)
+            .bind(before)
+            .execute(&mut *tx)
+            .await?;
+        for task in &purged {
+            HistoryMac::record_trash(&mut tx, HistoryAction::Purged, task, None).await?;
+        }
+        tx.commit().await?;
+        EventMac::notify(db);
+        Ok(purged.len())
+    }
+
+    /// Make tasks top-level tasks, as their parent leaves the trash without them.
+    async fn detach_from_parent(
+        conn: &mut SqliteConnection,
+        tasks: &[Task],
+        client: Option<&str>,
+    ) -> Result<(), crate::Error> {
+        for task in tasks {
+            let detached = sqlx::query_as::<_, Task>(Self::DETACH_SQL)
+                .bind(task.id)
+                .fetch_one(&mut *conn)
+                .await?;
+            HistoryMac::record(conn, Some(task), Some(&detached), client).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Purge items that have been in the trash for longer than `retention`, once an hour.
+/// Runs until the process exits.
+pub async fn purge_periodically(db: Arc<Database>, retention: Duration) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match TaskMac::purge_expired(&db, Utc::now() - retention).await {
+            Ok(0) => (),
+            Ok(count) => info!("Purged {} tasks from the trash.", count),
+            Err(e) => warn!("Failed to purge the trash: {}", e),
+        }
+    }
+}
+
+/// A new batch id for tasks moved to the trash together, so that they can be told
+/// apart from tasks deleted separately. Batches are numbered in order of deletion.
+pub(super) async fn next_trash_batch(conn: &mut SqliteConnection) -> Result<i64, crate::Error> {
+    let batch = sqlx::query_scalar(TaskMac::NEXT_BATCH_SQL)
+        .fetch_one(conn)
+        .await?;
+    Ok(batch)
+}
+
+/// `NotFound` error for a task that is not in the trash.
+fn trash_not_found(id: i64) -> crate::Error {
+    crate::Error::NotFound(format!("Task {} is not in the trash.", id))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use crate::model::hierarchy::ChildPolicy;
+    use crate::model::listing::TaskQuery;
+    use crate::model::task::TaskPatch;
+
+    async fn insert(
+        db: &Database,
+        name: &str,
+        parent_id: Option<i64>,
+    ) -> Result<Task, crate::Error> {
+        TaskMac::insert(
+            db,
+            TaskPatch {
+                name: Some(name.to_string()),
+                parent_id: Some(parent_id),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    /// Test that deleted tasks are hidden until they are restored.
+    #[tokio::test]
+    async fn test_delete_and_restore() -> Result<(), crate::Error> {
+        // # Fixture
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let parent = insert(&db, "Plan trip", None).await?;
+        let child = insert(&db, "Book trip hotel", Some(parent.id)).await?;
+
+        // # Action
+        TaskMac::delete_with(&db, parent.id, ChildPolicy::Cascade, None).await?;
+
+        // # Check
+        assert!(matches!(
+            TaskMac::get(&db, parent.id).await,
+            Err(crate::Error::NotFound(_))
+        ));
+        assert!(TaskMac::list(&db).await?.is_empty());
+        assert_eq!(
+            TaskMac::list_page(&db, &TaskQuery::default()).await?.total,
+            0
+        );
+        assert!(TaskMac::search(&db, "trip", 10).await?.is_empty());
+        let trash = TaskMac::trash(&db).await?;
+        assert_eq!(trash.len(), 2);
+        assert!(trash.iter().all(|t| t.deleted_at.is_some()));
+
+        // Restoring the parent brings back the subtask deleted with it
+        let restored = TaskMac::restore(&db, parent.id, None).await?;
+        assert_eq!(restored.deleted_at, None);
+        assert_eq!(
+            TaskMac::get(&db, child.id).await?.parent_id,
+            Some(parent.id)
+        );
+        assert!(TaskMac::trash(&db).await?.is_empty());
+        assert!(matches!(
+            TaskMac::restore(&db, parent.id, None).await,
+            Err(crate::Error::NotFound(_))
+        ));
+        Ok(())
+    }
+
+    /// Test that a subtask restored without its parent becomes a top-level task.
+    #[tokio::test]
+    async fn test_restore_without_parent() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let parent = insert(&db, "Parent", None).await?;
+        let child = insert(&db, "Child", Some(parent.id)).await?;
+        TaskMac::delete_with(&db, parent.id, ChildPolicy::Cascade, None).await?;
+
+        let restored = TaskMac::restore(&db, child.id, None).await?;
+        assert_eq!(restored.parent_id, None);
+        assert_eq!(TaskMac::trash(&db).await?.len(), 1);
+        Ok(())
+    }
+
+    /// Test purging single items and expired items.
+    #[tokio::test]
+    async fn test_purge() -> Result<(), crate::Error> {
+        // # Fixture
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let parent = insert(&db, "Parent", None).await?;
+        let child = insert(&db, "Child", Some(parent.id)).await?;
+        let other = insert(&db, "Other", None).await?;
+        TaskMac::delete(&db, child.id).await?;
+        TaskMac::delete(&db, parent.id).await?;
+        TaskMac::delete(&db, other.id).await?;
+
+        // # Action
+        TaskMac::purge(&db, parent.id, None).await?;
+
+        // # Check
+        // The child was deleted on its own, so it stays in the trash.
+        let ids: Vec<i64> = TaskMac::trash(&db).await?.iter().map(|t| t.id).collect();
+        assert_eq!(ids, vec![other.id, child.id]);
+        assert!(matches!(
+            TaskMac::purge(&db, parent.id, None).await,
+            Err(crate::Error::NotFound(_))
+        ));
+        let history = HistoryMac::for_task(&db, parent.id).await?;
+        assert_eq!(history.last().unwrap().action, HistoryAction::Purged);
+        // The child lost its parent, as a recorded change.
+        let detached = &TaskMac::trash(&db).await?[1];
+        assert_eq!((detached.parent_id, detached.version), (None, 3));
+        let history = HistoryMac::for_task(&db, child.id).await?;
+        assert_eq!(
+            serde_json::json!(history.last().unwrap().new_values),
+            serde_json::json!({ "parent_id": null })
+        );
+
+        assert_eq!(
+            TaskMac::purge_expired(&db, Utc::now() - Duration::days(1)).await?,
+            0
+        );
+        assert_eq!(
+            TaskMac::purge_expired(&db, Utc::now() + Duration::days(1)).await?,
+            2
+        );
+        assert!(TaskMac::trash(&db).await?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+//! Saved views: named filter expressions with a sort order.
+
+use super::filter::Expr;
+use super::listing::{Page, Sort, TaskQuery};
+use super::task::{Task, TaskMac};
+use crate::database::Database;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// View model. View names are unique, ignoring case.
+#[derive(Debug, Default, FromRow, Clone, PartialEq, Serialize)]
+pub struct View {
+    pub id: i64,
+    pub name: String,
+    /// Expression in the filter language of [`super::filter`].
+    pub filter: String,
+    /// Sort order in the syntax of the `sort` query parameter.
+    pub sort: String,
+}
+
+/// Patch type for creating or updating a view.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct ViewPatch {
+    pub name: Option<String>,
+    pub filter: Option<String>,
+    pub sort: Option<String>,
+}
+
+/// View model access controller.
+pub struct ViewMac;
+
+impl ViewMac {
+    const INSERT_SQL: &'static str =
+        "INSERT INTO views (name, filter, sort) VALUES (?, ?, ?) RETURNING id, name, filter, sort";
+    const GET_SQL: &'static str = "SELECT id, name, filter, sort FROM views WHERE id = ?";
+    const LIST_SQL: &'static str = "SELECT id, name, filter, sort FROM views ORDER BY name";
+    const UPDATE_SQL: &'static str =
+        "UPDATE views SET name = ?, filter = ?, sort = ? WHERE id = ? RETURNING id, name, filter, sort";
+    const DELETE_SQL: &'static str = "DELETE FROM views WHERE id = ?";
+
+    /// Create a new view.
+    pub async fn insert(db: &Database, data: ViewPatch) -> Result<View, crate::Error> {
+        let view = Self::validate(View {
+            id: 0,
+            name: data.name.unwrap_or_default(),
+            filter: data.filter.unwrap_or_default(),
+            sort: data.sort.unwrap_or_default(),
+        })?;
+        let view = sqlx::query_as::<_, View>(Self::INSERT_SQL)
+            .bind(view.name)
+            .bind(view.filter)
+            .bind(view.sort)
+            .fetch_one(db)
+            .await?;
+        Ok(view)
+    }
+
+    /// Get a view by id.
+    pub async fn get(db: &Database, id: i64) -> Result<View, crate::Error> {
+        let view = sqlx::query_as::<_, View>(Self::GET_SQL)
+            .bind(id)
+            .fetch_one(db)
+            .await
+            .map_err(not_found(id))?;
+        Ok(view)
+    }
+
+    /// List all views by name.
+    pub async fn list(db: &Database) -> Result<Vec<View>, crate::Error> {
+        let views = sqlx::query_as::<_, View>(Self::LIST_SQL)
+            .fetch_all(db)
+            .await?;
+        Ok(views)
+    }
+
+    /// Update the fields that are set in a patch.
+    pub async fn update(db: &Database, id: i64, data: ViewPatch) -> Result<View, crate::Error> {
+        let current = Self::get(db, id).await?;
+        let view = Self::validate(View {
+            id,
+            name: data.name.unwrap_or(current.name),
+            filter: data.filter.unwrap_or(current.filter),
+            sort: data.sort.unwrap_or(current.sort),
+        })?;
+        let view = sqlx::query_as::<_, View>(Self::UPDATE_SQL)
+            .bind(view.name)
+            .bind(view.filter)
+            .bind(view.sort)
+            .bind(id)
+            .fetch_one(db)
+            .await
+            .map_err(not_found(id))?;
+        Ok(view)
+    }
+
+    /// Delete a view.
+    pub async fn delete(db: &Database, id: i64) -> Result<(), crate::Error> {
+        let result = sqlx::query(Self::DELETE_SQL).bind(id).execute(db).await?;
+        if result.rows_affected() == 0 {
+            return Err(not_found(id)(sqlx::Error::RowNotFound));
+        }
+        Ok(())
+    }
+
+    /// List one page of the tasks matching a view.
+    pub async fn tasks(
+        db: &Database,
+        id: i64,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Task>, crate::Error> {
+        let view = Self::get(db, id).await?;
+        let query = TaskQuery {
+            filter: Some(view.filter.parse()?),
+            sort: view.sort.parse()?,
+            cursor,
+            limit,
+            ..Default::default()
+        };
+        TaskMac::list_page(db, &query).await
+    }
+
+    /// Check a view and normalize its name and sort order.
+    fn validate(mut view: View) -> Result<View, crate::Error> {
+        view.name = view.name.trim().to_string();
+        if view.name.is_empty() {
+            return Err(crate::Error::Validation(
+                "View name must not be empty.".to_string(),
+            ));
+        }
+        view.filter.parse::<Expr>()?;
+        view.sort = view.sort.parse::<Sort>()?.to_string();
+        Ok(view)
+    }
+}
+
+/// Map a missing row to a `NotFound` error for the view with the given id.
+fn not_found(id: i64) -> impl FnOnce(sqlx::Error) -> crate::Error {
+    move |e| match e {
+        sqlx::Error::RowNotFound => crate::Error::NotFound(format!("View {} not found.", id)),
+        e => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use crate::model::task::{TaskPatch, TaskStatus};
+
+    fn patch(name: &str, filter: &str) -> ViewPatch {
+        ViewPatch {
+            name: Some(name.to_string()),
+            filter: Some(filter.to_string()),
+            sort: None,
+        }
+    }
+
+    /// Test creating, updating and deleting views.
+    #[tokio::test]
+    async fn test_view_lifecycle() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        let view = ViewMac::insert(&db, patch(" Open ", "status:open")).await?;
+        assert_eq!(view.name, "Open");
+        assert_eq!(view.sort, "id:asc");
+        assert_eq!(ViewMac::get(&db, view.id).await?, view);
+
+        // The conflict does not reveal the table or column.
+        assert_eq!(
+            ViewMac::insert(&db, patch("open", "status:closed"))
+                .await
+                .unwrap_err()
+                .to_string(),
+            "A resource with the same unique value already exists."
+        );
+        assert!(matches!(
+            ViewMac::insert(&db, patch("Broken", "status:")).await,
+            Err(crate::Error::Filter { column: 8, .. })
+        ));
+
+        let updated = ViewMac::update(
+            &db,
+            view.id,
+            ViewPatch {
+                sort: Some("priority".to_string()),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(updated.filter, "status:open");
+        assert_eq!(updated.sort, "priority:desc,id:asc");
+        assert_eq!(ViewMac::list(&db).await?, vec![updated]);
+
+        ViewMac::delete(&db, view.id).await?;
+        assert!(matches!(
+            ViewMac::get(&db, view.id).await,
+            Err(crate::Error::NotFound(_))
+        ));
+        Ok(())
+    }
+
+    /// Test listing the tasks of a view.
+    #[tokio::test]
+    async fn test_view_tasks() -> Result<(), crate::Error> {
+        let db = create_and_connect(DbAddress::Memory).await?;
+        for (name, status) in [("One", TaskStatus::Open), ("Two", TaskStatus::Closed)] {
+            TaskMac::insert(
+                &db,
+                TaskPatch {
+                    name: Some(name.to_string()),
+                    status: Some(status),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        }
+        let view = ViewMac::insert(&db, patch("Done", "status:closed")).await?;
+
+        let page = ViewMac::tasks(&db, view.id, None, 10).await?;
+        assert_eq!(page.total, 1);
+        assert_eq!(page.items[0].name, "Two");
+        Ok(())
+    }
+}
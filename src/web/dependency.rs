@@ -0,0 +1,143 @@
+use crate::database::Database;
+use crate::model::task::TaskMac;
+
+use super::task::{with_client, with_db};
+use super::{api_logger, json_response};
+
+use std::sync::Arc;
+use warp::reply::Json;
+use warp::Filter;
+
+pub fn dependency_rest_filters(
+    base_path: &'static str,
+    database: Arc<Database>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let task_path = warp::path(base_path)
+        .and(warp::path("tasks"))
+        .and(warp::path::param::<i64>()); // /api/tasks/:id
+    let blocker_path = task_path // /api/tasks/:id/blockers/:blocker_id
+        .and(warp::path("blockers"))
+        .and(warp::path::param::<i64>())
+        .and(warp::path::end());
+    let common = with_db(database.clone());
+
+    // List blockers and dependents (GET /api/tasks/:id/dependencies)
+    let list = task_path
+        .and(warp::path("dependencies"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(common.clone())
+        .and_then(dependency_list);
+
+    // Add blocker (PUT /api/tasks/:id/blockers/:blocker_id)
+    let add = blocker_path
+        .and(warp::put())
+        .and(common.clone())
+        .and(with_client())
+        .and_then(dependency_add);
+
+    // Remove blocker (DELETE /api/tasks/:id/blockers/:blocker_id)
+    let remove = blocker_path
+        .and(warp::delete())
+        .and(common.clone())
+        .and(with_client())
+        .and_then(dependency_remove);
+
+    list.or(add).or(remove).with(api_logger())
+}
+
+/// List the blockers and dependents of a task.
+async fn dependency_list(id: i64, database: Arc<Database>) -> Result<Json, warp::Rejection> {
+    let dependencies = TaskMac::dependencies(&database, id).await?;
+    json_response(dependencies)
+}
+
+/// Make a task blocked by another one.
+async fn dependency_add(
+    id: i64,
+    blocker_id: i64,
+    database: Arc<Database>,
+    client: Option<String>,
+) -> Result<Json, warp::Rejection> {
+    let task = TaskMac::add_dependency(&database, blocker_id, id, client.as_deref()).await?;
+    json_response(task)
+}
+
+/// Remove a blocker from a task.
+async fn dependency_remove(
+    id: i64,
+    blocker_id: i64,
+    database: Arc<Database>,
+    client: Option<String>,
+) -> Result<Json, warp::Rejection> {
+    let task = TaskMac::remove_dependency(&database, blocker_id, id, client.as_deref()).await?;
+    json_response(task)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use crate::model::task::TaskPatch;
+    use serde_json::json;
+    use std::io::Result;
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_dependencies() -> Result<()> {
+        // # Setup
+        let database = Arc::new(create_and_connect(DbAddress::Memory).await.unwrap());
+        for name in ["Blocker", "Blocked"] {
+            TaskMac::insert(
+                &database,
+                TaskPatch {
+                    name: Some(name.to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        }
+        let filters = dependency_rest_filters("api", database.clone())
+            .or(crate::web::task::task_rest_filters("api", database.clone()))
+            .recover(super::super::handle_rejection);
+
+        // # Add
+        let response = warp::test::request()
+            .method("PUT")
+            .path("/api/tasks/2/blockers/1")
+            .reply(&filters)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["data"]["blocked"], true);
+
+        let response = warp::test::request()
+            .method("PUT")
+            .path("/api/tasks/1/blockers/2")
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        // # List
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/tasks/1/dependencies")
+            .reply(&filters)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["data"]["blockers"], json!([]));
+        assert_eq!(body["data"]["dependents"][0]["id"], 2);
+
+        // # Close the blocker
+        let response = warp::test::request()
+            .method("PATCH")
+            .path("/api/tasks/1")
+            .json(&json!({"status": "Closed"}))
+            .reply(&filters)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["data"]["unblocked"][0]["id"], 2);
+        assert_eq!(body["data"]["unblocked"][0]["blocked"], false);
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use crate::database::Database;
+use crate::model::events::{EventMac, TaskEvent};
+
+use super::api_logger;
+use super::task::with_db;
+
+use futures_util::stream::{self, Stream};
+use log::warn;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::Arc;
+use warp::sse::Event;
+use warp::Filter;
+
+/// Events read from the database at a time.
+const BATCH_SIZE: usize = 100;
+
+pub fn event_filters(
+    base_path: &'static str,
+    database: Arc<Database>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // Stream task changes (GET /api/events, resuming after Last-Event-ID)
+    warp::path(base_path)
+        .and(warp::path("events"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_db(database))
+        .and(warp::header::optional::<String>("last-event-id"))
+        .and_then(event_stream)
+        .with(api_logger())
+}
+
+/// Stream the changes after the event given by `Last-Event-ID`, or after the latest
+/// event for a new client.
+async fn event_stream(
+    database: Arc<Database>,
+    last_event_id: Option<String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    // Subscribe first, so that changes made while starting up still wake us.
+    let changes = EventMac::subscribe(&database);
+    let after = match last_event_id {
+        Some(id) => id.trim().parse::<i64>().map_err(|_| {
+            crate::Error::Validation(format!("Invalid Last-Event-ID {:?}.", id))
+        })?,
+        None => EventMac::latest_id(&database).await?,
+    };
+    let events = task_events(database, after, changes);
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(events)))
+}
+
+/// Endless stream of the events after `after`, read in batches whenever a change
+/// wakes up `changes`.
+fn task_events(
+    database: Arc<Database>,
+    after: i64,
+    changes: tokio::sync::watch::Receiver<()>,
+) -> impl Stream<Item = Result<Event, Infallible>> + Send {
+    let state = (database, after, changes, VecDeque::<TaskEvent>::new());
+    stream::unfold(state, |(database, mut after, mut changes, mut pending)| async move {
+        loop {
+            if let Some(event) = pending.pop_front() {
+                let Some(sse) = sse_event(&event) else {
+                    continue;
+                };
+                return Some((Ok(sse), (database, after, changes, pending)));
+            }
+            match EventMac::since(&database, after, BATCH_SIZE).await {
+                Ok(events) if !events.is_empty() => {
+                    after = events.last().map(TaskEvent::id).unwrap_or(after);
+                    pending.extend(events);
+                    continue;
+                }
+                Ok(_) => (),
+                Err(e) => warn!("Failed to read task events: {}", e),
+            }
+            // The sender lives as long as the database, which the stream holds on to.
+            changes.changed().await.ok()?;
+        }
+    })
+}
+
+/// Server-sent event for a task change, named after its kind. Events that cannot be
+/// serialized are logged and skipped.
+fn sse_event(event: &TaskEvent) -> Option<Event> {
+    let data = match serde_json::to_string(event) {
+        Ok(data) => data,
+        Err(e) => {
+            warn!("Failed to serialize task event {}: {}", event.id(), e);
+            return None;
+        }
+    };
+    let event = Event::default()
+        .id(event.id().to_string())
+        .event(event.kind.name())
+        .data(data);
+    Some(event)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use crate::model::task::{TaskMac, TaskPatch};
+    use std::io::Result;
+    use std::time::Duration;
+    use warp::hyper::body::HttpBody;
+    use warp::Reply;
+
+    fn patch(name: &str) -> TaskPatch {
+        TaskPatch {
+            name: Some(name.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Read from the stream until `needle` shows up.
+    async fn read_until(body: &mut warp::hyper::Body, needle: &str) -> String {
+        let mut text = String::new();
+        while !text.contains(needle) {
+            let chunk = tokio::time::timeout(Duration::from_secs(5), body.data())
+                .await
+                .expect("timed out waiting for an event")
+                .unwrap()
+                .unwrap();
+            text.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        text
+    }
+
+    #[tokio::test]
+    async fn test_events() -> Result<()> {
+        // # Setup
+        let database = Arc::new(create_and_connect(DbAddress::Memory).await.unwrap());
+        let first = TaskMac::insert(&database, patch("First")).await.unwrap();
+
+        // # Replay from the start
+        let reply = event_stream(database.clone(), Some("0".to_string()))
+            .await
+            .unwrap();
+        let mut body = reply.into_response().into_body();
+        let text = read_until(&mut body, "First").await;
+        assert!(text.contains("event:created\n"));
+        assert!(text.contains("id:1\n"));
+
+        // # Live change
+        TaskMac::update(&database, first.id, patch("Renamed"))
+            .await
+            .unwrap();
+        let text = read_until(&mut body, "Renamed").await;
+        assert!(text.contains("event:updated\n"));
+        assert!(text.contains("id:2\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use crate::database::Database;
+use crate::model::history::HistoryMac;
+use crate::model::listing::DEFAULT_LIMIT;
+
+use super::task::with_db;
+use super::{api_logger, json_response, page_response};
+
+use serde::Deserialize;
+use std::sync::Arc;
+use warp::reply::Json;
+use warp::Filter;
+
+pub fn history_rest_filters(
+    base_path: &'static str,
+    database: Arc<Database>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let common = with_db(database.clone());
+
+    // History of a task (GET /api/tasks/:id/history)
+    let task_history = warp::path(base_path)
+        .and(warp::path("tasks"))
+        .and(warp::path::param())
+        .and(warp::path("history"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(common.clone())
+        .and_then(history_for_task);
+
+    // Changes to all tasks, newest first (GET /api/activity?limit=50&cursor=...)
+    let activity = warp::path(base_path)
+        .and(warp::path("activity"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(common.clone())
+        .and(warp::query::<ActivityQuery>())
+        .and_then(history_feed);
+
+    task_history.or(activity).with(api_logger())
+}
+
+/// List the history of a task, oldest first.
+async fn history_for_task(id: i64, database: Arc<Database>) -> Result<Json, warp::Rejection> {
+    let entries = HistoryMac::for_task(&database, id).await?;
+    json_response(entries)
+}
+
+/// Query parameters for the activity feed.
+#[derive(Debug, Deserialize)]
+struct ActivityQuery {
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
+/// List one page of the activity feed.
+async fn history_feed(
+    database: Arc<Database>,
+    query: ActivityQuery,
+) -> Result<Json, warp::Rejection> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    let page = HistoryMac::feed(&database, query.cursor.as_deref(), limit).await?;
+    page_response(page)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use serde_json::json;
+    use std::io::Result;
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_history() -> Result<()> {
+        // # Setup
+        let database = Arc::new(create_and_connect(DbAddress::Memory).await.unwrap());
+        let filters = super::super::task::task_rest_filters("api", database.clone())
+            .or(history_rest_filters("api", database.clone()))
+            .recover(super::super::handle_rejection);
+
+        // # Action
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/tasks")
+            .header("x-client-id", "phone")
+            .json(&json!({"name": "Call Bob"}))
+            .reply(&filters)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        let id = body["data"]["id"].as_i64().unwrap();
+        warp::test::request()
+            .method("PATCH")
+            .path(&format!("/api/tasks/{}", id))
+            .header("x-client-id", "laptop")
+            .json(&json!({"priority": "High"}))
+            .reply(&filters)
+            .await;
+
+        // # Check
+        let response = warp::test::request()
+            .method("GET")
+            .path(&format!("/api/tasks/{}/history", id))
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["data"][0]["action"], "created");
+        assert_eq!(body["data"][0]["client"], "phone");
+        assert_eq!(body["data"][1]["action"], "updated");
+        assert_eq!(body["data"][1]["client"], "laptop");
+        assert_eq!(body["data"][1]["old_values"], json!({"priority": "Normal"}));
+        assert_eq!(body["data"][1]["new_values"], json!({"priority": "High"}));
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/activity?limit=1")
+            .reply(&filters)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["total"], 2);
+        assert_eq!(body["data"][0]["action"], "updated");
+        assert!(body["next_cursor"].is_string());
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+use crate::database::Database;
+use crate::model::ical::{to_calendar, IcalMac};
+use crate::model::listing::MAX_LIMIT;
+use crate::model::task::TaskMac;
+
+use super::task::{with_client, with_db, ListQuery};
+use super::{api_logger, import_body, json_response};
+
+use serde::Deserialize;
+use std::sync::Arc;
+use warp::http::header::CONTENT_TYPE;
+use warp::http::Response;
+use warp::hyper::body::Bytes;
+use warp::reply::Json;
+use warp::Filter;
+
+pub fn ical_filters(
+    base_path: &'static str,
+    database: Arc<Database>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let calendar_path = warp::path(base_path)
+        .and(warp::path("calendar.ics"))
+        .and(warp::path::end()); // /api/calendar.ics
+    let common = with_db(database);
+
+    // Calendar feed of the tasks matching the query (GET /api/calendar.ics?status=Open)
+    let feed = calendar_path
+        .and(warp::get())
+        .and(common.clone())
+        .and(warp::query::<ListQuery>())
+        .and_then(calendar_feed);
+
+    // Import the VTODOs of a calendar (POST /api/calendar.ics?dry_run=true)
+    let import = calendar_path
+        .and(warp::post())
+        .and(warp::query::<ImportQuery>())
+        .and(import_body())
+        .and(common.clone())
+        .and(with_client())
+        .and_then(calendar_import);
+
+    feed.or(import).with(api_logger())
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportQuery {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// All tasks matching the query parameters of `GET /api/tasks`, as a calendar.
+/// Paging parameters are ignored.
+async fn calendar_feed(
+    database: Arc<Database>,
+    query: ListQuery,
+) -> Result<Response<String>, warp::Rejection> {
+    let mut query = query.into_task_query()?;
+    query.limit = MAX_LIMIT;
+    query.cursor = None;
+    let mut tasks = Vec::new();
+    loop {
+        let page = TaskMac::list_page(&database, &query).await?;
+        tasks.extend(page.items);
+        match page.next_cursor {
+            Some(cursor) => query.cursor = Some(cursor),
+            None => break,
+        }
+    }
+    let response = Response::builder()
+        .header(CONTENT_TYPE, "text/calendar; charset=utf-8")
+        .body(to_calendar(&tasks))
+        .expect("calendar response is valid");
+    Ok(response)
+}
+
+/// Create tasks from an uploaded calendar, and report what was done.
+async fn calendar_import(
+    query: ImportQuery,
+    body: Bytes,
+    database: Arc<Database>,
+    client: Option<String>,
+) -> Result<Json, warp::Rejection> {
+    let text = std::str::from_utf8(&body)
+        .map_err(|_| crate::Error::Validation("Calendar must be UTF-8.".to_string()))?;
+    let report = IcalMac::import(&database, text, query.dry_run, client.as_deref()).await?;
+    json_response(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use crate::model::task::{TaskPatch, TaskStatus};
+    use serde_json::Value;
+    use std::io::Result;
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_calendar() -> Result<()> {
+        // # Setup
+        let database = Arc::new(create_and_connect(DbAddress::Memory).await.unwrap());
+        for (name, status) in [
+            ("Renew passport", TaskStatus::Open),
+            ("Done", TaskStatus::Closed),
+        ] {
+            let patch = TaskPatch {
+                name: Some(name.to_string()),
+                status: Some(status),
+                ..Default::default()
+            };
+            TaskMac::insert(&database, patch).await.unwrap();
+        }
+        let filters = ical_filters("api", database.clone()).recover(super::super::handle_rejection);
+
+        // # Feed
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/calendar.ics?status=Open")
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[CONTENT_TYPE],
+            "text/calendar; charset=utf-8"
+        );
+        let text = std::str::from_utf8(response.body()).unwrap();
+        assert!(text.contains("SUMMARY:Renew passport\r\n"));
+        assert!(!text.contains("SUMMARY:Done"));
+
+        // # Import
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/calendar.ics")
+            .body(text.replace("Renew passport", "Renew visa"))
+            .reply(&filters)
+            .await;
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["data"]["created"], 1);
+        assert_eq!(TaskMac::list(&database).await.unwrap().len(), 3);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/calendar.ics")
+            .body("hello")
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        Ok(())
+    }
+}
@@ -1,47 +1,178 @@
+use crate::config::Config;
 use crate::database::Database;
+use crate::model::listing::Page;
+use crate::model::trash::purge_periodically;
 use crate::Error;
 use log::{error, info};
 use serde::Serialize;
 // use serde_json::{json, Value};
 use serde_json::json;
 use std::convert::Infallible;
+use std::net::SocketAddr;
 use std::path::Path;
 // use std::str::from_utf8;
 use std::sync::Arc;
 // use warp::hyper::{body::Bytes, Response};
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::hyper::body::Bytes;
 use warp::reply::Json;
 use warp::Filter;
 
 // use std::io::Result;
 
-mod task;
+pub mod dependency;
+pub mod events;
+pub mod history;
+pub mod ical;
+pub mod recurrence;
+pub mod sync;
+pub mod tag;
+pub mod task;
+pub mod taskwarrior;
+pub mod transfer;
+pub mod trash;
+pub mod view;
+pub mod ws;
 
-pub async fn serve(root_dir: &str, port: u16, database: Arc<Database>) -> Result<(), Error> {
-    if !Path::new(root_dir).exists() {
-        return Err(Error::RootNotFound(
-            "Root directory does not exist.".to_owned(),
-        ));
+/// All routes of the API under `/<base_path>`, e.g. `/api/tasks`. Rejections are left
+/// to the caller, so that the routes can be combined with others; recover them with
+/// [`handle_rejection`] to answer with the API's error bodies.
+pub fn api_filters(
+    base_path: &'static str,
+    database: Arc<Database>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    task::task_rest_filters(base_path, database.clone())
+        .or(tag::tag_rest_filters(base_path, database.clone()))
+        .or(dependency::dependency_rest_filters(
+            base_path,
+            database.clone(),
+        ))
+        .or(view::view_rest_filters(base_path, database.clone()))
+        .or(history::history_rest_filters(base_path, database.clone()))
+        .or(trash::trash_rest_filters(base_path, database.clone()))
+        .or(events::event_filters(base_path, database.clone()))
+        .or(ws::ws_filters(base_path, database.clone()))
+        .or(sync::sync_rest_filters(base_path, database.clone()))
+        .or(transfer::transfer_rest_filters(base_path, database.clone()))
+        .or(ical::ical_filters(base_path, database.clone()))
+        .or(taskwarrior::taskwarrior_rest_filters(base_path, database))
+        .or(recurrence::recurrence_rest_filters(base_path))
+}
+
+/// Builder for the web server: the API, and optionally the frontend as a static site.
+///
+/// ```no_run
+/// # async fn run() -> Result<(), taskapp::Error> {
+/// use std::sync::Arc;
+/// use taskapp::{create_and_connect, DbAddress, ServerBuilder};
+///
+/// let database = create_and_connect(DbAddress::Path("tasks.sqlite".to_string())).await?;
+/// ServerBuilder::new(Arc::new(database))
+///     .address(([127, 0, 0, 1], 3000).into())
+///     .root_dir("frontend/dist")
+///     .run()
+///     .await
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ServerBuilder {
+    database: Arc<Database>,
+    address: SocketAddr,
+    base_path: &'static str,
+    /// Directory of the static site, served at `/`.
+    root_dir: Option<String>,
+    trash_retention: Option<chrono::Duration>,
+}
+
+impl ServerBuilder {
+    /// A server on the default address of [`Config`], with the API under `/api`, no
+    /// static site, and no purging of the trash.
+    pub fn new(database: Arc<Database>) -> Self {
+        ServerBuilder {
+            database,
+            address: Config::default().socket_address(),
+            base_path: "api",
+            root_dir: None,
+            trash_retention: None,
+        }
     }
 
-    // Static site
-    let content = warp::fs::dir(root_dir.to_string());
-    let index = warp::get()
-        .and(warp::path::end())
-        .and(warp::fs::file(format!("{}/index.html", root_dir)));
-    let static_site = content.or(index);
+    /// A server as the configuration says.
+    pub fn from_config(database: Arc<Database>, config: &Config) -> Self {
+        ServerBuilder::new(database)
+            .address(config.socket_address())
+            .root_dir(config.root_dir.clone())
+            .trash_retention(config.trash_retention())
+    }
 
-    let api = task::task_rest_filters("api", database);
+    pub fn address(mut self, address: SocketAddr) -> Self {
+        self.address = address;
+        self
+    }
 
-    // Combine routes
-    let routes = api.or(static_site).recover(handle_rejection);
+    /// First path segment of the API routes.
+    pub fn base_path(mut self, base_path: &'static str) -> Self {
+        self.base_path = base_path;
+        self
+    }
+
+    /// Serve the files of a directory, with its `index.html` at `/`.
+    pub fn root_dir(mut self, root_dir: impl Into<String>) -> Self {
+        self.root_dir = Some(root_dir.into());
+        self
+    }
 
-    info!(
-        "Starting server http://127.0.0.1:{} from {}",
-        port, root_dir
-    );
-    warp::serve(routes).run(([127, 0, 0, 1], port)).await;
+    /// Purge tasks that have been in the trash for longer than this, or never if `None`.
+    pub fn trash_retention(mut self, retention: Option<chrono::Duration>) -> Self {
+        self.trash_retention = retention;
+        self
+    }
+
+    /// All routes of the server, with rejections answered by [`handle_rejection`].
+    pub fn routes(&self) -> BoxedFilter<(Box<dyn warp::Reply>,)> {
+        let api = api_filters(self.base_path, self.database.clone())
+            .map(boxed_reply)
+            .boxed();
+        let routes = match &self.root_dir {
+            Some(root_dir) => {
+                let content = warp::fs::dir(root_dir.clone());
+                let index = warp::get()
+                    .and(warp::path::end())
+                    .and(warp::fs::file(format!("{}/index.html", root_dir)));
+                api.or(content.or(index).map(boxed_reply)).unify().boxed()
+            }
+            None => api,
+        };
+        routes.recover(handle_rejection).map(boxed_reply).boxed()
+    }
+
+    /// Run the server until the process exits.
+    pub async fn run(self) -> Result<(), Error> {
+        if let Some(root_dir) = &self.root_dir {
+            if !Path::new(root_dir).exists() {
+                return Err(Error::RootNotFound(
+                    "Root directory does not exist.".to_owned(),
+                ));
+            }
+        }
+        if let Some(retention) = self.trash_retention {
+            tokio::spawn(purge_periodically(self.database.clone(), retention));
+        }
+
+        info!(
+            "Starting server http://{} from {}",
+            self.address,
+            self.root_dir.as_deref().unwrap_or("(no static site)")
+        );
+        warp::serve(self.routes()).run(self.address).await;
+
+        Ok(())
+    }
+}
 
-    Ok(())
+fn boxed_reply(reply: impl warp::Reply + 'static) -> Box<dyn warp::Reply> {
+    Box::new(reply)
 }
 
 // fn extract_body_data<D>(response: Response<Bytes>) -> std::io::Result<D>
@@ -63,63 +194,152 @@ pub async fn serve(root_dir: &str, port: u16, database: Arc<Database>) -> Result
 /// A custom error for Warp-related stuff.
 #[derive(Debug, Clone)]
 pub struct WebError {
+    /// Stable, machine-readable error code.
     typ: &'static str,
-    #[allow(dead_code)]
+    status: StatusCode,
     message: String,
+    /// Position of the error in a filter expression, if any.
+    column: Option<usize>,
+    /// Current server copy of a resource that changed concurrently.
+    data: Option<serde_json::Value>,
 }
 
 impl warp::reject::Reject for WebError {}
 
 impl WebError {
-    pub fn rejection(typ: &'static str, message: String) -> warp::Rejection {
-        warp::reject::custom(WebError { typ, message })
+    pub fn new(status: StatusCode, typ: &'static str, message: impl Into<String>) -> Self {
+        WebError {
+            typ,
+            status,
+            message: message.into(),
+            column: None,
+            data: None,
+        }
     }
 }
 
 impl From<crate::Error> for WebError {
     fn from(e: crate::Error) -> Self {
-        WebError {
-            typ: "internal",
-            message: e.to_string(),
+        match e {
+            Error::NotFound(message) => WebError::new(StatusCode::NOT_FOUND, "notFound", message),
+            Error::Validation(message) => {
+                WebError::new(StatusCode::UNPROCESSABLE_ENTITY, "validation", message)
+            }
+            Error::Filter { message, column } => WebError {
+                column: Some(column),
+                ..WebError::new(StatusCode::UNPROCESSABLE_ENTITY, "filter", message)
+            },
+            Error::Conflict(message) => WebError::new(StatusCode::CONFLICT, "conflict", message),
+            Error::StaleVersion(ref current) => WebError {
+                data: Some(json!(current)),
+                ..WebError::new(StatusCode::CONFLICT, "staleVersion", e.to_string())
+            },
+            Error::Internal(_)
+            | Error::RootNotFound(_)
+            | Error::SchemaTooNew { .. }
+            | Error::Config(_) => {
+                // Details stay in the server log.
+                error!("Internal error: {}", e);
+                WebError::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "internal",
+                    "Internal server error.",
+                )
+            }
         }
     }
 }
 
 impl From<crate::Error> for warp::Rejection {
     fn from(other: crate::Error) -> Self {
-        let typ = match other {
-            Error::RootNotFound(_) => "rootDirNotFound",
-            Error::SqlxError(_) => "sqlError",
-        };
-        WebError::rejection(typ, format!("{}", other))
+        warp::reject::custom(WebError::from(other))
     }
 }
 
-async fn handle_rejection(err: warp::Rejection) -> Result<impl warp::Reply, Infallible> {
-    // error!("Error: {:?}", err);
-
-    // TODO: logging API?
-
-    let web_err: WebError = match err.find::<WebError>() {
-        Some(err) => err.clone(),
-        None => {
-            error!("Unknown error: {:?}", err);
-            WebError { typ: "unknown", message: format!("{:#?}", err)}
-        }
+/// Answer a rejection with the status and error body of the API.
+pub async fn handle_rejection(err: warp::Rejection) -> Result<impl warp::Reply, Infallible> {
+    let web_err: WebError = if let Some(err) = err.find::<WebError>() {
+        err.clone()
+    } else if err.is_not_found() {
+        WebError::new(StatusCode::NOT_FOUND, "routeNotFound", "No such route.")
+    } else if let Some(err) = err.find::<warp::filters::body::BodyDeserializeError>() {
+        WebError::new(StatusCode::UNPROCESSABLE_ENTITY, "invalidBody", err.to_string())
+    } else if let Some(err) = err.find::<warp::reject::InvalidQuery>() {
+        WebError::new(StatusCode::BAD_REQUEST, "invalidQuery", err.to_string())
+    } else if let Some(err) = err.find::<warp::reject::InvalidHeader>() {
+        WebError::new(StatusCode::BAD_REQUEST, "invalidHeader", err.to_string())
+    } else if let Some(err) = err.find::<warp::reject::UnsupportedMediaType>() {
+        WebError::new(StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupportedMediaType", err.to_string())
+    } else if let Some(err) = err.find::<warp::reject::PayloadTooLarge>() {
+        WebError::new(StatusCode::PAYLOAD_TOO_LARGE, "payloadTooLarge", err.to_string())
+    } else if let Some(err) = err.find::<warp::reject::MethodNotAllowed>() {
+        WebError::new(StatusCode::METHOD_NOT_ALLOWED, "methodNotAllowed", err.to_string())
+    } else {
+        error!("Unhandled rejection: {:?}", err);
+        WebError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal",
+            "Internal server error.",
+        )
     };
 
-    let result = json!({"error": {"type": web_err.typ, "message": web_err.message}});
-    let result = warp::reply::json(&result);
+    let result = warp::reply::json(&error_body(&web_err));
 
-    Ok(warp::reply::with_status(
-        result,
-        warp::http::StatusCode::BAD_REQUEST,
-    ))
+    Ok(warp::reply::with_status(result, web_err.status))
+}
+
+/// Response body for an error: the `error` object, and the `data` it carries if any.
+fn error_body(web_err: &WebError) -> serde_json::Value {
+    let mut result = json!({"error": {"type": web_err.typ, "message": web_err.message}});
+    if let Some(column) = web_err.column {
+        result["error"]["column"] = json!(column);
+    }
+    if let Some(data) = &web_err.data {
+        result["data"] = data.clone();
+    }
+    result
 }
 
 // # API Response helpers
 
+/// Largest file accepted by the import routes, in bytes.
+const MAX_IMPORT_BYTES: u64 = 16 * 1024 * 1024;
+
+/// The body of an import, rejected with `413` if it is larger than
+/// [`MAX_IMPORT_BYTES`].
+fn import_body() -> impl Filter<Extract = (Bytes,), Error = warp::Rejection> + Copy {
+    warp::body::content_length_limit(MAX_IMPORT_BYTES).and(warp::body::bytes())
+}
+
+/// Log API calls as JSON.
+fn api_logger() -> warp::log::Log<impl Fn(warp::log::Info) + Copy> {
+    warp::log::custom(|info| {
+        let body = json!(
+            {
+                "status": info.status().as_u16(),
+                "version": format!("{:?}", info.version()),
+                "method": info.method().as_str(),
+                "path": info.path(),
+                "elapsed": info.elapsed().as_secs_f64(),
+            }
+        );
+        if let Ok(json_str) = serde_json::to_string_pretty(&body) {
+            tracing::info!("{}", json_str);
+        }
+    })
+}
+
 fn json_response<D: Serialize>(data: D) -> Result<Json, warp::Rejection> {
     let response = json!({ "data": data});
     Ok(warp::reply::json(&response))
 }
+
+/// Wrap one page of a listing, with the cursor of the next page and the total count.
+fn page_response<D: Serialize>(page: Page<D>) -> Result<Json, warp::Rejection> {
+    let response = json!({
+        "data": page.items,
+        "next_cursor": page.next_cursor,
+        "total": page.total,
+    });
+    Ok(warp::reply::json(&response))
+}
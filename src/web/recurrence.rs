@@ -0,0 +1,96 @@
+use crate::model::recurrence::RRule;
+
+use super::{api_logger, json_response};
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use warp::reply::Json;
+use warp::Filter;
+
+/// Upper bound on the number of previewed occurrences.
+const MAX_PREVIEW: usize = 100;
+
+pub fn recurrence_rest_filters(
+    base_path: &'static str,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // Preview occurrences (GET /api/recurrence?rule=FREQ=WEEKLY&start=2024-01-01T09:00:00Z&count=5)
+    warp::path(base_path)
+        .and(warp::path("recurrence"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<PreviewQuery>())
+        .and_then(recurrence_preview)
+        .with(api_logger())
+}
+
+#[derive(Debug, Deserialize)]
+struct PreviewQuery {
+    rule: String,
+    /// First occurrence of the series. Defaults to now.
+    start: Option<DateTime<Utc>>,
+    #[serde(default = "PreviewQuery::default_count")]
+    count: usize,
+    /// IANA time zone the rule is evaluated in. Defaults to UTC.
+    tz: Option<String>,
+}
+
+impl PreviewQuery {
+    fn default_count() -> usize {
+        5
+    }
+}
+
+/// List the upcoming occurrences of a recurrence rule.
+async fn recurrence_preview(query: PreviewQuery) -> Result<Json, warp::Rejection> {
+    let rule: RRule = query.rule.parse()?;
+    let tz = match &query.tz {
+        Some(name) => name
+            .parse::<Tz>()
+            .map_err(|_| crate::Error::Validation(format!("Unknown time zone {:?}.", name)))?,
+        None => Tz::UTC,
+    };
+    let start = query.start.unwrap_or_else(Utc::now);
+    let occurrences: Vec<DateTime<Utc>> = rule
+        .occurrences(start, tz)
+        .take(query.count.min(MAX_PREVIEW))
+        .collect();
+    json_response(occurrences)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use std::io::Result;
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_preview() -> Result<()> {
+        let filters = recurrence_rest_filters("api").recover(super::super::handle_rejection);
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/recurrence?rule=FREQ=WEEKLY;BYDAY=MO,WE&start=2024-01-01T09:00:00Z&count=3")
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(
+            body["data"],
+            json!([
+                "2024-01-01T09:00:00Z",
+                "2024-01-03T09:00:00Z",
+                "2024-01-08T09:00:00Z"
+            ])
+        );
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/recurrence?rule=FREQ=HOURLY")
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        Ok(())
+    }
+}
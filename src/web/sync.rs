@@ -0,0 +1,131 @@
+use crate::database::Database;
+use crate::model::sync::{Mutation, SyncMac};
+
+use super::task::{with_client, with_db};
+use super::{api_logger, json_response};
+
+use serde::Deserialize;
+use std::sync::Arc;
+use warp::reply::Json;
+use warp::Filter;
+
+pub fn sync_rest_filters(
+    base_path: &'static str,
+    database: Arc<Database>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let sync_path = warp::path(base_path)
+        .and(warp::path("sync"))
+        .and(warp::path::end()); // /api/sync
+    let common = with_db(database);
+
+    // Pull the changes since a sync token (GET /api/sync?token=12)
+    let pull = sync_path
+        .and(warp::get())
+        .and(warp::query::<PullQuery>())
+        .and(common.clone())
+        .and_then(sync_pull);
+
+    // Push offline mutations and pull the changes (POST /api/sync)
+    let push = sync_path
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(common.clone())
+        .and(with_client())
+        .and_then(sync_push);
+
+    pull.or(push).with(api_logger())
+}
+
+#[derive(Debug, Deserialize)]
+struct PullQuery {
+    token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PushBody {
+    token: Option<String>,
+    #[serde(default)]
+    mutations: Vec<Mutation>,
+}
+
+/// Return the changes since the token, or every task without one.
+async fn sync_pull(query: PullQuery, database: Arc<Database>) -> Result<Json, warp::Rejection> {
+    let response = SyncMac::sync(&database, query.token.as_deref(), Vec::new(), None).await?;
+    json_response(response)
+}
+
+/// Apply the mutations, then return their outcome and the changes since the token.
+async fn sync_push(
+    body: PushBody,
+    database: Arc<Database>,
+    client: Option<String>,
+) -> Result<Json, warp::Rejection> {
+    let response = SyncMac::sync(
+        &database,
+        body.token.as_deref(),
+        body.mutations,
+        client.as_deref(),
+    )
+    .await?;
+    json_response(response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use serde_json::{json, Value};
+    use std::io::Result;
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_sync() -> Result<()> {
+        // # Setup
+        let database = Arc::new(create_and_connect(DbAddress::Memory).await.unwrap());
+        let filters =
+            sync_rest_filters("api", database.clone()).recover(super::super::handle_rejection);
+
+        // # Initial pull
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/sync")
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["data"]["tasks"], json!([]));
+        let token = body["data"]["token"].as_str().unwrap().to_string();
+
+        // # Push
+        let mutations = json!({
+            "token": token,
+            "mutations": [
+                {"op": "create", "at": "2024-05-01T10:00:00Z", "fields": {"name": "Offline"}},
+                {"op": "delete", "at": "2024-05-01T10:00:00Z", "id": 42},
+            ],
+        });
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/sync")
+            .header("x-client-id", "laptop")
+            .json(&mutations)
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["data"]["results"][0]["status"], "applied");
+        assert_eq!(body["data"]["results"][1]["status"], "rejected");
+        assert_eq!(body["data"]["results"][1]["id"], 42);
+        assert_eq!(body["data"]["tasks"][0]["name"], "Offline");
+        assert_ne!(body["data"]["token"], token);
+
+        // # Bad token
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/sync?token=later")
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        Ok(())
+    }
+}
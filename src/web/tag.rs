@@ -0,0 +1,241 @@
+use crate::database::Database;
+use crate::model::tag::{TagMac, TagPatch};
+
+use super::task::{with_client, with_db};
+use super::{api_logger, json_response};
+
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use warp::reply::Json;
+use warp::Filter;
+
+pub fn tag_rest_filters(
+    base_path: &'static str,
+    database: Arc<Database>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let tag_path = warp::path(base_path).and(warp::path("tags")); // /api/tags
+    let task_tag_path = warp::path(base_path) // /api/tasks/:id/tags/:tag_id
+        .and(warp::path("tasks"))
+        .and(warp::path::param::<i64>())
+        .and(warp::path("tags"))
+        .and(warp::path::param::<i64>())
+        .and(warp::path::end());
+    let common = with_db(database.clone());
+
+    // List tags (GET /api/tags)
+    let list = tag_path
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(common.clone())
+        .and_then(tag_list);
+
+    // Create tag (POST /api/tags with body TagPatch)
+    let insert = tag_path
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(common.clone())
+        .and(warp::body::json())
+        .and_then(tag_insert);
+
+    // Rename tag (PATCH /api/tags/:id with body TagPatch)
+    let rename = tag_path
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::patch())
+        .and(common.clone())
+        .and(warp::body::json())
+        .and(with_client())
+        .and_then(tag_rename);
+
+    // Merge tag into another (POST /api/tags/:id/merge with body {"into": id})
+    let merge = tag_path
+        .and(warp::path::param())
+        .and(warp::path("merge"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(common.clone())
+        .and(warp::body::json())
+        .and(with_client())
+        .and_then(tag_merge);
+
+    // Delete tag (DELETE /api/tags/:id)
+    let delete = tag_path
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(common.clone())
+        .and(with_client())
+        .and_then(tag_delete);
+
+    // Attach tag to task (PUT /api/tasks/:id/tags/:tag_id)
+    let attach = task_tag_path
+        .and(warp::put())
+        .and(common.clone())
+        .and(with_client())
+        .and_then(tag_attach);
+
+    // Detach tag from task (DELETE /api/tasks/:id/tags/:tag_id)
+    let detach = task_tag_path
+        .and(warp::delete())
+        .and(common.clone())
+        .and(with_client())
+        .and_then(tag_detach);
+
+    list.or(insert)
+        .or(rename)
+        .or(merge)
+        .or(delete)
+        .or(attach)
+        .or(detach)
+        .with(api_logger())
+}
+
+/// List all tags.
+async fn tag_list(database: Arc<Database>) -> Result<Json, warp::Rejection> {
+    let tags = TagMac::list(&database).await?;
+    json_response(tags)
+}
+
+/// Create a new tag.
+async fn tag_insert(database: Arc<Database>, data: TagPatch) -> Result<Json, warp::Rejection> {
+    let tag = TagMac::insert(&database, data).await?;
+    json_response(tag)
+}
+
+/// Rename a tag.
+async fn tag_rename(
+    id: i64,
+    database: Arc<Database>,
+    data: TagPatch,
+    client: Option<String>,
+) -> Result<Json, warp::Rejection> {
+    let tag = TagMac::rename(&database, id, data, client.as_deref()).await?;
+    json_response(tag)
+}
+
+/// Body of a merge request.
+#[derive(Debug, Deserialize)]
+struct MergeBody {
+    into: i64,
+}
+
+/// Merge a tag into another one.
+async fn tag_merge(
+    id: i64,
+    database: Arc<Database>,
+    data: MergeBody,
+    client: Option<String>,
+) -> Result<Json, warp::Rejection> {
+    let tag = TagMac::merge(&database, id, data.into, client.as_deref()).await?;
+    json_response(tag)
+}
+
+/// Delete a tag.
+async fn tag_delete(
+    id: i64,
+    database: Arc<Database>,
+    client: Option<String>,
+) -> Result<Json, warp::Rejection> {
+    TagMac::delete(&database, id, client.as_deref()).await?;
+    json_response(json!({}))
+}
+
+/// Attach a tag to a task.
+async fn tag_attach(
+    task_id: i64,
+    tag_id: i64,
+    database: Arc<Database>,
+    client: Option<String>,
+) -> Result<Json, warp::Rejection> {
+    let task = TagMac::attach(&database, task_id, tag_id, client.as_deref()).await?;
+    json_response(task)
+}
+
+/// Detach a tag from a task.
+async fn tag_detach(
+    task_id: i64,
+    tag_id: i64,
+    database: Arc<Database>,
+    client: Option<String>,
+) -> Result<Json, warp::Rejection> {
+    let task = TagMac::detach(&database, task_id, tag_id, client.as_deref()).await?;
+    json_response(task)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use crate::model::task::{TaskMac, TaskPatch};
+    use std::io::Result;
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_tag_lifecycle() -> Result<()> {
+        // # Setup
+        let database = Arc::new(create_and_connect(DbAddress::Memory).await.unwrap());
+        TaskMac::insert(
+            &database,
+            TaskPatch {
+                name: Some("Hello world".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let filters = tag_rest_filters("api", database.clone())
+            .or(crate::web::task::task_rest_filters("api", database.clone()))
+            .recover(super::super::handle_rejection);
+
+        // # Create
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/tags")
+            .json(&json!({"name": "backend"}))
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/tags")
+            .json(&json!({"name": "Backend"}))
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        // # Attach
+        let response = warp::test::request()
+            .method("PUT")
+            .path("/api/tasks/1/tags/1")
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // # Check
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/tasks?tags=backend")
+            .reply(&filters)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["data"][0]["tags"], json!([{"id": 1, "name": "backend"}]));
+
+        // # Detach
+        let response = warp::test::request()
+            .method("DELETE")
+            .path("/api/tasks/1/tags/1")
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/tasks/1")
+            .reply(&filters)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["data"]["tags"], json!([]));
+        Ok(())
+    }
+}
@@ -1,13 +1,21 @@
 use crate::database::Database;
-use crate::model::task::{TaskMac, TaskPatch};
+use crate::model::hierarchy::ChildPolicy;
+use crate::model::listing::{TaskQuery, DEFAULT_LIMIT};
+use crate::model::tag::TagMatch;
+use crate::model::task::{DueWindow, Task, TaskMac, TaskPatch, TaskStatus};
 
-use super::json_response;
+use super::{api_logger, json_response, page_response, WebError};
 
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
 use serde_json::json;
 use std::convert::Infallible;
 use std::sync::Arc;
-use warp::reply::Json;
-use warp::Filter;
+use warp::http::header::ETAG;
+use warp::http::StatusCode;
+use warp::reply::{with_header, Json, Response};
+use warp::{Filter, Reply};
 
 
 
@@ -16,62 +24,85 @@ pub fn task_rest_filters(
     database: Arc<Database>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
 
-    let logger = warp::log::custom(|info| {
-        let body = json!(
-            {
-                "status": info.status().as_u16(),
-                "version": format!("{:?}", info.version()),
-                "method": info.method().as_str(),
-                "path": info.path(),
-                "elapsed": info.elapsed().as_secs_f64(),
-            }
-        );
-        if let Ok(json_str) = serde_json::to_string_pretty(&body) {
-            tracing::info!("{}", json_str);
-        }
-    });
-
-
     let task_path = warp::path(base_path).and(warp::path("tasks")); // /api/tasks
     let common = with_db(database.clone());
 
-    // List tasks (GET /api/tasks/)
+    // List tasks (GET /api/tasks?status=Open&name=deploy&tags=backend,api&match=any&sort=priority,due_at&limit=50&cursor=...)
     let list = task_path
         .and(warp::get())
         .and(warp::path::end())
         .and(common.clone())
+        .and(warp::query::<ListQuery>())
         .and_then(task_list);
 
-    // Get task (GET /api/tasks/:id)
+    // List tasks due in a window (GET /api/tasks/due?window=today&tz=Europe/Copenhagen)
+    let due = task_path
+        .and(warp::path("due"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(common.clone())
+        .and(warp::query::<DueQuery>())
+        .and_then(task_list_due);
+
+    // Rank open tasks (GET /api/tasks/next?limit=5)
+    let next = task_path
+        .and(warp::path("next"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(common.clone())
+        .and(warp::query::<NextQuery>())
+        .and_then(task_list_next);
+
+    // Search task names (GET /api/tasks/search?q=back*&limit=20)
+    let search = task_path
+        .and(warp::path("search"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(common.clone())
+        .and(warp::query::<SearchQuery>())
+        .and_then(task_search);
+
+    // Get task (GET /api/tasks/:id?include=children, honoring If-None-Match)
     let get = task_path
         .and(warp::get())
         .and(common.clone())
         .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::query::<GetQuery>())
+        .and(warp::header::optional::<String>("if-none-match"))
         .and_then(task_get);
 
     // Create task (POST /api/tasks with body TaskPatch)
     let insert = task_path
+        .and(warp::path::end())
         .and(warp::post())
         .and(common.clone())
+        .and(with_client())
         .and(warp::body::json())
         .and_then(task_insert);
 
-    // Update task (PATCH /api/tasks with body TaskPatch and id)
+    // Update task (PATCH /api/tasks/:id with body TaskPatch, honoring If-Match)
     let update = task_path
         .and(warp::patch())
         .and(common.clone())
         .and(warp::path::param())
+        .and(warp::path::end())
+        .and(with_client())
+        .and(warp::header::optional::<String>("if-match"))
         .and(warp::body::json())
         .and_then(task_update);
 
-    // Delete task (DELETE /api/tasks/:id)
+    // Delete task (DELETE /api/tasks/:id?children=cascade|orphan)
     let delete = task_path
         .and(warp::delete())
         .and(common.clone())
         .and(warp::path::param())
+        .and(warp::path::end())
+        .and(with_client())
+        .and(warp::query::<DeleteQuery>())
         .and_then(task_delete);
 
-    list.or(get).or(insert).or(update).or(delete).with(logger)
+    list.or(due).or(next).or(search).or(get).or(insert).or(update).or(delete).with(api_logger())
 }
 
 
@@ -80,38 +111,234 @@ pub fn task_rest_filters(
 // }
 
 
-/// List all tasks.
-async fn task_list(database: Arc<Database>) -> Result<Json, warp::Rejection> {
-    let tasks = TaskMac::list(&database).await?;
+/// Query parameters for listing tasks.
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct ListQuery {
+    /// Comma-separated sort keys, e.g. `priority,due_at:asc`.
+    sort: Option<String>,
+    status: Option<TaskStatus>,
+    created_after: Option<DateTime<Utc>>,
+    created_before: Option<DateTime<Utc>>,
+    /// Substring of the name.
+    name: Option<String>,
+    /// Comma-separated tag names.
+    tags: Option<String>,
+    #[serde(default, rename = "match")]
+    tag_match: TagMatch,
+    /// Filter expression, e.g. `status:open and tag:backend`.
+    filter: Option<String>,
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
+impl ListQuery {
+    /// The model query for these parameters.
+    pub(super) fn into_task_query(self) -> Result<TaskQuery, crate::Error> {
+        let tags: Vec<String> = self
+            .tags
+            .iter()
+            .flat_map(|tags| tags.split(','))
+            .filter(|tag| !tag.trim().is_empty())
+            .map(str::to_string)
+            .collect();
+        Ok(TaskQuery {
+            status: self.status,
+            created_after: self.created_after,
+            created_before: self.created_before,
+            name_contains: self.name.filter(|name| !name.is_empty()),
+            tags,
+            tag_match: self.tag_match,
+            filter: self
+                .filter
+                .as_deref()
+                .filter(|filter| !filter.trim().is_empty())
+                .map(str::parse)
+                .transpose()?,
+            sort: self.sort.as_deref().unwrap_or_default().parse()?,
+            cursor: self.cursor,
+            limit: self.limit.unwrap_or(DEFAULT_LIMIT),
+        })
+    }
+}
+
+/// List one page of tasks matching the query parameters.
+async fn task_list(database: Arc<Database>, query: ListQuery) -> Result<Json, warp::Rejection> {
+    let page = TaskMac::list_page(&database, &query.into_task_query()?).await?;
+    page_response(page)
+}
+
+/// Query parameters for ranking tasks.
+#[derive(Debug, Deserialize)]
+struct NextQuery {
+    #[serde(default = "NextQuery::default_limit")]
+    limit: usize,
+}
+
+impl NextQuery {
+    fn default_limit() -> usize {
+        5
+    }
+}
+
+/// List the most important open tasks, best first.
+async fn task_list_next(database: Arc<Database>, query: NextQuery) -> Result<Json, warp::Rejection> {
+    let tasks = TaskMac::list_next(&database, Utc::now(), query.limit).await?;
+    json_response(tasks)
+}
+
+/// Query parameters for searching tasks.
+#[derive(Debug, Deserialize)]
+struct SearchQuery {
+    q: String,
+    #[serde(default = "SearchQuery::default_limit")]
+    limit: usize,
+}
+
+impl SearchQuery {
+    fn default_limit() -> usize {
+        20
+    }
+}
+
+/// Search task names, best matches first.
+async fn task_search(database: Arc<Database>, query: SearchQuery) -> Result<Json, warp::Rejection> {
+    let hits = TaskMac::search(&database, &query.q, query.limit).await?;
+    json_response(hits)
+}
+
+/// Query parameters for listing due tasks.
+#[derive(Debug, Deserialize)]
+struct DueQuery {
+    window: DueWindow,
+    /// IANA time zone of the user. Defaults to UTC.
+    tz: Option<String>,
+}
+
+/// List open tasks due in a window, relative to the user's time zone.
+async fn task_list_due(database: Arc<Database>, query: DueQuery) -> Result<Json, warp::Rejection> {
+    let tz = match &query.tz {
+        Some(name) => name.parse::<Tz>().map_err(|_| {
+            crate::Error::Validation(format!("Unknown time zone {:?}.", name))
+        })?,
+        None => Tz::UTC,
+    };
+    let tasks = TaskMac::list_due(&database, query.window, Utc::now(), tz).await?;
     json_response(tasks)
 }
 
+/// Related data to include when getting a task.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Include {
+    /// Nest all subtasks, with progress counts.
+    Children,
+}
+
+/// Query parameters for getting a task.
+#[derive(Debug, Deserialize)]
+struct GetQuery {
+    include: Option<Include>,
+}
+
 /// Get a task by id.
-async fn task_get(database: Arc<Database>, id: i64) -> Result<Json, warp::Rejection> {
-    let task = TaskMac::get(&database, id).await?;
-    json_response(task)
+async fn task_get(
+    database: Arc<Database>,
+    id: i64,
+    query: GetQuery,
+    if_none_match: Option<String>,
+) -> Result<Response, warp::Rejection> {
+    match query.include {
+        Some(Include::Children) => {
+            Ok(json_response(TaskMac::subtree(&database, id).await?)?.into_response())
+        }
+        None => {
+            let task = TaskMac::get(&database, id).await?;
+            let etag = etag(&task);
+            if if_none_match.is_some_and(|header| etag_matches(&header, &etag)) {
+                return Ok(with_header(StatusCode::NOT_MODIFIED, ETAG, etag).into_response());
+            }
+            Ok(with_header(json_response(task)?, ETAG, etag).into_response())
+        }
+    }
 }
 
 /// Insert a new task.
-async fn task_insert(database: Arc<Database>, data: TaskPatch) -> Result<Json, warp::Rejection> {
-    let task = TaskMac::insert(&database, data).await?;
+async fn task_insert(
+    database: Arc<Database>,
+    client: Option<String>,
+    data: TaskPatch,
+) -> Result<Json, warp::Rejection> {
+    let task = TaskMac::insert_by(&database, data, client.as_deref()).await?;
     json_response(task)
 }
 
+/// Query parameters for deleting a task.
+#[derive(Debug, Deserialize)]
+struct DeleteQuery {
+    #[serde(default)]
+    children: ChildPolicy,
+}
+
 /// Delete a task by id.
-async fn task_delete(database: Arc<Database>, id: i64) -> Result<Json, warp::Rejection> {
-    TaskMac::delete(&database, id).await?;
+async fn task_delete(
+    database: Arc<Database>,
+    id: i64,
+    client: Option<String>,
+    query: DeleteQuery,
+) -> Result<Json, warp::Rejection> {
+    TaskMac::delete_with(&database, id, query.children, client.as_deref()).await?;
     json_response(json!({}))
 }
 
-/// Update a task by id.
+/// Update a task by id. The response lists the tasks that closing it unblocked.
 async fn task_update(
     database: Arc<Database>,
     id: i64,
-    data: TaskPatch,
-) -> Result<Json, warp::Rejection> {
-    let task = TaskMac::update(&database, id, data).await?;
-    json_response(task)
+    client: Option<String>,
+    if_match: Option<String>,
+    mut data: TaskPatch,
+) -> Result<Response, warp::Rejection> {
+    let precondition = if_match.filter(|header| header.trim() != "*");
+    if let Some(header) = &precondition {
+        let current = TaskMac::get(&database, id).await?;
+        // Versions start at 1, so a header without the current tag never matches.
+        data.version = Some(if if_match_lists(header, &etag(&current)) {
+            current.version
+        } else {
+            0
+        });
+    }
+    match TaskMac::update_by(&database, id, data, client.as_deref()).await {
+        Ok(update) => {
+            let etag = etag(&update.task);
+            Ok(with_header(json_response(update)?, ETAG, etag).into_response())
+        }
+        Err(e @ crate::Error::StaleVersion(_)) if precondition.is_some() => {
+            let mut error = WebError::from(e);
+            error.status = StatusCode::PRECONDITION_FAILED;
+            error.typ = "preconditionFailed";
+            Err(warp::reject::custom(error))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Entity tag of the current version of a task.
+fn etag(task: &Task) -> String {
+    format!("\"{}\"", task.version)
+}
+
+/// Whether an `If-Match` header lists the given entity tag. Weak tags never match.
+fn if_match_lists(header: &str, etag: &str) -> bool {
+    header.split(',').map(str::trim).any(|tag| tag == etag)
+}
+
+/// Whether an `If-None-Match` header lists the given entity tag.
+fn etag_matches(header: &str, etag: &str) -> bool {
+    header
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
 }
 
 /// Extract the database from the request.
@@ -121,10 +348,15 @@ pub fn with_db(
     warp::any().map(move || database.clone())
 }
 
+/// Extract the acting client from the `X-Client-Id` header, recorded in the task history.
+pub fn with_client() -> impl Filter<Extract = (Option<String>,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("x-client-id")
+}
+
 #[cfg(test)]
 mod test {
     use crate::database::{create_and_connect, DbAddress};
-    use crate::model::task::{TaskMac, TaskPatch, TaskStatus};
+    use crate::model::task::{TaskMac, TaskPatch, TaskPriority, TaskStatus};
     use std::io::Result;
 
     use super::*;
@@ -146,6 +378,177 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_task_list_pages() -> Result<()> {
+        // # Setup
+        let database = Arc::new(create_and_connect(DbAddress::Memory).await.unwrap());
+        for name in ["Write docs", "Deploy", "Write tests", "Write changelog"] {
+            TaskMac::insert(
+                &database,
+                TaskPatch {
+                    name: Some(name.to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        }
+        let filters = task_rest_filters("api", database.clone()).recover(super::super::handle_rejection);
+
+        // # Action
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/tasks?name=write&status=Open&sort=name:desc&limit=2")
+            .reply(&filters)
+            .await;
+
+        // # Check
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["total"], 3);
+        assert_eq!(body["data"][0]["name"], "Write tests");
+        assert_eq!(body["data"][1]["name"], "Write docs");
+        let cursor = body["next_cursor"].as_str().unwrap();
+
+        let response = warp::test::request()
+            .method("GET")
+            .path(&format!("/api/tasks?name=write&status=Open&sort=name:desc&limit=2&cursor={}", cursor))
+            .reply(&filters)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["data"].as_array().unwrap().len(), 1);
+        assert_eq!(body["data"][0]["name"], "Write changelog");
+        assert_eq!(body["next_cursor"], serde_json::Value::Null);
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/tasks?sort=color")
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_task_next() -> Result<()> {
+        // # Setup
+        let database = Arc::new(create_and_connect(DbAddress::Memory).await.unwrap());
+        for (name, priority) in [("Later", TaskPriority::Low), ("Now", TaskPriority::Urgent)] {
+            TaskMac::insert(
+                &database,
+                TaskPatch {
+                    name: Some(name.to_string()),
+                    priority: Some(priority),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        }
+        let filters = task_rest_filters("api", database.clone());
+
+        // # Action
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/tasks/next?limit=1")
+            .reply(&filters)
+            .await;
+
+        // # Check
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["data"].as_array().unwrap().len(), 1);
+        assert_eq!(body["data"][0]["name"], "Now");
+        assert_eq!(body["data"][0]["score"], 50);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_task_search() -> Result<()> {
+        // # Setup
+        let database = Arc::new(create_and_connect(DbAddress::Memory).await.unwrap());
+        for name in ["Deploy backend", "Write docs"] {
+            TaskMac::insert(
+                &database,
+                TaskPatch {
+                    name: Some(name.to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        }
+        let filters = task_rest_filters("api", database.clone()).recover(super::super::handle_rejection);
+
+        // # Action
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/tasks/search?q=back*")
+            .reply(&filters)
+            .await;
+
+        // # Check
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["data"].as_array().unwrap().len(), 1);
+        assert_eq!(body["data"][0]["id"], 1);
+        assert_eq!(body["data"][0]["snippet"], "Deploy <mark>backend</mark>");
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/tasks/search?q=%22%22")
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_task_tree() -> Result<()> {
+        // # Setup
+        let database = Arc::new(create_and_connect(DbAddress::Memory).await.unwrap());
+        for (name, parent_id) in [("Root", None), ("Child", Some(1))] {
+            TaskMac::insert(
+                &database,
+                TaskPatch {
+                    name: Some(name.to_string()),
+                    parent_id: Some(parent_id),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        }
+        let filters =
+            task_rest_filters("api", database.clone()).recover(super::super::handle_rejection);
+
+        // # Check tree
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/tasks/1?include=children")
+            .reply(&filters)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["data"]["progress"], json!({"closed": 0, "total": 1}));
+        assert_eq!(body["data"]["children"][0]["name"], "Child");
+
+        // # Check delete
+        let response = warp::test::request()
+            .method("DELETE")
+            .path("/api/tasks/1")
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let response = warp::test::request()
+            .method("DELETE")
+            .path("/api/tasks/1?children=cascade")
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(TaskMac::list(&database).await.unwrap().is_empty());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_task_get() -> Result<()> {
         // # Setup
@@ -155,6 +558,7 @@ mod test {
             TaskPatch {
                 name: Some("Hello world".to_string()),
                 status: Some(TaskStatus::Open),
+                ..Default::default()
             },
         )
         .await
@@ -174,4 +578,192 @@ mod test {
 
         Ok(())
     }
+
+    /// Send a request through the task filters with rejection handling.
+    async fn error_of(
+        database: Arc<Database>,
+        request: warp::test::RequestBuilder,
+    ) -> (StatusCode, serde_json::Value) {
+        let filters =
+            task_rest_filters("api", database).recover(super::super::handle_rejection);
+        let response = request.reply(&filters).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        (response.status(), body["error"]["type"].clone())
+    }
+
+    #[tokio::test]
+    async fn test_task_etags() -> Result<()> {
+        // # Setup
+        let database = Arc::new(create_and_connect(DbAddress::Memory).await.unwrap());
+        TaskMac::insert(
+            &database,
+            TaskPatch {
+                name: Some("Hello world".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let filters =
+            task_rest_filters("api", database.clone()).recover(super::super::handle_rejection);
+
+        // # Conditional GET
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/tasks/1")
+            .reply(&filters)
+            .await;
+        assert_eq!(response.headers()["etag"], "\"1\"");
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/tasks/1")
+            .header("if-none-match", "\"1\"")
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert!(response.body().is_empty());
+
+        // # Conditional PATCH
+        let response = warp::test::request()
+            .method("PATCH")
+            .path("/api/tasks/1")
+            .header("if-match", "\"1\"")
+            .json(&json!({"name": "First"}))
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["etag"], "\"2\"");
+
+        let response = warp::test::request()
+            .method("PATCH")
+            .path("/api/tasks/1")
+            .header("if-match", "\"1\"")
+            .json(&json!({"name": "Second"}))
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["error"]["type"], "preconditionFailed");
+        assert_eq!(body["data"]["name"], "First");
+        assert_eq!(body["data"]["version"], 2);
+
+        // Any listed strong tag matches, weak ones never do.
+        let response = warp::test::request()
+            .method("PATCH")
+            .path("/api/tasks/1")
+            .header("if-match", "W/\"2\"")
+            .json(&json!({"name": "Second"}))
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let response = warp::test::request()
+            .method("PATCH")
+            .path("/api/tasks/1")
+            .header("if-match", "\"7\", \"2\"")
+            .json(&json!({"name": "Second"}))
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["etag"], "\"3\"");
+
+        // A stale version in the body is a conflict.
+        let response = warp::test::request()
+            .method("PATCH")
+            .path("/api/tasks/1")
+            .json(&json!({"name": "Second", "version": 1}))
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["error"]["type"], "staleVersion");
+        assert_eq!(body["data"]["version"], 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_error_statuses() -> Result<()> {
+        let database = Arc::new(create_and_connect(DbAddress::Memory).await.unwrap());
+
+        // Missing task
+        let (status, typ) = error_of(
+            database.clone(),
+            warp::test::request().method("GET").path("/api/tasks/42"),
+        )
+        .await;
+        assert_eq!((status, typ.as_str().unwrap()), (StatusCode::NOT_FOUND, "notFound"));
+
+        // Unknown route
+        let (status, typ) = error_of(
+            database.clone(),
+            warp::test::request().method("GET").path("/api/nothing"),
+        )
+        .await;
+        assert_eq!((status, typ.as_str().unwrap()), (StatusCode::NOT_FOUND, "routeNotFound"));
+
+        // Malformed body
+        let (status, typ) = error_of(
+            database.clone(),
+            warp::test::request().method("POST").path("/api/tasks").body("{nope"),
+        )
+        .await;
+        assert_eq!(
+            (status, typ.as_str().unwrap()),
+            (StatusCode::UNPROCESSABLE_ENTITY, "invalidBody")
+        );
+
+        // Invalid content
+        let (status, typ) = error_of(
+            database.clone(),
+            warp::test::request().method("POST").path("/api/tasks").json(&json!({"name": ""})),
+        )
+        .await;
+        assert_eq!(
+            (status, typ.as_str().unwrap()),
+            (StatusCode::UNPROCESSABLE_ENTITY, "validation")
+        );
+
+        // Filter nested deeper than the parser allows
+        let path = format!("/api/tasks?filter={}", "%28".repeat(10_000));
+        let (status, typ) = error_of(
+            database.clone(),
+            warp::test::request().method("GET").path(&path),
+        )
+        .await;
+        assert_eq!(
+            (status, typ.as_str().unwrap()),
+            (StatusCode::UNPROCESSABLE_ENTITY, "filter")
+        );
+
+        // Wrong method
+        let (status, typ) = error_of(
+            database.clone(),
+            warp::test::request().method("PUT").path("/api/tasks/1"),
+        )
+        .await;
+        assert_eq!(
+            (status, typ.as_str().unwrap()),
+            (StatusCode::METHOD_NOT_ALLOWED, "methodNotAllowed")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_internal_error_hides_details() -> Result<()> {
+        let database = Arc::new(create_and_connect(DbAddress::Memory).await.unwrap());
+        database.close().await;
+
+        let filters =
+            task_rest_filters("api", database.clone()).recover(super::super::handle_rejection);
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/tasks")
+            .reply(&filters)
+            .await;
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["error"]["type"], "internal");
+        assert_eq!(body["error"]["message"], "Internal server error.");
+        Ok(())
+    }
 }
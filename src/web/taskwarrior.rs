@@ -0,0 +1,110 @@
+use crate::database::Database;
+use crate::model::taskwarrior::{parse_export, TaskwarriorMac};
+
+use super::task::{with_client, with_db};
+use super::{api_logger, import_body, json_response};
+
+use std::sync::Arc;
+use warp::hyper::body::Bytes;
+use warp::reply::Json;
+use warp::Filter;
+
+pub fn taskwarrior_rest_filters(
+    base_path: &'static str,
+    database: Arc<Database>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let taskwarrior_path = warp::path(base_path)
+        .and(warp::path("taskwarrior"))
+        .and(warp::path::end()); // /api/taskwarrior
+    let common = with_db(database);
+
+    // All tasks as a Taskwarrior export (GET /api/taskwarrior)
+    let export = taskwarrior_path
+        .and(warp::get())
+        .and(common.clone())
+        .and_then(taskwarrior_export);
+
+    // Import a Taskwarrior export (POST /api/taskwarrior)
+    let import = taskwarrior_path
+        .and(warp::post())
+        .and(import_body())
+        .and(common.clone())
+        .and(with_client())
+        .and_then(taskwarrior_import);
+
+    export.or(import).with(api_logger())
+}
+
+/// All tasks as a bare JSON array, ready for `task import`.
+async fn taskwarrior_export(database: Arc<Database>) -> Result<Json, warp::Rejection> {
+    let tasks = TaskwarriorMac::export(&database).await?;
+    Ok(warp::reply::json(&tasks))
+}
+
+/// Import the output of `task export`, and report what was done.
+async fn taskwarrior_import(
+    body: Bytes,
+    database: Arc<Database>,
+    client: Option<String>,
+) -> Result<Json, warp::Rejection> {
+    let tasks = parse_export(&body)?;
+    let report = TaskwarriorMac::import(&database, tasks, client.as_deref()).await?;
+    json_response(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use serde_json::Value;
+    use std::io::Result;
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_import_export() -> Result<()> {
+        // # Setup
+        let database = Arc::new(create_and_connect(DbAddress::Memory).await.unwrap());
+        let filters = taskwarrior_rest_filters("api", database.clone())
+            .recover(super::super::handle_rejection);
+        let export = r#"[
+            {"uuid":"5f1c0b6e-2d7a-4c51-9a0e-3b8f7d2e6a10","description":"Call the bank","status":"pending","entry":"20240501T100000Z","tags":["phone"]},
+            {"uuid":"5f1c0b6e-2d7a-4c51-9a0e-3b8f7d2e6a11","description":"Weekly review","status":"recurring","entry":"20240501T100000Z"}
+        ]"#;
+
+        // # Import
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/taskwarrior")
+            .body(export)
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["data"]["mapped"], 1);
+        assert_eq!(body["data"]["skipped"], 1);
+        assert_eq!(body["data"]["conflicts"], 0);
+        assert_eq!(body["data"]["issues"][0]["kind"], "skipped");
+
+        // # Export
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/taskwarrior")
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body[0]["uuid"], "5f1c0b6e-2d7a-4c51-9a0e-3b8f7d2e6a10");
+        assert_eq!(body[0]["status"], "pending");
+        assert_eq!(body[0]["tags"], serde_json::json!(["phone"]));
+
+        // # Invalid file
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/taskwarrior")
+            .body("not json")
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+use crate::database::Database;
+use crate::model::plaintext::{to_markdown, to_todo_txt};
+use crate::model::transfer::{
+    csv_header, csv_rows, parse_records, ImportMode, TransferFormat, TransferMac,
+};
+
+use super::api_logger;
+use super::{import_body, json_response};
+use super::task::{with_client, with_db};
+
+use futures_util::stream::{self, Stream};
+use serde::Deserialize;
+use std::sync::Arc;
+use warp::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use warp::http::Response;
+use warp::hyper::body::Bytes;
+use warp::hyper::Body;
+use warp::reply::Json;
+use warp::Filter;
+
+/// Tasks read from the database at a time.
+const BATCH_SIZE: usize = 100;
+
+pub fn transfer_rest_filters(
+    base_path: &'static str,
+    database: Arc<Database>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let common = with_db(database);
+
+    // Export all tasks (GET /api/export?format=csv)
+    let export = warp::path(base_path)
+        .and(warp::path("export"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<ExportQuery>())
+        .and(common.clone())
+        .map(export);
+
+    // Import tasks (POST /api/import?format=csv&mode=create&dry_run=true)
+    let import = warp::path(base_path)
+        .and(warp::path("import"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::query::<ImportQuery>())
+        .and(import_body())
+        .and(common.clone())
+        .and(with_client())
+        .and_then(import);
+
+    export.or(import).with(api_logger())
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: TransferFormat,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportQuery {
+    #[serde(default)]
+    format: TransferFormat,
+    #[serde(default)]
+    mode: ImportMode,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// Stream all tasks in the requested format, as a download.
+fn export(query: ExportQuery, database: Arc<Database>) -> Response<Body> {
+    let (content_type, file_name) = match query.format {
+        TransferFormat::Json => ("application/json", "tasks.json"),
+        TransferFormat::Csv => ("text/csv", "tasks.csv"),
+        TransferFormat::Todotxt => ("text/plain; charset=utf-8", "todo.txt"),
+        TransferFormat::Markdown => ("text/markdown; charset=utf-8", "tasks.md"),
+    };
+    Response::builder()
+        .header(CONTENT_TYPE, content_type)
+        .header(
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", file_name),
+        )
+        .body(Body::wrap_stream(export_chunks(database, query.format)))
+        .expect("export response is valid")
+}
+
+/// Where an export stream stands.
+enum ExportState {
+    Start,
+    /// Tasks up to this id have been written.
+    After(i64),
+    Done,
+}
+
+/// Chunks of an export: the opening, one chunk per batch of tasks, and the closing.
+/// Markdown nests subtasks under their parent, so it is written in one chunk. A
+/// database error ends the stream early, so that the client sees a broken download
+/// rather than a short file.
+fn export_chunks(
+    database: Arc<Database>,
+    format: TransferFormat,
+) -> impl Stream<Item = Result<Bytes, crate::Error>> + Send {
+    stream::unfold(ExportState::Start, move |state| {
+        let database = database.clone();
+        async move {
+            let after = match state {
+                ExportState::Start if format == TransferFormat::Markdown => {
+                    let chunk = TransferMac::export_all(&database)
+                        .await
+                        .map(|records| Bytes::from(to_markdown(&records)));
+                    return Some((chunk, ExportState::Done));
+                }
+                ExportState::Start => {
+                    let opening = match format {
+                        TransferFormat::Json => b"[".to_vec(),
+                        TransferFormat::Csv => csv_header(),
+                        _ => Vec::new(),
+                    };
+                    return Some((Ok(Bytes::from(opening)), ExportState::After(0)));
+                }
+                ExportState::After(after) => after,
+                ExportState::Done => return None,
+            };
+            let records = match TransferMac::export_batch(&database, after, BATCH_SIZE).await {
+                Ok(records) => records,
+                Err(e) => return Some((Err(e), ExportState::Done)),
+            };
+            let Some(last) = records.last().and_then(|record| record.id) else {
+                let closing = match format {
+                    TransferFormat::Json => b"]".to_vec(),
+                    _ => Vec::new(),
+                };
+                return Some((Ok(Bytes::from(closing)), ExportState::Done));
+            };
+            let chunk = match format {
+                TransferFormat::Json => {
+                    let mut chunk = Vec::new();
+                    for record in &records {
+                        if after > 0 || !chunk.is_empty() {
+                            chunk.push(b',');
+                        }
+                        chunk.push(b'\n');
+                        serde_json::to_writer(&mut chunk, record).expect("records serialize");
+                    }
+                    chunk
+                }
+                TransferFormat::Csv => csv_rows(records),
+                _ => to_todo_txt(&records).into_bytes(),
+            };
+            Some((Ok(Bytes::from(chunk)), ExportState::After(last)))
+        }
+    })
+}
+
+/// Import tasks from the request body, and report what was done.
+async fn import(
+    query: ImportQuery,
+    body: Bytes,
+    database: Arc<Database>,
+    client: Option<String>,
+) -> Result<Json, warp::Rejection> {
+    let records = parse_records(query.format, &body)?;
+    let mode = if query.format.keeps_ids() {
+        query.mode
+    } else {
+        ImportMode::Create
+    };
+    let report =
+        TransferMac::import(&database, records, mode, query.dry_run, client.as_deref()).await?;
+    json_response(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use serde_json::Value;
+    use std::io::Result;
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_export_import() -> Result<()> {
+        // # Setup
+        let database = Arc::new(create_and_connect(DbAddress::Memory).await.unwrap());
+        let filters =
+            transfer_rest_filters("api", database.clone()).recover(super::super::handle_rejection);
+        let csv = "id,name,status,tags\n1,Write report,Open,\"work,q3\"\n2,,Open,\n";
+
+        // # Dry run
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/import?format=csv&dry_run=true")
+            .body(csv)
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["data"]["created"], 1);
+        assert_eq!(body["data"]["errors"][0]["row"], 2);
+        assert!(TransferMac::export_batch(&database, 0, 10)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // # Import
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/import?format=csv")
+            .header("x-client-id", "backup")
+            .body(csv)
+            .reply(&filters)
+            .await;
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["data"]["created"], 1);
+
+        // # Export
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/export")
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body[0]["name"], "Write report");
+        assert_eq!(body[0]["tags"], serde_json::json!(["q3", "work"]));
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/export?format=csv")
+            .reply(&filters)
+            .await;
+        let text = std::str::from_utf8(response.body()).unwrap();
+        assert!(text.starts_with("id,name,status,creation_time,priority,"));
+        assert!(text.contains("\n1,Write report,Open,"));
+        assert!(text.ends_with(",Normal,,,,,\"q3,work\"\n"));
+
+        // # Plain text
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/import?format=markdown")
+            .body("- [ ] Plan trip @home\n  - [x] Book hotel\n")
+            .reply(&filters)
+            .await;
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["data"]["created"], 2);
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/export?format=markdown")
+            .reply(&filters)
+            .await;
+        let text = std::str::from_utf8(response.body()).unwrap();
+        assert!(text.contains(" Plan trip @home\n  - [x] 20"), "{}", text);
+        assert!(text.ends_with(" Book hotel\n"));
+
+        // # Invalid file
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/import")
+            .body("{}")
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        // # Too large
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/import")
+            .body(vec![b' '; super::super::MAX_IMPORT_BYTES as usize + 1])
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        Ok(())
+    }
+}
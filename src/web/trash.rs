@@ -0,0 +1,136 @@
+use crate::database::Database;
+use crate::model::task::TaskMac;
+
+use super::task::{with_client, with_db};
+use super::{api_logger, json_response};
+
+use serde_json::json;
+use std::sync::Arc;
+use warp::reply::Json;
+use warp::Filter;
+
+pub fn trash_rest_filters(
+    base_path: &'static str,
+    database: Arc<Database>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let trash_path = warp::path(base_path).and(warp::path("trash")); // /api/trash
+    let common = with_db(database.clone());
+
+    // List deleted tasks (GET /api/trash)
+    let list = trash_path
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(common.clone())
+        .and_then(trash_list);
+
+    // Restore a deleted task (POST /api/trash/:id/restore)
+    let restore = trash_path
+        .and(warp::path::param())
+        .and(warp::path("restore"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(common.clone())
+        .and(with_client())
+        .and_then(trash_restore);
+
+    // Purge a deleted task for good (DELETE /api/trash/:id)
+    let purge = trash_path
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(common.clone())
+        .and(with_client())
+        .and_then(trash_purge);
+
+    list.or(restore).or(purge).with(api_logger())
+}
+
+/// List the tasks in the trash, most recently deleted first.
+async fn trash_list(database: Arc<Database>) -> Result<Json, warp::Rejection> {
+    let tasks = TaskMac::trash(&database).await?;
+    json_response(tasks)
+}
+
+/// Restore a task and the subtasks deleted with it.
+async fn trash_restore(
+    id: i64,
+    database: Arc<Database>,
+    client: Option<String>,
+) -> Result<Json, warp::Rejection> {
+    let task = TaskMac::restore(&database, id, client.as_deref()).await?;
+    json_response(task)
+}
+
+/// Permanently remove a task and the subtasks deleted with it.
+async fn trash_purge(
+    id: i64,
+    database: Arc<Database>,
+    client: Option<String>,
+) -> Result<Json, warp::Rejection> {
+    TaskMac::purge(&database, id, client.as_deref()).await?;
+    json_response(json!({}))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use crate::model::task::TaskPatch;
+    use std::io::Result;
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_trash() -> Result<()> {
+        // # Setup
+        let database = Arc::new(create_and_connect(DbAddress::Memory).await.unwrap());
+        let task = TaskMac::insert(
+            &database,
+            TaskPatch {
+                name: Some("Oops".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        TaskMac::delete(&database, task.id).await.unwrap();
+        let filters =
+            trash_rest_filters("api", database.clone()).recover(super::super::handle_rejection);
+
+        // # List
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/trash")
+            .reply(&filters)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["data"][0]["id"], task.id);
+        assert!(body["data"][0]["deleted_at"].is_string());
+
+        // # Restore
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/api/trash/{}/restore", task.id))
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["data"]["deleted_at"], serde_json::Value::Null);
+
+        // # Purge
+        let response = warp::test::request()
+            .method("DELETE")
+            .path(&format!("/api/trash/{}", task.id))
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        TaskMac::delete(&database, task.id).await.unwrap();
+        let response = warp::test::request()
+            .method("DELETE")
+            .path(&format!("/api/trash/{}", task.id))
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(TaskMac::trash(&database).await.unwrap().is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use crate::database::Database;
+use crate::model::listing::DEFAULT_LIMIT;
+use crate::model::view::{ViewMac, ViewPatch};
+
+use super::task::with_db;
+use super::{api_logger, json_response, page_response};
+
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use warp::reply::Json;
+use warp::Filter;
+
+pub fn view_rest_filters(
+    base_path: &'static str,
+    database: Arc<Database>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let view_path = warp::path(base_path).and(warp::path("views")); // /api/views
+    let common = with_db(database.clone());
+
+    // List views (GET /api/views)
+    let list = view_path
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(common.clone())
+        .and_then(view_list);
+
+    // Create view (POST /api/views with body ViewPatch)
+    let insert = view_path
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(common.clone())
+        .and(warp::body::json())
+        .and_then(view_insert);
+
+    // Get view (GET /api/views/:id)
+    let get = view_path
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(common.clone())
+        .and_then(view_get);
+
+    // Update view (PATCH /api/views/:id with body ViewPatch)
+    let update = view_path
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::patch())
+        .and(common.clone())
+        .and(warp::body::json())
+        .and_then(view_update);
+
+    // Delete view (DELETE /api/views/:id)
+    let delete = view_path
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(common.clone())
+        .and_then(view_delete);
+
+    // List the tasks of a view (GET /api/views/:id/tasks?limit=50&cursor=...)
+    let tasks = view_path
+        .and(warp::path::param())
+        .and(warp::path("tasks"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(common.clone())
+        .and(warp::query::<TasksQuery>())
+        .and_then(view_tasks);
+
+    list.or(insert)
+        .or(get)
+        .or(update)
+        .or(delete)
+        .or(tasks)
+        .with(api_logger())
+}
+
+/// List all views.
+async fn view_list(database: Arc<Database>) -> Result<Json, warp::Rejection> {
+    let views = ViewMac::list(&database).await?;
+    json_response(views)
+}
+
+/// Create a new view.
+async fn view_insert(database: Arc<Database>, data: ViewPatch) -> Result<Json, warp::Rejection> {
+    let view = ViewMac::insert(&database, data).await?;
+    json_response(view)
+}
+
+/// Get a view.
+async fn view_get(id: i64, database: Arc<Database>) -> Result<Json, warp::Rejection> {
+    let view = ViewMac::get(&database, id).await?;
+    json_response(view)
+}
+
+/// Update a view.
+async fn view_update(
+    id: i64,
+    database: Arc<Database>,
+    data: ViewPatch,
+) -> Result<Json, warp::Rejection> {
+    let view = ViewMac::update(&database, id, data).await?;
+    json_response(view)
+}
+
+/// Delete a view.
+async fn view_delete(id: i64, database: Arc<Database>) -> Result<Json, warp::Rejection> {
+    ViewMac::delete(&database, id).await?;
+    json_response(json!({}))
+}
+
+/// Query parameters for listing the tasks of a view.
+#[derive(Debug, Deserialize)]
+struct TasksQuery {
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
+/// List one page of the tasks matching a view.
+async fn view_tasks(
+    id: i64,
+    database: Arc<Database>,
+    query: TasksQuery,
+) -> Result<Json, warp::Rejection> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    let page = ViewMac::tasks(&database, id, query.cursor, limit).await?;
+    page_response(page)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use crate::model::task::{TaskMac, TaskPatch};
+    use std::io::Result;
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_views() -> Result<()> {
+        // # Setup
+        let database = Arc::new(create_and_connect(DbAddress::Memory).await.unwrap());
+        for name in ["Fix login", "WIP: new login"] {
+            TaskMac::insert(
+                &database,
+                TaskPatch {
+                    name: Some(name.to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        }
+        let filters =
+            view_rest_filters("api", database.clone()).recover(super::super::handle_rejection);
+
+        // # Create
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/views")
+            .json(&json!({"name": "Ready", "filter": "name~login and not name~\"wip\""}))
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        let id = body["data"]["id"].as_i64().unwrap();
+
+        // # Tasks
+        let response = warp::test::request()
+            .method("GET")
+            .path(&format!("/api/views/{}/tasks", id))
+            .reply(&filters)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["total"], 1);
+        assert_eq!(body["data"][0]["name"], "Fix login");
+
+        // # Parse error
+        let response = warp::test::request()
+            .method("PATCH")
+            .path(&format!("/api/views/{}", id))
+            .json(&json!({"filter": "status:open or"}))
+            .reply(&filters)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["error"]["type"], "filter");
+        assert_eq!(body["error"]["column"], 15);
+        Ok(())
+    }
+}
@@ -0,0 +1,377 @@
+//! WebSocket API. Clients send JSON-RPC-style requests over one connection, each with
+//! an `id` that its response repeats, and subscribe to change notifications:
+//!
+//! ```text
+//! -> {"id": 1, "method": "subscribe", "params": {"status": "Open"}}
+//! <- {"id": 1, "result": {"subscription": 1}}
+//! <- {"method": "event", "params": {"subscription": 1, "event": {...}}}
+//! ```
+
+use crate::database::Database;
+use crate::model::events::{EventFilter, EventMac, TaskEvent};
+use crate::model::hierarchy::ChildPolicy;
+use crate::model::task::{TaskMac, TaskPatch};
+
+use super::task::{with_client, with_db, ListQuery};
+use super::{api_logger, error_body, WebError};
+
+use futures_util::{SinkExt, StreamExt};
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use warp::http::StatusCode;
+use warp::ws::{Message, WebSocket, Ws};
+use warp::Filter;
+
+/// Events read from the database at a time.
+const BATCH_SIZE: usize = 100;
+
+pub fn ws_filters(
+    base_path: &'static str,
+    database: Arc<Database>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // Open a WebSocket (GET /api/ws with an upgrade request)
+    warp::path(base_path)
+        .and(warp::path("ws"))
+        .and(warp::path::end())
+        .and(warp::ws())
+        .and(with_db(database))
+        .and(with_client())
+        .map(|ws: Ws, database: Arc<Database>, client: Option<String>| {
+            ws.on_upgrade(move |socket| run_session(socket, Session::new(database, client)))
+        })
+        .with(api_logger())
+}
+
+/// A request from the client.
+#[derive(Debug, Deserialize)]
+struct Request {
+    /// Repeated in the response. Any JSON value.
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// Parameters that identify a task.
+#[derive(Debug, Deserialize)]
+struct IdParams {
+    id: i64,
+}
+
+/// Parameters for updating a task.
+#[derive(Debug, Deserialize)]
+struct UpdateParams {
+    id: i64,
+    patch: TaskPatch,
+}
+
+/// Parameters for deleting a task.
+#[derive(Debug, Deserialize)]
+struct DeleteParams {
+    id: i64,
+    #[serde(default)]
+    children: ChildPolicy,
+}
+
+/// Parameters for ending a subscription.
+#[derive(Debug, Deserialize)]
+struct UnsubscribeParams {
+    subscription: u64,
+}
+
+/// State of one connection.
+struct Session {
+    database: Arc<Database>,
+    /// Client from the `X-Client-Id` header of the upgrade request.
+    client: Option<String>,
+    subscriptions: BTreeMap<u64, EventFilter>,
+    next_subscription: u64,
+}
+
+impl Session {
+    fn new(database: Arc<Database>, client: Option<String>) -> Self {
+        Session {
+            database,
+            client,
+            subscriptions: BTreeMap::new(),
+            next_subscription: 1,
+        }
+    }
+
+    /// Handle one text message and build the response to it.
+    async fn handle(&mut self, text: &str) -> Value {
+        let request: Request = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(e) => {
+                let error = WebError::new(StatusCode::BAD_REQUEST, "invalidMessage", e.to_string());
+                return reply_error(Value::Null, &error);
+            }
+        };
+        match self.dispatch(&request.method, request.params).await {
+            Ok(result) => json!({"id": request.id, "result": result}),
+            Err(error) => reply_error(request.id, &error),
+        }
+    }
+
+    /// Run a method.
+    async fn dispatch(&mut self, method: &str, params: Value) -> Result<Value, WebError> {
+        let db = &self.database;
+        let client = self.client.as_deref();
+        let result = match method {
+            "tasks.list" => {
+                let query = parse::<ListQuery>(params)?.into_task_query()?;
+                let page = TaskMac::list_page(db, &query).await?;
+                json!({"data": page.items, "next_cursor": page.next_cursor, "total": page.total})
+            }
+            "tasks.get" => json!(TaskMac::get(db, parse::<IdParams>(params)?.id).await?),
+            "tasks.create" => json!(TaskMac::insert_by(db, parse(params)?, client).await?),
+            "tasks.update" => {
+                let params: UpdateParams = parse(params)?;
+                json!(TaskMac::update_by(db, params.id, params.patch, client).await?)
+            }
+            "tasks.delete" => {
+                let params: DeleteParams = parse(params)?;
+                TaskMac::delete_with(db, params.id, params.children, client).await?;
+                json!({})
+            }
+            "subscribe" => {
+                let subscription = self.next_subscription;
+                self.next_subscription += 1;
+                self.subscriptions.insert(subscription, parse(params)?);
+                json!({ "subscription": subscription })
+            }
+            "unsubscribe" => {
+                let subscription = parse::<UnsubscribeParams>(params)?.subscription;
+                if self.subscriptions.remove(&subscription).is_none() {
+                    return Err(crate::Error::NotFound(format!(
+                        "Subscription {} not found.",
+                        subscription
+                    ))
+                    .into());
+                }
+                json!({})
+            }
+            _ => {
+                return Err(WebError::new(
+                    StatusCode::NOT_FOUND,
+                    "methodNotFound",
+                    format!("No such method {:?}.", method),
+                ))
+            }
+        };
+        Ok(result)
+    }
+
+    /// Notifications for the events after `after`. `after` only advances past an event
+    /// once every subscription was checked against it. If reading or checking fails,
+    /// the error is logged and the rest is tried again after the next change.
+    async fn notifications(&self, after: &mut i64) -> Vec<Value> {
+        let mut notifications = Vec::new();
+        loop {
+            let events = match EventMac::since(&self.database, *after, BATCH_SIZE).await {
+                Ok(events) if events.is_empty() => return notifications,
+                Ok(events) => events,
+                Err(e) => {
+                    warn!("Failed to read task events: {}", e);
+                    return notifications;
+                }
+            };
+            for event in &events {
+                match self.event_notifications(event).await {
+                    Ok(matched) => notifications.extend(matched),
+                    Err(e) => {
+                        warn!("Failed to filter task event {}: {}", event.id(), e);
+                        return notifications;
+                    }
+                }
+                *after = event.id();
+            }
+        }
+    }
+
+    /// Notifications for one event, one for each subscription that it matches.
+    async fn event_notifications(&self, event: &TaskEvent) -> Result<Vec<Value>, crate::Error> {
+        let mut notifications = Vec::new();
+        for (subscription, filter) in &self.subscriptions {
+            if filter.matches(&self.database, event).await? {
+                notifications.push(json!({
+                    "method": "event",
+                    "params": {"subscription": subscription, "event": event},
+                }));
+            }
+        }
+        Ok(notifications)
+    }
+}
+
+/// Parse the parameters of a method. Missing parameters are an empty object.
+fn parse<T: DeserializeOwned>(params: Value) -> Result<T, WebError> {
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|e| {
+        WebError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalidParams",
+            e.to_string(),
+        )
+    })
+}
+
+/// Response to a failed request.
+fn reply_error(id: Value, error: &WebError) -> Value {
+    let mut reply = error_body(error);
+    reply["id"] = id;
+    reply
+}
+
+/// Serve one connection until the client goes away.
+async fn run_session(socket: WebSocket, mut session: Session) {
+    let (mut outgoing, mut incoming) = socket.split();
+    // Subscribe before reading the latest event, so that no change is missed.
+    let mut changes = EventMac::subscribe(&session.database);
+    let mut after = match EventMac::latest_id(&session.database).await {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Failed to start WebSocket session: {}", e);
+            return;
+        }
+    };
+    loop {
+        let replies = tokio::select! {
+            message = incoming.next() => match message {
+                Some(Ok(message)) if message.is_text() => {
+                    vec![session.handle(message.to_str().unwrap_or_default()).await]
+                }
+                Some(Ok(message)) if message.is_close() => break,
+                // Pings are answered by the library; binary messages are ignored.
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => {
+                    warn!("WebSocket error: {}", e);
+                    break;
+                }
+                None => break,
+            },
+            changed = changes.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                session.notifications(&mut after).await
+            }
+        };
+        for reply in replies {
+            if outgoing
+                .send(Message::text(reply.to_string()))
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{create_and_connect, DbAddress};
+    use std::time::Duration;
+
+    async fn recv(client: &mut warp::test::WsClient) -> Value {
+        let message = tokio::time::timeout(Duration::from_secs(5), client.recv())
+            .await
+            .expect("timed out waiting for a message")
+            .unwrap();
+        serde_json::from_str(message.to_str().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_requests() {
+        // # Setup
+        let database = Arc::new(create_and_connect(DbAddress::Memory).await.unwrap());
+        let filters = ws_filters("api", database.clone());
+        let mut client = warp::test::ws()
+            .path("/api/ws")
+            .handshake(filters)
+            .await
+            .unwrap();
+
+        // # Create
+        let request =
+            json!({"id": "a", "method": "tasks.create", "params": {"name": "Over the wire"}});
+        client.send_text(request.to_string()).await;
+        let reply = recv(&mut client).await;
+        assert_eq!(reply["id"], "a");
+        assert_eq!(reply["result"]["name"], "Over the wire");
+        let id = reply["result"]["id"].as_i64().unwrap();
+
+        // # List
+        client
+            .send_text(
+                json!({"id": 2, "method": "tasks.list", "params": {"name": "wire"}}).to_string(),
+            )
+            .await;
+        let reply = recv(&mut client).await;
+        assert_eq!(reply["id"], 2);
+        assert_eq!(reply["result"]["total"], 1);
+
+        // # Errors
+        client
+            .send_text(
+                json!({"id": 3, "method": "tasks.get", "params": {"id": id + 1}}).to_string(),
+            )
+            .await;
+        let reply = recv(&mut client).await;
+        assert_eq!(reply["id"], 3);
+        assert_eq!(reply["error"]["type"], "notFound");
+
+        client
+            .send_text(json!({"id": 4, "method": "tasks.fly"}).to_string())
+            .await;
+        assert_eq!(recv(&mut client).await["error"]["type"], "methodNotFound");
+
+        client.send_text("not json").await;
+        let reply = recv(&mut client).await;
+        assert_eq!(reply["id"], Value::Null);
+        assert_eq!(reply["error"]["type"], "invalidMessage");
+    }
+
+    #[tokio::test]
+    async fn test_subscriptions() {
+        // # Setup
+        let database = Arc::new(create_and_connect(DbAddress::Memory).await.unwrap());
+        let filters = ws_filters("api", database.clone());
+        let mut client = warp::test::ws()
+            .path("/api/ws")
+            .handshake(filters)
+            .await
+            .unwrap();
+        let subscribe = json!({"id": 1, "method": "subscribe", "params": {"status": "Closed"}});
+        client.send_text(subscribe.to_string()).await;
+        let subscription = recv(&mut client).await["result"]["subscription"].clone();
+
+        // # Action
+        for (name, status) in [("Still open", "Open"), ("Done already", "Closed")] {
+            let create = json!({"id": name, "method": "tasks.create", "params": {"name": name, "status": status}});
+            client.send_text(create.to_string()).await;
+        }
+
+        // # Check
+        // Responses and notifications interleave; only the closed task is notified.
+        let mut notified = Vec::new();
+        let mut responses = 0;
+        while responses < 2 || notified.is_empty() {
+            let message = recv(&mut client).await;
+            if message["method"] == "event" {
+                assert_eq!(message["params"]["subscription"], subscription);
+                notified.push(message["params"]["event"]["new_values"]["name"].clone());
+            } else {
+                responses += 1;
+            }
+        }
+        assert_eq!(notified, vec![json!("Done already")]);
+    }
+}
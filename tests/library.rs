@@ -0,0 +1,78 @@
+//! Use of the library from outside the crate, through its public API only.
+
+use serde_json::Value;
+use std::sync::Arc;
+use taskapp::{api_filters, create_and_connect, handle_rejection, DbAddress, ServerBuilder};
+use taskapp::{TaskMac, TaskPatch, TaskStatus};
+use warp::http::StatusCode;
+use warp::Filter;
+
+/// Test mounting the API next to the routes of another app.
+#[tokio::test]
+async fn test_compose_routes() -> Result<(), taskapp::Error> {
+    // # Setup
+    let database = Arc::new(create_and_connect(DbAddress::Memory).await?);
+    let patch = TaskPatch {
+        name: Some("Embed the API".to_string()),
+        ..Default::default()
+    };
+    let task = TaskMac::insert(&database, patch).await?;
+    let app = warp::path("health")
+        .map(|| "ok")
+        .or(api_filters("api", database.clone()))
+        .recover(handle_rejection);
+
+    // # Own route
+    let response = warp::test::request().path("/health").reply(&app).await;
+    assert_eq!(response.body(), "ok");
+
+    // # API route
+    let response = warp::test::request()
+        .method("PATCH")
+        .path(&format!("/api/tasks/{}", task.id))
+        .json(&TaskPatch {
+            status: Some(TaskStatus::Closed),
+            ..Default::default()
+        })
+        .reply(&app)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        TaskMac::get(&database, task.id).await?.status,
+        TaskStatus::Closed
+    );
+
+    // # Unknown route
+    let response = warp::test::request().path("/api/nothing").reply(&app).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body: Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["error"]["type"], "routeNotFound");
+    Ok(())
+}
+
+/// Test the routes of a server built under another base path.
+#[tokio::test]
+async fn test_server_routes() -> Result<(), taskapp::Error> {
+    // # Setup
+    let database = Arc::new(create_and_connect(DbAddress::Memory).await?);
+    let routes = ServerBuilder::new(database).base_path("v1").routes();
+
+    // # Action
+    let created = warp::test::request()
+        .method("POST")
+        .path("/v1/tasks")
+        .json(&serde_json::json!({ "name": "Versioned" }))
+        .reply(&routes)
+        .await;
+    let old_path = warp::test::request()
+        .path("/api/tasks")
+        .reply(&routes)
+        .await;
+
+    // # Check
+    assert_eq!(created.status(), StatusCode::OK);
+    let body: Value = serde_json::from_slice(created.body()).unwrap();
+    assert_eq!(body["data"]["name"], "Versioned");
+    assert_eq!(old_path.status(), StatusCode::NOT_FOUND);
+    Ok(())
+}